@@ -1 +1,8 @@
+pub mod qc_circuit;
 pub mod qc_keyagg;
+pub mod qc_multi_circuit;
+pub mod qc_schnorr;
+pub mod qc_snark;
+pub mod qc_solidity;
+pub mod qc_witness;
+pub mod stake_table;
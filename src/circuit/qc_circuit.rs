@@ -0,0 +1,411 @@
+//! End-to-end circuit for the verification of a quorum certificate over a committed stake table.
+//!
+//! The public inputs of the circuit are laid out as
+//! * `digest` - the stake table digest, see [`compute_stake_table_hash`],
+//! * `agg_key` - the native limbs of the aggregated key of the signers, see
+//!   `SerializableEmulatedStruct::serialize_to_native_elements`,
+//! * `threshold` - the quorum threshold.
+//!
+//! The stake keys, stake amounts and the signer bit vector are witnesses.
+//!
+//! The circuit depends on the size of the stake table only. To keep the same circuit, and the
+//! same keys, across stake tables of different sizes, use [`QcCircuit::new_padded`] to pad them to
+//! a fixed capacity.
+
+use crate::circuit::qc_keyagg::{
+    compute_stake_table_hash, pad_stake_table, QCKeyAggregateGadget, VerKeyVar,
+};
+use ark_std::{format, vec, vec::Vec};
+use jf_primitives::rescue::RescueParameter;
+use jf_relation::{
+    errors::CircuitError,
+    gadgets::{
+        ecc::{
+            emulated::{EmulatedSWPointVariable, EmulatedTEPointVariable, SWPoint},
+            TEPoint,
+        },
+        EmulationConfig, SerializableEmulatedStruct,
+    },
+    BoolVar, Circuit, PlonkCircuit, Variable,
+};
+
+/// Bit length of the lookup range of the QC circuits.
+pub const QC_CIRCUIT_RANGE_BIT_LEN: usize = 16;
+
+/// Stake keys that can be allocated in the QC circuit.
+pub trait QcCircuitKey<F, E>: SerializableEmulatedStruct<F> + Clone
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+{
+    /// The variable of the key.
+    type Var: VerKeyVar<F, E>;
+
+    /// Allocates the key as a witness.
+    fn create_variable(&self, circuit: &mut PlonkCircuit<F>) -> Result<Self::Var, CircuitError>;
+
+    /// Allocates the key as a public input.
+    fn create_public_variable(
+        &self,
+        circuit: &mut PlonkCircuit<F>,
+    ) -> Result<Self::Var, CircuitError>;
+}
+
+impl<F, E> QcCircuitKey<F, E> for SWPoint<E>
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+{
+    type Var = EmulatedSWPointVariable<E>;
+
+    fn create_variable(&self, circuit: &mut PlonkCircuit<F>) -> Result<Self::Var, CircuitError> {
+        circuit.create_emulated_sw_point_variable(*self)
+    }
+
+    fn create_public_variable(
+        &self,
+        circuit: &mut PlonkCircuit<F>,
+    ) -> Result<Self::Var, CircuitError> {
+        circuit.create_public_emulated_sw_point_variable(*self)
+    }
+}
+
+impl<F, E> QcCircuitKey<F, E> for TEPoint<E>
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+{
+    type Var = EmulatedTEPointVariable<E>;
+
+    fn create_variable(&self, circuit: &mut PlonkCircuit<F>) -> Result<Self::Var, CircuitError> {
+        circuit.create_emulated_te_point_variable(*self)
+    }
+
+    fn create_public_variable(
+        &self,
+        circuit: &mut PlonkCircuit<F>,
+    ) -> Result<Self::Var, CircuitError> {
+        circuit.create_public_emulated_te_point_variable(*self)
+    }
+}
+
+/// Computes the public inputs of the QC circuit, in the order given in the module documentation.
+/// * `stake_keys` - list of stake public keys.
+/// * `stake_amts` - list of stake amounts for the corresponding stake keys.
+/// * `agg_key` - the aggregated key of the signers.
+/// * `threshold` - the quorum threshold.
+pub fn compute_qc_public_inputs<F, K>(
+    stake_keys: &[K],
+    stake_amts: &[F],
+    agg_key: &K,
+    threshold: F,
+) -> Vec<F>
+where
+    F: RescueParameter,
+    K: SerializableEmulatedStruct<F>,
+{
+    let mut public_inputs = vec![compute_stake_table_hash(stake_amts, stake_keys)];
+    public_inputs.extend(agg_key.serialize_to_native_elements());
+    public_inputs.push(threshold);
+    public_inputs
+}
+
+/// The variables allocated by [`QcCircuit::synthesize`].
+#[derive(Clone, Debug)]
+pub struct QcCircuitVars<V> {
+    /// Public stake table digest
+    pub digest: Variable,
+    /// Public aggregated key
+    pub agg_key: V,
+    /// Public quorum threshold
+    pub threshold: Variable,
+    /// Stake keys
+    pub stake_keys: Vec<V>,
+    /// Stake amounts
+    pub stake_amts: Vec<Variable>,
+    /// Signer bit vector
+    pub signers: Vec<BoolVar>,
+}
+
+/// Builder of the QC verification circuit.
+#[derive(Clone, Debug)]
+pub struct QcCircuit<F, E, K> {
+    /// Stake keys of the stake table
+    pub stake_keys: Vec<K>,
+    /// Stake amounts of the stake table
+    pub stake_amts: Vec<F>,
+    /// Signer bit vector
+    pub signers: Vec<bool>,
+    /// Aggregated key of the signers
+    pub agg_key: K,
+    /// Quorum threshold
+    pub threshold: F,
+    /// The internal curve parameter
+    pub coef: E,
+}
+
+impl<F, E, K> QcCircuit<F, E, K>
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+    K: QcCircuitKey<F, E>,
+{
+    /// Creates the builder.
+    /// * `stake_keys` - list of stake public keys.
+    /// * `stake_amts` - list of stake amounts for the corresponding stake keys.
+    /// * `signers` - the indicator vector for the quorum set.
+    /// * `agg_key` - the aggregated key of the signers.
+    /// * `threshold` - the quorum threshold.
+    /// * `coef` - the internal curve parameter.
+    pub fn new(
+        stake_keys: Vec<K>,
+        stake_amts: Vec<F>,
+        signers: Vec<bool>,
+        agg_key: K,
+        threshold: F,
+        coef: E,
+    ) -> Self {
+        Self {
+            stake_keys,
+            stake_amts,
+            signers,
+            agg_key,
+            threshold,
+            coef,
+        }
+    }
+
+    /// Creates the builder of a stake table padded to a fixed capacity, see `pad_stake_table`.
+    /// The padded slots hold the default key and zero stake, and are not selected.
+    /// * `capacity` - the size of the padded stake table.
+    /// * other parameters - see [`QcCircuit::new`].
+    pub fn new_padded(
+        stake_keys: Vec<K>,
+        stake_amts: Vec<F>,
+        mut signers: Vec<bool>,
+        agg_key: K,
+        threshold: F,
+        coef: E,
+        capacity: usize,
+    ) -> Result<Self, CircuitError>
+    where
+        K: Default,
+    {
+        if signers.len() != stake_keys.len() {
+            return Err(CircuitError::ParameterError(format!(
+                "bit vector len {} != the number of stake keys {}",
+                signers.len(),
+                stake_keys.len(),
+            )));
+        }
+        let (stake_amts, stake_keys) = pad_stake_table(&stake_amts, &stake_keys, capacity)?;
+        signers.resize(capacity, false);
+        Ok(Self::new(
+            stake_keys, stake_amts, signers, agg_key, threshold, coef,
+        ))
+    }
+
+    /// The public inputs of the circuit, see [`compute_qc_public_inputs`].
+    pub fn public_inputs(&self) -> Vec<F> {
+        compute_qc_public_inputs(
+            &self.stake_keys,
+            &self.stake_amts,
+            &self.agg_key,
+            self.threshold,
+        )
+    }
+
+    /// Allocates the public inputs and witnesses in `circuit` and adds the QC checking gadgets.
+    /// The public inputs are allocated first, so `circuit` should not have any public input yet.
+    pub fn synthesize(
+        &self,
+        circuit: &mut PlonkCircuit<F>,
+    ) -> Result<QcCircuitVars<K::Var>, CircuitError> {
+        // public input
+        let digest = circuit
+            .create_public_variable(compute_stake_table_hash(&self.stake_amts, &self.stake_keys))?;
+        let agg_key = self.agg_key.create_public_variable(circuit)?;
+        let threshold = circuit.create_public_variable(self.threshold)?;
+
+        // add witness
+        let stake_keys = self
+            .stake_keys
+            .iter()
+            .map(|key| key.create_variable(circuit))
+            .collect::<Result<Vec<_>, _>>()?;
+        let stake_amts = self
+            .stake_amts
+            .iter()
+            .map(|&amt| circuit.create_variable(amt))
+            .collect::<Result<Vec<_>, _>>()?;
+        let signers = self
+            .signers
+            .iter()
+            .map(|&b| circuit.create_boolean_variable(b))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // add circuit gadgets
+        circuit.check_stake_table_digest(&stake_keys, &stake_amts, digest)?;
+        circuit.check_aggregate_vk(&stake_keys, &signers, &agg_key, self.coef)?;
+        circuit.check_threshold(&stake_amts, &signers, threshold)?;
+        circuit.check_stake_table_padding(&stake_keys, &stake_amts, &signers)?;
+
+        Ok(QcCircuitVars {
+            digest,
+            agg_key,
+            threshold,
+            stake_keys,
+            stake_amts,
+            signers,
+        })
+    }
+
+    /// Builds a new UltraPlonk circuit checking the QC.
+    /// The circuit is not finalized.
+    pub fn build(&self) -> Result<PlonkCircuit<F>, CircuitError> {
+        let mut circuit = PlonkCircuit::new_ultra_plonk(QC_CIRCUIT_RANGE_BIT_LEN);
+        self.synthesize(&mut circuit)?;
+        Ok(circuit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::qc_keyagg::compute_padded_stake_table_hash;
+    use ark_bn254::{g1::Config as Param254, Fq as Fq254, Fr as Fr254};
+    use ark_ec::{short_weierstrass::Projective, CurveGroup};
+    use ark_std::{UniformRand, Zero};
+
+    type Circuit254 = QcCircuit<Fr254, Fq254, SWPoint<Fq254>>;
+
+    fn test_qc(signers: Vec<bool>, threshold: u8) -> Circuit254 {
+        let mut rng = jf_utils::test_rng();
+        let points: Vec<Projective<Param254>> = (0..signers.len())
+            .map(|_| Projective::<Param254>::rand(&mut rng))
+            .collect();
+        let agg_key = points
+            .iter()
+            .zip(signers.iter())
+            .filter(|(_, b)| **b)
+            .fold(Projective::<Param254>::zero(), |acc, (p, _)| acc + p);
+        QcCircuit::new(
+            points.iter().map(|p| p.into_affine().into()).collect(),
+            (1..=signers.len() as u8).map(Fr254::from).collect(),
+            signers,
+            agg_key.into_affine().into(),
+            Fr254::from(threshold),
+            Fq254::zero(),
+        )
+    }
+
+    #[test]
+    fn test_qc_circuit() -> Result<(), CircuitError> {
+        let qc = test_qc(vec![false, true, false, true, false], 6);
+        let public_inputs = qc.public_inputs();
+        let agg_key_limbs = qc.agg_key.serialize_to_native_elements();
+        assert_eq!(public_inputs.len(), agg_key_limbs.len() + 2);
+        assert_eq!(
+            public_inputs[0],
+            compute_stake_table_hash(&qc.stake_amts, &qc.stake_keys)
+        );
+        assert_eq!(public_inputs[1..=agg_key_limbs.len()], agg_key_limbs[..]);
+        assert_eq!(*public_inputs.last().unwrap(), Fr254::from(6u8));
+
+        let circuit = qc.build()?;
+        assert_eq!(circuit.num_inputs(), public_inputs.len());
+        assert!(circuit.check_circuit_satisfiability(&public_inputs).is_ok());
+
+        // bad path: wrong public inputs
+        for i in [0, 1, public_inputs.len() - 1] {
+            let mut bad_inputs = public_inputs.clone();
+            bad_inputs[i] += Fr254::from(1u8);
+            assert!(circuit.check_circuit_satisfiability(&bad_inputs).is_err());
+        }
+        // bad path: the signers do not reach the threshold
+        let qc = test_qc(vec![true, true, false, false, false], 6);
+        let circuit = qc.build()?;
+        assert!(circuit
+            .check_circuit_satisfiability(&qc.public_inputs())
+            .is_err());
+        // bad path: the aggregated key is not the one of the signers
+        let mut qc = test_qc(vec![false, true, false, true, false], 6);
+        qc.signers = vec![false, false, true, true, false];
+        let circuit = qc.build()?;
+        assert!(circuit
+            .check_circuit_satisfiability(&qc.public_inputs())
+            .is_err());
+
+        // check input parameter errors
+        let mut qc = test_qc(vec![false, true, false, true, false], 6);
+        qc.signers.pop();
+        assert!(qc.build().is_err());
+        let mut qc = test_qc(vec![false, true, false, true, false], 6);
+        qc.stake_amts.pop();
+        assert!(qc.build().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_padded_qc_circuit() -> Result<(), CircuitError> {
+        let capacity = 8;
+        let pad = |qc: Circuit254| {
+            QcCircuit::new_padded(
+                qc.stake_keys,
+                qc.stake_amts,
+                qc.signers,
+                qc.agg_key,
+                qc.threshold,
+                qc.coef,
+                capacity,
+            )
+        };
+        let qc = test_qc(vec![false, true, false, true, false], 6);
+        let padded_qc = pad(qc.clone())?;
+        assert_eq!(padded_qc.stake_keys.len(), capacity);
+        assert_eq!(padded_qc.stake_keys[5..], [SWPoint::default(); 3]);
+        assert_eq!(padded_qc.stake_amts[5..], [Fr254::zero(); 3]);
+        assert_eq!(padded_qc.signers[5..], [false; 3]);
+        assert_eq!(
+            padded_qc.public_inputs()[0],
+            compute_padded_stake_table_hash(&qc.stake_amts, &qc.stake_keys, capacity)?
+        );
+        let circuit = padded_qc.build()?;
+        assert!(circuit
+            .check_circuit_satisfiability(&padded_qc.public_inputs())
+            .is_ok());
+
+        // a smaller stake table gives the same circuit
+        let small_qc = pad(test_qc(vec![true, true, true], 6))?;
+        let small_circuit = small_qc.build()?;
+        assert!(small_circuit
+            .check_circuit_satisfiability(&small_qc.public_inputs())
+            .is_ok());
+        assert_eq!(small_circuit.num_gates(), circuit.num_gates());
+        assert_eq!(small_circuit.num_inputs(), circuit.num_inputs());
+
+        // bad path: a selected padded slot
+        let mut bad_qc = padded_qc.clone();
+        bad_qc.signers[6] = true;
+        assert!(bad_qc
+            .build()?
+            .check_circuit_satisfiability(&bad_qc.public_inputs())
+            .is_err());
+        // bad path: a padded slot with stake, even if committed in the digest
+        let mut bad_qc = padded_qc;
+        bad_qc.stake_amts[7] = Fr254::from(1u8);
+        assert!(bad_qc
+            .build()?
+            .check_circuit_satisfiability(&bad_qc.public_inputs())
+            .is_err());
+
+        // check input parameter errors
+        assert!(pad(test_qc(vec![true; 9], 6)).is_err());
+        let mut qc = qc;
+        qc.signers.pop();
+        assert!(pad(qc).is_err());
+
+        Ok(())
+    }
+}
@@ -1,44 +1,195 @@
 //! Circuit implementation of stake key aggregation for quorum certificates verification.
 
-use ark_ff::PrimeField;
-use ark_std::{format, vec, vec::Vec};
-use jf_primitives::{
-    circuit::rescue::RescueNativeGadget,
-    rescue::{sponge::RescueCRHF, RescueParameter},
-};
+pub use crate::qc::digest::{compute_stake_table_hash, STAKE_AMOUNT_BIT_LEN};
+
+use crate::qc::keys::ValidatorKeys;
+use ark_bn254::Fq as Fq254;
+use ark_ec::twisted_edwards::TECurveConfig;
+use ark_ff::{BigInteger, PrimeField};
+use ark_std::{format, marker::PhantomData, vec, vec::Vec};
+use bitvec::prelude::*;
+use jf_primitives::{circuit::rescue::RescueNativeGadget, rescue::RescueParameter};
 use jf_relation::{
     errors::CircuitError,
     gadgets::{
         ecc::{
             emulated::{EmulatedSWPointVariable, EmulatedTEPointVariable, SWPoint},
-            TEPoint,
+            PointVariable, TEPoint,
         },
-        EmulationConfig, SerializableEmulatedStruct,
+        from_emulated_field, EmulationConfig, SerializableEmulatedStruct,
     },
     BoolVar, Circuit, PlonkCircuit, Variable,
 };
 
-/// Digest a list of verification keys and their associated stake amounts
-/// * `stack_amts` - stake amounts
+/// Pads a stake table to a fixed capacity with dummy entries, made of the default key (the
+/// neutral point) and zero stake, see `QCKeyAggregateGadget::check_stake_table_padding`.
+/// * `stake_amts` - stake amounts
 /// * `keys` - list of verification keys
-pub fn compute_stake_table_hash<F: RescueParameter, T: SerializableEmulatedStruct<F>>(
+/// * `capacity` - the size of the padded stake table
+pub fn pad_stake_table<F: PrimeField, T: Default + Clone>(
     stake_amts: &[F],
     keys: &[T],
-) -> F {
-    let mut input_vec = vec![];
-    for (&amt, key) in stake_amts.iter().zip(keys.iter()) {
-        input_vec.extend(key.serialize_to_native_elements());
-        input_vec.push(amt);
+    capacity: usize,
+) -> Result<(Vec<F>, Vec<T>), CircuitError> {
+    if stake_amts.len() != keys.len() || keys.len() > capacity {
+        return Err(CircuitError::ParameterError(format!(
+            "cannot pad {} stake amounts and {} keys to capacity {}",
+            stake_amts.len(),
+            keys.len(),
+            capacity,
+        )));
+    }
+    let mut stake_amts = stake_amts.to_vec();
+    stake_amts.resize(capacity, F::zero());
+    let mut keys = keys.to_vec();
+    keys.resize(capacity, T::default());
+    Ok((stake_amts, keys))
+}
+
+/// Digest a stake table padded to a fixed capacity, see `pad_stake_table`.
+/// * `stake_amts` - stake amounts
+/// * `keys` - list of verification keys
+/// * `capacity` - the size of the padded stake table
+pub fn compute_padded_stake_table_hash<F, T>(
+    stake_amts: &[F],
+    keys: &[T],
+    capacity: usize,
+) -> Result<F, CircuitError>
+where
+    F: RescueParameter,
+    T: SerializableEmulatedStruct<F> + Default + Clone,
+{
+    let (stake_amts, keys) = pad_stake_table(stake_amts, keys, capacity)?;
+    Ok(compute_stake_table_hash(&stake_amts, &keys))
+}
+
+/// The keys of a validator are hashed with a fixed layout: the limbs of the `x.c0`, `x.c1`,
+/// `y.c0` and `y.c1` coordinates of the BLS key (a G2 point over the BN254 base field) and its
+/// infinity flag, then the native coordinates of the Schnorr key.
+impl<F, P> SerializableEmulatedStruct<F> for ValidatorKeys<P>
+where
+    F: PrimeField,
+    Fq254: EmulationConfig<F>,
+    P: TECurveConfig<BaseField = F>,
+{
+    fn serialize_to_native_elements(&self) -> Vec<F> {
+        let bls_key = self.bls_key.to_affine();
+        let mut result = from_emulated_field(bls_key.x.c0);
+        result.extend(from_emulated_field(bls_key.x.c1));
+        result.extend(from_emulated_field(bls_key.y.c0));
+        result.extend(from_emulated_field(bls_key.y.c1));
+        result.push(if bls_key.infinity {
+            F::one()
+        } else {
+            F::zero()
+        });
+        let schnorr_key = self.schnorr_key.to_affine();
+        result.push(schnorr_key.x);
+        result.push(schnorr_key.y);
+        result
+    }
+}
+
+/// Number of bits packed into a field element of a packed bit vector.
+pub fn bit_vector_chunk_size<F: PrimeField>() -> usize {
+    F::MODULUS_BIT_SIZE as usize - 1
+}
+
+/// Packs a bit vector into field elements, to be unpacked by
+/// `QCKeyAggregateGadget::unpack_bit_vector`.
+/// Each element holds `bit_vector_chunk_size::<F>()` bits in little-endian order, the last one
+/// holds the remaining bits.
+/// * `bit_vec` - the bit vector
+pub fn pack_bit_vector<F: PrimeField>(bit_vec: &BitSlice) -> Vec<F> {
+    bit_vec
+        .chunks(bit_vector_chunk_size::<F>())
+        .map(|chunk| {
+            let bits: Vec<bool> = chunk.iter().by_vals().collect();
+            F::from_bigint(F::BigInt::from_bits_le(&bits)).unwrap()
+        })
+        .collect()
+}
+
+/// Variables of the stake keys hashed into the stake table digest.
+pub trait StakeKeyVar: Sized + Clone {
+    /// Returns a list of variables associated with this key variable, with the layout of the
+    /// native elements hashed by [`compute_stake_table_hash`].
+    fn native_vars(&self) -> Vec<Variable>;
+}
+
+/// Variables of [`ValidatorKeys`], whose stake table entries hold both a BLS and a Schnorr key.
+/// The BLS key is not used in the circuits but is bound by the stake table digest: its native
+/// elements are allocated as is, with the layout of `serialize_to_native_elements`.
+#[derive(Clone, Debug)]
+pub struct ValidatorKeysVar {
+    bls_key: Vec<Variable>,
+    schnorr_key: PointVariable,
+}
+
+impl ValidatorKeysVar {
+    /// Allocates the keys of a validator as witnesses.
+    /// * `circuit` - associated Plonk circuit.
+    /// * `keys` - the keys of the validator.
+    pub fn new<F, P>(
+        circuit: &mut PlonkCircuit<F>,
+        keys: &ValidatorKeys<P>,
+    ) -> Result<Self, CircuitError>
+    where
+        F: PrimeField,
+        Fq254: EmulationConfig<F>,
+        P: TECurveConfig<BaseField = F>,
+    {
+        let mut elements = keys.serialize_to_native_elements();
+        // the last two elements are the coordinates of the Schnorr key, allocated as a point
+        elements.truncate(elements.len() - 2);
+        let bls_key = elements
+            .into_iter()
+            .map(|elem| circuit.create_variable(elem))
+            .collect::<Result<Vec<_>, _>>()?;
+        let schnorr_key =
+            circuit.create_point_variable(TEPoint::from(keys.schnorr_key.to_affine()))?;
+        Ok(Self {
+            bls_key,
+            schnorr_key,
+        })
+    }
+
+    /// The Schnorr key, e.g. to check the light client state signatures.
+    pub fn schnorr_key(&self) -> &PointVariable {
+        &self.schnorr_key
+    }
+}
+
+impl StakeKeyVar for ValidatorKeysVar {
+    fn native_vars(&self) -> Vec<Variable> {
+        let mut ret = self.bls_key.clone();
+        ret.push(self.schnorr_key.get_x());
+        ret.push(self.schnorr_key.get_y());
+        ret
     }
-    RescueCRHF::sponge_with_bit_padding(&input_vec[..], 1)[0]
 }
 
 /// Traits for verification keys
-pub trait VerKeyVar<E>: Sized + Clone {
+/// * `F` - the circuit field
+/// * `E` - the internal curve parameter: the emulated field for emulated keys, or the marker
+///   `PhantomData<P>` of the curve config `P` for keys over a curve embedded in `F`
+///
+/// NOTE: the circuit field is a parameter of the trait, instead of its methods as in the former
+/// `VerKeyVar<E>`, and `E` is no longer bound to `EmulationConfig<F>`. Keys over an embedded
+/// curve, see the implementation for `PointVariable`, are native variables of a single field, so
+/// they cannot take any `F` with an emulated field `E`. Implementations of `VerKeyVar<E>` become
+/// implementations of `VerKeyVar<F, E>` for every `F` such that `E: EmulationConfig<F>`, and
+/// `native_vars` moves to [`StakeKeyVar`], which the stake table digest is checked against.
+pub trait VerKeyVar<F: PrimeField, E>: StakeKeyVar {
     type KeyType: Default;
 
-    /// Returns a list of variables associated with this key variable.
-    fn native_vars(&self) -> Vec<Variable>;
+    /// Create a constant key variable.
+    /// * `circuit` - associated Plonk circuit.
+    /// * `key` - the constant key.
+    fn create_constant_variable(
+        circuit: &mut PlonkCircuit<F>,
+        key: Self::KeyType,
+    ) -> Result<Self, CircuitError>;
 
     /// Aggregate the verification keys with Boolean selectors.
     /// * `circuit` - associated Plonk circuit.
@@ -46,42 +197,33 @@ pub trait VerKeyVar<E>: Sized + Clone {
     /// * `selectors` - list of Boolean selectors.
     /// * `coef` - the internal curve parameter.
     /// * Returns an aggregated key variable.
-    fn aggregate_with_selectors<F>(
+    fn aggregate_with_selectors(
         circuit: &mut PlonkCircuit<F>,
         keys: &[Self],
         selectors: &[BoolVar],
         coef: E,
-    ) -> Result<Self, CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>;
+    ) -> Result<Self, CircuitError>;
 
     /// Check whether two input verification key variables are equal.
     /// * `circuit` - associated Plonk circuit.
     /// * `p0` - first verification key variable.
     /// * `p1` - second verification key variable.
     /// * Returns a Boolean variable indicates whether `p0 == p1`.
-    fn is_equal<F>(
+    fn is_equal(
         circuit: &mut PlonkCircuit<F>,
         p0: &Self,
         p1: &Self,
-    ) -> Result<BoolVar, CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>;
+    ) -> Result<BoolVar, CircuitError>;
 
     /// Enforce that two input verification key variables are equal.
     /// * `circuit` - associated Plonk circuit.
     /// * `p0` - first verification key variable.
     /// * `p1` - second verification key variable.
-    fn enforce_equal<F>(
+    fn enforce_equal(
         circuit: &mut PlonkCircuit<F>,
         p0: &Self,
         p1: &Self,
-    ) -> Result<(), CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>;
+    ) -> Result<(), CircuitError>;
 }
 
 /// Plonk circuit gadget for stake key aggregation for quorum certificates.
@@ -94,7 +236,7 @@ where
     /// * `bit_vec` - the indicator vector for the quorum set, `bit_vec[i] = 1` if `i` is in the quorum set, o/w `bit_vec[i] = 0`.
     /// * `agg_vk` - the public aggregated stake key.
     /// * `coef` - the internal curve parameter
-    fn check_aggregate_vk<E: EmulationConfig<F>, V: VerKeyVar<E>>(
+    fn check_aggregate_vk<E, V: VerKeyVar<F, E>>(
         &mut self,
         vks: &[V],
         bit_vec: &[BoolVar],
@@ -105,15 +247,43 @@ where
     /// Stake table commitment checking circuit
     /// * `vk` - list of stake public keys.
     /// * `stake_amts` - list of stake amounts for the corresponding stake keys.
-    /// * `digest` - the hash of the stake table.
-    fn check_stake_table_digest<E: EmulationConfig<F>, V: VerKeyVar<E>>(
+    /// * `digest` - the hash of the stake table, see [`compute_stake_table_hash`].
+    ///
+    /// NOTE: the keys are any [`StakeKeyVar`], e.g. [`ValidatorKeysVar`], and no longer need an
+    /// internal curve parameter. Callers passing `VerKeyVar`s are unchanged, except for naming
+    /// the type parameters explicitly.
+    fn check_stake_table_digest<V: StakeKeyVar>(
         &mut self,
         vks: &[V],
         stake_amts: &[Variable],
         digest: Variable,
     ) -> Result<(), CircuitError>;
 
+    /// Padding checking circuit for stake tables padded to a fixed capacity, see
+    /// `pad_stake_table`: the slots holding the dummy key have zero stake and are not selected,
+    /// so that they add nothing to the aggregated key nor to the stake of the quorum.
+    /// * `vks` - list of stake public keys.
+    /// * `stake_amts` - list of stake amounts for the corresponding stake keys.
+    /// * `bit_vec` - the indicator vector for the quorum set.
+    fn check_stake_table_padding<E, V: VerKeyVar<F, E>>(
+        &mut self,
+        vks: &[V],
+        stake_amts: &[Variable],
+        bit_vec: &[BoolVar],
+    ) -> Result<(), CircuitError>;
+
+    /// Unpacks a bit vector packed by `pack_bit_vector`, e.g. from public inputs.
+    /// * `packed` - the packed bit vector.
+    /// * `len` - the length of the bit vector.
+    /// * Returns the Boolean variables of the bit vector.
+    fn unpack_bit_vector(
+        &mut self,
+        packed: &[Variable],
+        len: usize,
+    ) -> Result<Vec<BoolVar>, CircuitError>;
+
     /// Quorum threshold checking circuit
+    /// Each stake amount is constrained to `STAKE_AMOUNT_BIT_LEN` bits, so that their sum does not wrap around.
     /// * `stake_amts` - list of stake amounts for the corresponding stake keys.
     /// * `bit_vec` - the indicator vector for the quorum set.
     /// * `threshold` - the public quorum threshold.
@@ -129,7 +299,7 @@ impl<F> QCKeyAggregateGadget<F> for PlonkCircuit<F>
 where
     F: RescueParameter,
 {
-    fn check_aggregate_vk<E: EmulationConfig<F>, V: VerKeyVar<E>>(
+    fn check_aggregate_vk<E, V: VerKeyVar<F, E>>(
         &mut self,
         vks: &[V],
         bit_vec: &[BoolVar],
@@ -143,11 +313,11 @@ where
                 vks.len(),
             )));
         }
-        let agg_key_var = V::aggregate_with_selectors::<F>(self, vks, bit_vec, coef)?;
+        let agg_key_var = V::aggregate_with_selectors(self, vks, bit_vec, coef)?;
         V::enforce_equal(self, &agg_key_var, agg_vk)
     }
 
-    fn check_stake_table_digest<E: EmulationConfig<F>, V: VerKeyVar<E>>(
+    fn check_stake_table_digest<V: StakeKeyVar>(
         &mut self,
         vks: &[V],
         stake_amts: &[Variable],
@@ -160,9 +330,16 @@ where
                 vks.len(),
             )));
         }
+        let keys: Vec<Vec<Variable>> = vks.iter().map(|vk| vk.native_vars()).collect();
+        // a fixed layout per entry, as the hash input does not delimit the keys
+        if keys.windows(2).any(|pair| pair[0].len() != pair[1].len()) {
+            return Err(CircuitError::ParameterError(
+                "the stake keys do not have the same number of variables".into(),
+            ));
+        }
         let mut hash_input = vec![];
-        for (vk, &stake_amt) in vks.iter().zip(stake_amts.iter()) {
-            hash_input.append(&mut vk.native_vars());
+        for (key, &stake_amt) in keys.iter().zip(stake_amts.iter()) {
+            hash_input.extend_from_slice(key);
             hash_input.push(stake_amt);
         }
         let expected_digest =
@@ -170,6 +347,63 @@ where
         self.enforce_equal(expected_digest, digest)
     }
 
+    fn check_stake_table_padding<E, V: VerKeyVar<F, E>>(
+        &mut self,
+        vks: &[V],
+        stake_amts: &[Variable],
+        bit_vec: &[BoolVar],
+    ) -> Result<(), CircuitError> {
+        if vks.len() != stake_amts.len() || vks.len() != bit_vec.len() {
+            return Err(CircuitError::ParameterError(format!(
+                "the number of stake amounts {} or bit vector len {} != the number of stake keys {}",
+                stake_amts.len(),
+                bit_vec.len(),
+                vks.len(),
+            )));
+        }
+        let dummy_key = V::create_constant_variable(self, V::KeyType::default())?;
+        for ((vk, &stake_amt), &bit) in vks.iter().zip(stake_amts.iter()).zip(bit_vec.iter()) {
+            let is_dummy = V::is_equal(self, vk, &dummy_key)?;
+            let dummy_amt = self.mul(is_dummy.into(), stake_amt)?;
+            self.enforce_constant(dummy_amt, F::zero())?;
+            let dummy_bit = self.logic_and(is_dummy, bit)?;
+            self.enforce_false(dummy_bit.into())?;
+        }
+        Ok(())
+    }
+
+    fn unpack_bit_vector(
+        &mut self,
+        packed: &[Variable],
+        len: usize,
+    ) -> Result<Vec<BoolVar>, CircuitError> {
+        let chunk_size = bit_vector_chunk_size::<F>();
+        if packed.len() != (len + chunk_size - 1) / chunk_size {
+            return Err(CircuitError::ParameterError(format!(
+                "{} packed elements for a bit vector of len {}",
+                packed.len(),
+                len,
+            )));
+        }
+        let mut bit_vec = vec![];
+        for (i, &var) in packed.iter().enumerate() {
+            let num_bits = chunk_size.min(len - i * chunk_size);
+            let value = self.witness(var)?.into_bigint();
+            let bits = (0..num_bits)
+                .map(|j| self.create_boolean_variable(value.get_bit(j)))
+                .collect::<Result<Vec<_>, _>>()?;
+            // the bits are constrained to be Boolean and to recompose the packed element
+            let coeffs: Vec<F> = (0..num_bits)
+                .map(|j| F::from(2u8).pow([j as u64]))
+                .collect();
+            let bit_vars: Vec<Variable> = bits.iter().map(|&b| b.into()).collect();
+            let sum = self.lin_comb(&coeffs, &F::zero(), &bit_vars)?;
+            self.enforce_equal(sum, var)?;
+            bit_vec.extend(bits);
+        }
+        Ok(bit_vec)
+    }
+
     fn check_threshold(
         &mut self,
         stake_amts: &[Variable],
@@ -183,8 +417,18 @@ where
                 stake_amts.len(),
             )));
         }
+        // the sum is less than `2^(STAKE_AMOUNT_BIT_LEN + bit_len(stake_amts.len()))`
+        let num_bits = (usize::BITS - stake_amts.len().leading_zeros()) as usize;
+        if STAKE_AMOUNT_BIT_LEN + num_bits >= F::MODULUS_BIT_SIZE as usize {
+            return Err(CircuitError::ParameterError(format!(
+                "the sum of {} stake amounts of {} bits may overflow the field",
+                stake_amts.len(),
+                STAKE_AMOUNT_BIT_LEN,
+            )));
+        }
         let mut active_amts = vec![];
         for (&stake_amt, &bit) in stake_amts.iter().zip(bit_vec.iter()) {
+            self.enforce_in_range(stake_amt, STAKE_AMOUNT_BIT_LEN)?;
             active_amts.push(self.mul(stake_amt, bit.into())?);
         }
         let sum = self.sum(&active_amts[..])?;
@@ -192,29 +436,35 @@ where
     }
 }
 
-impl<E> VerKeyVar<E> for EmulatedSWPointVariable<E>
-where
-    E: PrimeField,
-{
-    type KeyType = SWPoint<E>;
-
+impl<E: PrimeField> StakeKeyVar for EmulatedSWPointVariable<E> {
     fn native_vars(&self) -> Vec<Variable> {
         let mut ret = self.0.native_vars();
         ret.append(&mut self.1.native_vars());
         ret.push(self.2 .0);
         ret
     }
+}
 
-    fn aggregate_with_selectors<F>(
+impl<F, E> VerKeyVar<F, E> for EmulatedSWPointVariable<E>
+where
+    F: PrimeField,
+    E: EmulationConfig<F>,
+{
+    type KeyType = SWPoint<E>;
+
+    fn create_constant_variable(
+        circuit: &mut PlonkCircuit<F>,
+        key: Self::KeyType,
+    ) -> Result<Self, CircuitError> {
+        circuit.create_constant_emulated_sw_point_variable(key)
+    }
+
+    fn aggregate_with_selectors(
         circuit: &mut PlonkCircuit<F>,
         keys: &[Self],
         selectors: &[BoolVar],
         coef: E,
-    ) -> Result<Self, CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>,
-    {
+    ) -> Result<Self, CircuitError> {
         let neutral_point = Self::KeyType::default();
         let emulated_neutral_point_var =
             circuit.create_constant_emulated_sw_point_variable(neutral_point)?;
@@ -230,53 +480,51 @@ where
         Ok(agg_key_var)
     }
 
-    fn is_equal<F>(
+    fn is_equal(
         circuit: &mut PlonkCircuit<F>,
         p0: &Self,
         p1: &Self,
-    ) -> Result<BoolVar, CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>,
-    {
+    ) -> Result<BoolVar, CircuitError> {
         circuit.is_emulated_sw_point_equal(p0, p1)
     }
 
-    fn enforce_equal<F>(
+    fn enforce_equal(
         circuit: &mut PlonkCircuit<F>,
         p0: &Self,
         p1: &Self,
-    ) -> Result<(), CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>,
-    {
+    ) -> Result<(), CircuitError> {
         circuit.enforce_emulated_sw_point_equal(p0, p1)
     }
 }
 
-impl<E> VerKeyVar<E> for EmulatedTEPointVariable<E>
-where
-    E: PrimeField,
-{
-    type KeyType = TEPoint<E>;
-
+impl<E: PrimeField> StakeKeyVar for EmulatedTEPointVariable<E> {
     fn native_vars(&self) -> Vec<Variable> {
         let mut ret = self.0.native_vars();
         ret.append(&mut self.1.native_vars());
         ret
     }
+}
 
-    fn aggregate_with_selectors<F>(
+impl<F, E> VerKeyVar<F, E> for EmulatedTEPointVariable<E>
+where
+    F: PrimeField,
+    E: EmulationConfig<F>,
+{
+    type KeyType = TEPoint<E>;
+
+    fn create_constant_variable(
+        circuit: &mut PlonkCircuit<F>,
+        key: Self::KeyType,
+    ) -> Result<Self, CircuitError> {
+        circuit.create_constant_emulated_te_point_variable(key)
+    }
+
+    fn aggregate_with_selectors(
         circuit: &mut PlonkCircuit<F>,
         keys: &[Self],
         selectors: &[BoolVar],
         coef: E,
-    ) -> Result<Self, CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>,
-    {
+    ) -> Result<Self, CircuitError> {
         let neutral_point = Self::KeyType::default();
         let emulated_neutral_point_var =
             circuit.create_constant_emulated_te_point_variable(neutral_point)?;
@@ -292,34 +540,83 @@ where
         Ok(agg_key_var)
     }
 
-    fn is_equal<F>(
+    fn is_equal(
         circuit: &mut PlonkCircuit<F>,
         p0: &Self,
         p1: &Self,
-    ) -> Result<BoolVar, CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>,
-    {
+    ) -> Result<BoolVar, CircuitError> {
         circuit.is_emulated_te_point_equal(p0, p1)
     }
 
-    fn enforce_equal<F>(
+    fn enforce_equal(
         circuit: &mut PlonkCircuit<F>,
         p0: &Self,
         p1: &Self,
-    ) -> Result<(), CircuitError>
-    where
-        F: PrimeField,
-        E: EmulationConfig<F>,
-    {
+    ) -> Result<(), CircuitError> {
         circuit.enforce_emulated_te_point_equal(p0, p1)
     }
 }
 
+impl StakeKeyVar for PointVariable {
+    fn native_vars(&self) -> Vec<Variable> {
+        vec![self.get_x(), self.get_y()]
+    }
+}
+
+/// Keys over a twisted Edwards curve embedded in the circuit field, e.g. the Schnorr keys over
+/// BabyJubjub in a circuit over BN254, take native arithmetic only.
+/// The internal curve parameter is the marker `PhantomData<P>` of the curve config `P`, the
+/// curve being entirely defined by `P`.
+impl<F, P> VerKeyVar<F, PhantomData<P>> for PointVariable
+where
+    F: PrimeField,
+    P: TECurveConfig<BaseField = F>,
+{
+    type KeyType = TEPoint<F>;
+
+    fn create_constant_variable(
+        circuit: &mut PlonkCircuit<F>,
+        key: Self::KeyType,
+    ) -> Result<Self, CircuitError> {
+        circuit.create_constant_point_variable(key)
+    }
+
+    fn aggregate_with_selectors(
+        circuit: &mut PlonkCircuit<F>,
+        keys: &[Self],
+        selectors: &[BoolVar],
+        _coef: PhantomData<P>,
+    ) -> Result<Self, CircuitError> {
+        let neutral_point_var = circuit.neutral_point_variable();
+        let mut agg_key_var = circuit.neutral_point_variable();
+        for (key, &bit) in keys.iter().zip(selectors.iter()) {
+            let point_var = circuit.binary_point_vars_select(bit, &neutral_point_var, key)?;
+            agg_key_var = circuit.ecc_add::<P>(&agg_key_var, &point_var)?;
+        }
+        Ok(agg_key_var)
+    }
+
+    fn is_equal(
+        circuit: &mut PlonkCircuit<F>,
+        p0: &Self,
+        p1: &Self,
+    ) -> Result<BoolVar, CircuitError> {
+        circuit.is_equal_point(p0, p1)
+    }
+
+    fn enforce_equal(
+        circuit: &mut PlonkCircuit<F>,
+        p0: &Self,
+        p1: &Self,
+    ) -> Result<(), CircuitError> {
+        circuit.enforce_point_equal(p0, p1)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::qc::digest::hash_stake_table_entries;
     use ark_bls12_377::{g1::Config as Param377, Fq as Fq377};
     use ark_bn254::{g1::Config as Param254, Fq as Fq254, Fr as Fr254};
     use ark_ec::{
@@ -541,4 +838,293 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_vk_aggregate_native_te_circuit() -> Result<(), CircuitError> {
+        use ark_ed_on_bn254::{EdwardsAffine, EdwardsConfig, EdwardsProjective};
+
+        let mut rng = jf_utils::test_rng();
+        let vk_points: Vec<EdwardsProjective> =
+            (0..5).map(|_| EdwardsProjective::rand(&mut rng)).collect();
+        let selector = vec![false, true, false, true, false];
+        let agg_vk_point: TEPoint<Fr254> = vk_points
+            .iter()
+            .zip(selector.iter())
+            .filter(|(_, b)| **b)
+            .fold(EdwardsProjective::zero(), |acc, (x, _)| acc + x)
+            .into_affine()
+            .into();
+        let vk_points: Vec<TEPoint<Fr254>> = vk_points
+            .iter()
+            .map(|p| EdwardsAffine::from(*p).into())
+            .collect();
+        let stake_amts: Vec<Fr254> = (0..5).map(|i| Fr254::from((i + 1) as u32)).collect();
+        let threshold = Fr254::from(6u8);
+        let keys: Vec<Vec<Fr254>> = vk_points
+            .iter()
+            .map(|p| vec![p.get_x(), p.get_y()])
+            .collect();
+        let digest = hash_stake_table_entries(&stake_amts[..], &keys[..]);
+
+        let mut circuit = PlonkCircuit::<Fr254>::new_turbo_plonk();
+        // public input
+        let agg_vk_var = circuit.create_public_point_variable(agg_vk_point)?;
+        let public_input = [agg_vk_point.get_x(), agg_vk_point.get_y()];
+        let threshold_var = circuit.create_variable(threshold)?;
+        let digest_var = circuit.create_variable(digest)?;
+
+        // add witness
+        let vk_vars: Vec<PointVariable> = vk_points
+            .iter()
+            .map(|&p| circuit.create_point_variable(p).unwrap())
+            .collect();
+        let stake_amt_vars: Vec<Variable> = stake_amts
+            .iter()
+            .map(|&amt| circuit.create_variable(amt).unwrap())
+            .collect();
+        let selector_vars: Vec<BoolVar> = selector
+            .iter()
+            .map(|&b| circuit.create_boolean_variable(b).unwrap())
+            .collect();
+        // add circuit gadgets
+        let num_gates = circuit.num_gates();
+        circuit.check_aggregate_vk::<PhantomData<EdwardsConfig>, PointVariable>(
+            &vk_vars[..],
+            &selector_vars[..],
+            &agg_vk_var,
+            PhantomData,
+        )?;
+        let native_num_gates = circuit.num_gates() - num_gates;
+        circuit.check_stake_table_digest(&vk_vars[..], &stake_amt_vars[..], digest_var)?;
+        circuit.check_threshold(&stake_amt_vars[..], &selector_vars[..], threshold_var)?;
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_ok());
+
+        // bad path: wrong aggregated vk
+        let mut bad_input = public_input;
+        bad_input[0] = Fr254::zero();
+        assert!(circuit.check_circuit_satisfiability(&bad_input).is_err());
+
+        // bad path: wrong digest
+        *circuit.witness_mut(digest_var) = Fr254::zero();
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_err());
+        *circuit.witness_mut(digest_var) = digest;
+
+        // bad path: wrong selector
+        *circuit.witness_mut(selector_vars[0].into()) = Fr254::from(1u8);
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_err());
+        *circuit.witness_mut(selector_vars[0].into()) = Fr254::zero();
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_ok());
+
+        // the same aggregation over emulated keys
+        let d_ecc : Fq377 = MontFp!("122268283598675559488486339158635529096981886914877139579534153582033676785385790730042363341236035746924960903179");
+        let emulated_points: Vec<TEPoint<Fq377>> = (0..5)
+            .map(|_| Projective::<Param377>::rand(&mut rng).into_affine().into())
+            .collect();
+        let mut circuit = PlonkCircuit::<Fr254>::new_ultra_plonk(20);
+        let agg_vk_var = circuit.create_emulated_te_point_variable(TEPoint::default())?;
+        let vk_vars: Vec<EmulatedTEPointVariable<Fq377>> = emulated_points
+            .iter()
+            .map(|&p| circuit.create_emulated_te_point_variable(p).unwrap())
+            .collect();
+        let selector_vars: Vec<BoolVar> = selector
+            .iter()
+            .map(|&b| circuit.create_boolean_variable(b).unwrap())
+            .collect();
+        let num_gates = circuit.num_gates();
+        circuit.check_aggregate_vk::<Fq377, EmulatedTEPointVariable<Fq377>>(
+            &vk_vars[..],
+            &selector_vars[..],
+            &agg_vk_var,
+            d_ecc,
+        )?;
+        let emulated_num_gates = circuit.num_gates() - num_gates;
+        // native keys are cheaper by at least an order of magnitude
+        assert!(native_num_gates * 10 < emulated_num_gates);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validator_keys_stake_table_digest() -> Result<(), CircuitError> {
+        use ark_ed_on_bn254::EdwardsConfig;
+        use jf_primitives::signatures::{bls_over_bn254, schnorr};
+
+        let mut rng = jf_utils::test_rng();
+        let keys: Vec<ValidatorKeys<EdwardsConfig>> = (0..5)
+            .map(|_| ValidatorKeys {
+                bls_key: bls_over_bn254::KeyPair::generate(&mut rng).ver_key(),
+                schnorr_key: schnorr::KeyPair::<EdwardsConfig>::generate(&mut rng).ver_key(),
+            })
+            .collect();
+        let stake_amts: Vec<Fr254> = (0..5).map(|i| Fr254::from((i + 1) as u32)).collect();
+        // fixed layout: 4 emulated coordinates and the infinity flag of the BLS key, then the 2
+        // native coordinates of the Schnorr key
+        let num_elements = 4 * <Fq254 as EmulationConfig<Fr254>>::NUM_LIMBS + 3;
+        for key in keys.iter() {
+            assert_eq!(key.serialize_to_native_elements().len(), num_elements);
+        }
+        let digest = compute_stake_table_hash(&stake_amts[..], &keys[..]);
+        // the digest covers both keys
+        let mut other_keys = keys.clone();
+        other_keys[2].bls_key = keys[3].bls_key.clone();
+        assert_ne!(
+            digest,
+            compute_stake_table_hash(&stake_amts[..], &other_keys[..])
+        );
+        let mut other_keys = keys.clone();
+        other_keys[2].schnorr_key = keys[3].schnorr_key.clone();
+        assert_ne!(
+            digest,
+            compute_stake_table_hash(&stake_amts[..], &other_keys[..])
+        );
+
+        let mut circuit = PlonkCircuit::<Fr254>::new_turbo_plonk();
+        let digest_var = circuit.create_public_variable(digest)?;
+        let key_vars = keys
+            .iter()
+            .map(|key| ValidatorKeysVar::new(&mut circuit, key))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (key, key_var) in keys.iter().zip(key_vars.iter()) {
+            let elements = key_var
+                .native_vars()
+                .iter()
+                .map(|&var| circuit.witness(var))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(elements, key.serialize_to_native_elements());
+        }
+        let stake_amt_vars: Vec<Variable> = stake_amts
+            .iter()
+            .map(|&amt| circuit.create_variable(amt).unwrap())
+            .collect();
+        circuit.check_stake_table_digest(&key_vars[..], &stake_amt_vars[..], digest_var)?;
+        assert!(circuit.check_circuit_satisfiability(&[digest]).is_ok());
+
+        // bad paths: wrong BLS key or Schnorr key
+        for tmp_var in [
+            key_vars[2].native_vars()[0],
+            key_vars[2].schnorr_key().get_y(),
+        ] {
+            let tmp = circuit.witness(tmp_var)?;
+            *circuit.witness_mut(tmp_var) = Fr254::zero();
+            assert!(circuit.check_circuit_satisfiability(&[digest]).is_err());
+            *circuit.witness_mut(tmp_var) = tmp;
+        }
+        assert!(circuit.check_circuit_satisfiability(&[digest]).is_ok());
+
+        // check input parameter errors
+        assert!(circuit
+            .check_stake_table_digest(&key_vars[1..], &stake_amt_vars[..], digest_var)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_threshold_adversarial() -> Result<(), CircuitError> {
+        // the honest sum of the selected amounts is 3 < 4
+        let stake_amts = [Fr254::from(1u8), Fr254::from(2u8), Fr254::from(3u8)];
+        let selector = [true, true, false];
+        let threshold = Fr254::from(4u8);
+        let build = |stake_amts: &[Fr254]| -> Result<_, CircuitError> {
+            let mut circuit = PlonkCircuit::<Fr254>::new_ultra_plonk(8);
+            let threshold_var = circuit.create_public_variable(threshold)?;
+            let stake_amt_vars: Vec<Variable> = stake_amts
+                .iter()
+                .map(|&amt| circuit.create_variable(amt).unwrap())
+                .collect();
+            let selector_vars: Vec<BoolVar> = selector
+                .iter()
+                .map(|&b| circuit.create_boolean_variable(b).unwrap())
+                .collect();
+            circuit.check_threshold(&stake_amt_vars[..], &selector_vars[..], threshold_var)?;
+            Ok((circuit, stake_amt_vars))
+        };
+        let (mut circuit, stake_amt_vars) = build(&stake_amts)?;
+        assert!(circuit.check_circuit_satisfiability(&[threshold]).is_err());
+        // the largest amount allowed
+        let max_amt = Fr254::from(u128::MAX);
+        *circuit.witness_mut(stake_amt_vars[0]) = max_amt;
+        assert!(circuit.check_circuit_satisfiability(&[threshold]).is_ok());
+
+        // bad path: an amount just above the range
+        let (mut circuit, stake_amt_vars) = build(&stake_amts)?;
+        *circuit.witness_mut(stake_amt_vars[0]) = max_amt + Fr254::from(1u8);
+        assert!(circuit.check_circuit_satisfiability(&[threshold]).is_err());
+        // bad path: a huge amount, which would pass the threshold without range checks
+        *circuit.witness_mut(stake_amt_vars[0]) = ark_ff::Field::pow(&Fr254::from(2u8), [200u64]);
+        assert!(circuit.check_circuit_satisfiability(&[threshold]).is_err());
+        // bad path: amounts wrapping around the modulus, e.g. `(p - 1) + 5 = 4`
+        *circuit.witness_mut(stake_amt_vars[0]) = -Fr254::from(1u8);
+        *circuit.witness_mut(stake_amt_vars[1]) = Fr254::from(5u8);
+        assert!(circuit.check_circuit_satisfiability(&[threshold]).is_err());
+        // bad path: a huge amount of a non-signer
+        let (mut circuit, stake_amt_vars) = build(&stake_amts)?;
+        *circuit.witness_mut(stake_amt_vars[1]) = Fr254::from(3u8);
+        assert!(circuit.check_circuit_satisfiability(&[threshold]).is_ok());
+        *circuit.witness_mut(stake_amt_vars[2]) = -Fr254::from(1u8);
+        assert!(circuit.check_circuit_satisfiability(&[threshold]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_bit_vector() -> Result<(), CircuitError> {
+        let len = 600;
+        let bit_vec: BitVec = (0..len).map(|i| i % 3 == 0 || i % 7 == 1).collect();
+        let packed = pack_bit_vector::<Fr254>(&bit_vec);
+        // 253 bits per element
+        assert_eq!(bit_vector_chunk_size::<Fr254>(), 253);
+        assert_eq!(packed.len(), 3);
+        assert_eq!(
+            pack_bit_vector::<Fr254>(&bitvec![1, 0, 1]),
+            vec![Fr254::from(5u8)]
+        );
+        assert!(pack_bit_vector::<Fr254>(&BitVec::new()).is_empty());
+
+        let mut circuit = PlonkCircuit::<Fr254>::new_turbo_plonk();
+        let packed_vars: Vec<Variable> = packed
+            .iter()
+            .map(|&x| circuit.create_public_variable(x).unwrap())
+            .collect();
+        let bit_vars = circuit.unpack_bit_vector(&packed_vars, len)?;
+        assert_eq!(bit_vars.len(), len);
+        for (&bit_var, bit) in bit_vars.iter().zip(bit_vec.iter()) {
+            assert_eq!(circuit.witness(bit_var.into())?, Fr254::from(*bit as u8));
+        }
+        assert!(circuit.check_circuit_satisfiability(&packed).is_ok());
+
+        // bad path: wrong public input
+        let mut bad_input = packed.clone();
+        bad_input[1] += Fr254::from(1u8);
+        assert!(circuit.check_circuit_satisfiability(&bad_input).is_err());
+        // bad path: an unpacked bit flipped
+        let bit_var: Variable = bit_vars[300].into();
+        *circuit.witness_mut(bit_var) = Fr254::from(1u8) - circuit.witness(bit_var)?;
+        assert!(circuit.check_circuit_satisfiability(&packed).is_err());
+        // bad path: a non-Boolean bit compensated by the next one
+        *circuit.witness_mut(bit_var) = Fr254::from(bit_vec[300] as u8) + Fr254::from(2u8);
+        let next_var: Variable = bit_vars[301].into();
+        *circuit.witness_mut(next_var) = circuit.witness(next_var)? - Fr254::from(1u8);
+        assert!(circuit.check_circuit_satisfiability(&packed).is_err());
+
+        // bad path: the last element holding more bits than the bit vector
+        let mut bad_packed = packed.clone();
+        bad_packed[2] += ark_ff::Field::pow(&Fr254::from(2u8), [(len - 2 * 253) as u64]);
+        let mut circuit = PlonkCircuit::<Fr254>::new_turbo_plonk();
+        let packed_vars: Vec<Variable> = bad_packed
+            .iter()
+            .map(|&x| circuit.create_public_variable(x).unwrap())
+            .collect();
+        circuit.unpack_bit_vector(&packed_vars, len)?;
+        assert!(circuit.check_circuit_satisfiability(&bad_packed).is_err());
+
+        // check input parameter errors
+        assert!(circuit
+            .unpack_bit_vector(&packed_vars, 253 * 3 + 1)
+            .is_err());
+        assert!(circuit.unpack_bit_vector(&packed_vars, 2 * 253).is_err());
+        assert!(circuit.unpack_bit_vector(&packed_vars, 2 * 253 + 1).is_ok());
+
+        Ok(())
+    }
 }
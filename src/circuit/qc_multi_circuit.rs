@@ -0,0 +1,316 @@
+//! Circuit for the verification of `k` quorum certificates of consecutive views over the same
+//! committed stake table, e.g. the two or three chained QCs of the HotShot commit rules, in a
+//! single proof.
+//!
+//! The public inputs of the circuit are laid out as
+//! * `digest` - the stake table digest, see [`compute_stake_table_hash`],
+//! * for each QC, in the order of the chain,
+//!   - `view` - the view of the certified message,
+//!   - `agg_key` - the native limbs of the aggregated key of the signers,
+//! * `threshold` - the quorum threshold, shared by all the QCs.
+//!
+//! The view of each QC is constrained to be the view following the one of the previous QC, so
+//! that a verifier checking the aggregated signatures over the messages of these views gets a
+//! chain of certified messages. The stake table is hashed once, while the aggregated key and the
+//! threshold are checked for each signer bit vector.
+
+use crate::circuit::{
+    qc_circuit::{QcCircuitKey, QC_CIRCUIT_RANGE_BIT_LEN},
+    qc_keyagg::{compute_stake_table_hash, QCKeyAggregateGadget},
+};
+use ark_std::{format, vec, vec::Vec};
+use jf_primitives::rescue::RescueParameter;
+use jf_relation::{
+    errors::CircuitError,
+    gadgets::{EmulationConfig, SerializableEmulatedStruct},
+    BoolVar, Circuit, PlonkCircuit, Variable,
+};
+
+/// A QC of the chain.
+#[derive(Clone, Debug)]
+pub struct ChainedQc<K> {
+    /// View of the certified message
+    pub view: u64,
+    /// Signer bit vector
+    pub signers: Vec<bool>,
+    /// Aggregated key of the signers
+    pub agg_key: K,
+}
+
+/// The variables of a QC of the chain allocated by [`MultiQcCircuit::synthesize`].
+#[derive(Clone, Debug)]
+pub struct ChainedQcVars<V> {
+    /// Public view
+    pub view: Variable,
+    /// Public aggregated key
+    pub agg_key: V,
+    /// Signer bit vector
+    pub signers: Vec<BoolVar>,
+}
+
+/// The variables allocated by [`MultiQcCircuit::synthesize`].
+#[derive(Clone, Debug)]
+pub struct MultiQcCircuitVars<V> {
+    /// Public stake table digest
+    pub digest: Variable,
+    /// The QCs of the chain
+    pub qcs: Vec<ChainedQcVars<V>>,
+    /// Public quorum threshold
+    pub threshold: Variable,
+    /// Stake keys
+    pub stake_keys: Vec<V>,
+    /// Stake amounts
+    pub stake_amts: Vec<Variable>,
+}
+
+/// Builder of the circuit verifying a chain of QCs.
+#[derive(Clone, Debug)]
+pub struct MultiQcCircuit<F, E, K> {
+    /// Stake keys of the stake table
+    pub stake_keys: Vec<K>,
+    /// Stake amounts of the stake table
+    pub stake_amts: Vec<F>,
+    /// The QCs, in the order of their views
+    pub qcs: Vec<ChainedQc<K>>,
+    /// Quorum threshold
+    pub threshold: F,
+    /// The internal curve parameter
+    pub coef: E,
+}
+
+impl<F, E, K> MultiQcCircuit<F, E, K>
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+    K: QcCircuitKey<F, E>,
+{
+    /// Creates the builder.
+    /// * `stake_keys` - list of stake public keys.
+    /// * `stake_amts` - list of stake amounts for the corresponding stake keys.
+    /// * `qcs` - the QCs, in the order of their views.
+    /// * `threshold` - the quorum threshold.
+    /// * `coef` - the internal curve parameter.
+    pub fn new(
+        stake_keys: Vec<K>,
+        stake_amts: Vec<F>,
+        qcs: Vec<ChainedQc<K>>,
+        threshold: F,
+        coef: E,
+    ) -> Self {
+        Self {
+            stake_keys,
+            stake_amts,
+            qcs,
+            threshold,
+            coef,
+        }
+    }
+
+    /// The public inputs of the circuit, in the order given in the module documentation.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut public_inputs = vec![compute_stake_table_hash(&self.stake_amts, &self.stake_keys)];
+        for qc in self.qcs.iter() {
+            public_inputs.push(F::from(qc.view));
+            public_inputs.extend(qc.agg_key.serialize_to_native_elements());
+        }
+        public_inputs.push(self.threshold);
+        public_inputs
+    }
+
+    /// Allocates the public inputs and witnesses in `circuit` and adds the QC checking gadgets.
+    /// The public inputs are allocated first, so `circuit` should not have any public input yet.
+    pub fn synthesize(
+        &self,
+        circuit: &mut PlonkCircuit<F>,
+    ) -> Result<MultiQcCircuitVars<K::Var>, CircuitError> {
+        if self.qcs.is_empty() {
+            return Err(CircuitError::ParameterError(format!(
+                "no QC to check over the stake table of {} keys",
+                self.stake_keys.len()
+            )));
+        }
+
+        // public input
+        let digest = circuit
+            .create_public_variable(compute_stake_table_hash(&self.stake_amts, &self.stake_keys))?;
+        let mut public_qcs = vec![];
+        for qc in self.qcs.iter() {
+            let view = circuit.create_public_variable(F::from(qc.view))?;
+            let agg_key = qc.agg_key.create_public_variable(circuit)?;
+            public_qcs.push((view, agg_key));
+        }
+        let threshold = circuit.create_public_variable(self.threshold)?;
+
+        // add witness
+        let stake_keys = self
+            .stake_keys
+            .iter()
+            .map(|key| key.create_variable(circuit))
+            .collect::<Result<Vec<_>, _>>()?;
+        let stake_amts = self
+            .stake_amts
+            .iter()
+            .map(|&amt| circuit.create_variable(amt))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut qcs = vec![];
+        for ((view, agg_key), qc) in public_qcs.into_iter().zip(self.qcs.iter()) {
+            let signers = qc
+                .signers
+                .iter()
+                .map(|&b| circuit.create_boolean_variable(b))
+                .collect::<Result<Vec<_>, _>>()?;
+            qcs.push(ChainedQcVars {
+                view,
+                agg_key,
+                signers,
+            });
+        }
+
+        // add circuit gadgets
+        circuit.check_stake_table_digest(&stake_keys, &stake_amts, digest)?;
+        for qc in qcs.iter() {
+            circuit.check_aggregate_vk(&stake_keys, &qc.signers, &qc.agg_key, self.coef)?;
+            circuit.check_threshold(&stake_amts, &qc.signers, threshold)?;
+            circuit.check_stake_table_padding(&stake_keys, &stake_amts, &qc.signers)?;
+        }
+        for pair in qcs.windows(2) {
+            let next_view = circuit.add_constant(pair[0].view, &F::one())?;
+            circuit.enforce_equal(next_view, pair[1].view)?;
+        }
+
+        Ok(MultiQcCircuitVars {
+            digest,
+            qcs,
+            threshold,
+            stake_keys,
+            stake_amts,
+        })
+    }
+
+    /// Builds a new UltraPlonk circuit checking the chain of QCs.
+    /// The circuit is not finalized.
+    pub fn build(&self) -> Result<PlonkCircuit<F>, CircuitError> {
+        let mut circuit = PlonkCircuit::new_ultra_plonk(QC_CIRCUIT_RANGE_BIT_LEN);
+        self.synthesize(&mut circuit)?;
+        Ok(circuit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::qc_circuit::QcCircuit;
+    use ark_bn254::{g1::Config as Param254, Fq as Fq254, Fr as Fr254};
+    use ark_ec::{short_weierstrass::Projective, CurveGroup};
+    use ark_std::{UniformRand, Zero};
+    use jf_relation::gadgets::ecc::emulated::SWPoint;
+
+    type Circuit254 = MultiQcCircuit<Fr254, Fq254, SWPoint<Fq254>>;
+
+    fn test_multi_qc(views: &[u64], signers: &[Vec<bool>], threshold: u8) -> Circuit254 {
+        let mut rng = jf_utils::test_rng();
+        let points: Vec<Projective<Param254>> = (0..signers[0].len())
+            .map(|_| Projective::<Param254>::rand(&mut rng))
+            .collect();
+        let qcs = views
+            .iter()
+            .zip(signers.iter())
+            .map(|(&view, signers)| {
+                let agg_key = points
+                    .iter()
+                    .zip(signers.iter())
+                    .filter(|(_, b)| **b)
+                    .fold(Projective::<Param254>::zero(), |acc, (p, _)| acc + p);
+                ChainedQc {
+                    view,
+                    signers: signers.clone(),
+                    agg_key: agg_key.into_affine().into(),
+                }
+            })
+            .collect();
+        MultiQcCircuit::new(
+            points.iter().map(|p| p.into_affine().into()).collect(),
+            (1..=points.len() as u8).map(Fr254::from).collect(),
+            qcs,
+            Fr254::from(threshold),
+            Fq254::zero(),
+        )
+    }
+
+    #[test]
+    fn test_multi_qc_circuit() -> Result<(), CircuitError> {
+        let signers = vec![
+            vec![false, true, false, true, false],
+            vec![true, false, false, false, true],
+            vec![true, true, true, true, true],
+        ];
+        let qc = test_multi_qc(&[7, 8, 9], &signers, 6);
+        let public_inputs = qc.public_inputs();
+        let agg_key_limbs = qc.qcs[1].agg_key.serialize_to_native_elements();
+        assert_eq!(public_inputs.len(), 2 + 3 * (1 + agg_key_limbs.len()));
+        assert_eq!(
+            public_inputs[0],
+            compute_stake_table_hash(&qc.stake_amts, &qc.stake_keys)
+        );
+        let offset = 2 + agg_key_limbs.len();
+        assert_eq!(public_inputs[offset], Fr254::from(8u8));
+        assert_eq!(
+            public_inputs[offset + 1..=offset + agg_key_limbs.len()],
+            agg_key_limbs[..]
+        );
+        assert_eq!(*public_inputs.last().unwrap(), Fr254::from(6u8));
+
+        let circuit = qc.build()?;
+        assert_eq!(circuit.num_inputs(), public_inputs.len());
+        assert!(circuit.check_circuit_satisfiability(&public_inputs).is_ok());
+
+        // the stake table is hashed once
+        let single_qc = QcCircuit::new(
+            qc.stake_keys.clone(),
+            qc.stake_amts.clone(),
+            signers[0].clone(),
+            qc.qcs[0].agg_key,
+            qc.threshold,
+            qc.coef,
+        );
+        assert!(circuit.num_gates() < 3 * single_qc.build()?.num_gates());
+
+        // bad path: wrong public inputs
+        for i in [0, 1, 2, offset, public_inputs.len() - 1] {
+            let mut bad_inputs = public_inputs.clone();
+            bad_inputs[i] += Fr254::from(1u8);
+            assert!(circuit.check_circuit_satisfiability(&bad_inputs).is_err());
+        }
+        // bad path: views not consecutive
+        for views in [[7, 9, 10], [7, 8, 8], [9, 8, 7]] {
+            let qc = test_multi_qc(&views, &signers, 6);
+            assert!(qc
+                .build()?
+                .check_circuit_satisfiability(&qc.public_inputs())
+                .is_err());
+        }
+        // bad path: one QC does not reach the threshold
+        let qc = test_multi_qc(&[7, 8, 9], &signers, 7);
+        assert!(qc
+            .build()?
+            .check_circuit_satisfiability(&qc.public_inputs())
+            .is_err());
+        // bad path: the aggregated key of a QC is not the one of its signers
+        let mut qc = test_multi_qc(&[7, 8, 9], &signers, 6);
+        qc.qcs[2].signers[0] = false;
+        assert!(qc
+            .build()?
+            .check_circuit_satisfiability(&qc.public_inputs())
+            .is_err());
+
+        // check input parameter errors
+        let mut qc = test_multi_qc(&[7, 8, 9], &signers, 6);
+        qc.qcs[1].signers.pop();
+        assert!(qc.build().is_err());
+        let mut qc = test_multi_qc(&[7, 8, 9], &signers, 6);
+        qc.qcs.clear();
+        assert!(qc.build().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+//! Circuit implementation of the Schnorr QC verification, see [`crate::qc::schnorr`].
+
+use crate::{circuit::qc_keyagg::QCKeyAggregateGadget, qc::digest::hash_stake_table_entries};
+use ark_ec::twisted_edwards::TECurveConfig;
+use ark_std::{format, vec, vec::Vec};
+use jf_primitives::{
+    circuit::signature::schnorr::{SignatureGadget, SignatureVar, VerKeyVar},
+    rescue::RescueParameter,
+    signatures::schnorr::{Signature, VerKey},
+};
+use jf_relation::{
+    errors::CircuitError, gadgets::ecc::PointVariable, BoolVar, Circuit, PlonkCircuit, Variable,
+};
+
+/// Digest a stake table of Schnorr keys, as checked by [`QCSchnorrGadget::check_schnorr_qc`].
+/// Each key contributes the coordinates of its affine point, see `StakeKeyVar` for
+/// `PointVariable`.
+/// * `stake_amts` - stake amounts
+/// * `vks` - list of Schnorr verification keys
+pub fn compute_schnorr_stake_table_hash<F, P>(stake_amts: &[F], vks: &[VerKey<P>]) -> F
+where
+    F: RescueParameter,
+    P: TECurveConfig<BaseField = F>,
+{
+    let keys: Vec<Vec<F>> = vks
+        .iter()
+        .map(|vk| {
+            let point = vk.to_affine();
+            vec![point.x, point.y]
+        })
+        .collect();
+    hash_stake_table_entries(stake_amts, &keys)
+}
+
+/// Expands the signatures of a Schnorr QC to one signature per stake entry, as expected by
+/// [`QCSchnorrGadget::check_schnorr_signatures`].
+/// The slots of the non-signers are filled with a copy of the first signature, they are ignored
+/// by the circuit.
+/// * `signers` - the signer bit vector of the QC
+/// * `sigs` - the signatures of the QC
+pub fn expand_signatures<P: TECurveConfig>(
+    signers: &[bool],
+    sigs: &[Signature<P>],
+) -> Result<Vec<Signature<P>>, CircuitError> {
+    if signers.iter().filter(|b| **b).count() != sigs.len() {
+        return Err(CircuitError::ParameterError(format!(
+            "the number of signers != the number of signatures {}",
+            sigs.len(),
+        )));
+    }
+    let dummy = sigs
+        .first()
+        .ok_or_else(|| CircuitError::ParameterError("no signature in the QC".into()))?;
+    let mut sigs = sigs.iter();
+    Ok(signers
+        .iter()
+        .map(|b| {
+            if *b {
+                sigs.next().unwrap_or(dummy).clone()
+            } else {
+                dummy.clone()
+            }
+        })
+        .collect())
+}
+
+/// Plonk circuit gadget for Schnorr quorum certificates.
+pub trait QCSchnorrGadget<F, P>
+where
+    F: RescueParameter,
+    P: TECurveConfig<BaseField = F>,
+{
+    /// Signature checking circuit: every selected signature is valid.
+    /// * `vks` - list of stake public keys.
+    /// * `bit_vec` - the indicator vector for the quorum set.
+    /// * `msg` - the signed message.
+    /// * `sigs` - one signature per stake key, arbitrary for the keys not in the quorum set.
+    fn check_schnorr_signatures(
+        &mut self,
+        vks: &[VerKeyVar],
+        bit_vec: &[BoolVar],
+        msg: &[Variable],
+        sigs: &[SignatureVar],
+    ) -> Result<(), CircuitError>;
+
+    /// Schnorr QC checking circuit: the stake keys and amounts are the ones of the committed
+    /// stake table, and the selected signatures are valid and reach the threshold.
+    /// * `vks` - list of stake public keys.
+    /// * `stake_amts` - list of stake amounts for the corresponding stake keys.
+    /// * `digest` - the public stake table digest, see [`compute_schnorr_stake_table_hash`].
+    /// * `bit_vec` - the indicator vector for the quorum set.
+    /// * `msg` - the signed message.
+    /// * `sigs` - one signature per stake key, see [`expand_signatures`].
+    /// * `threshold` - the public quorum threshold.
+    #[allow(clippy::too_many_arguments)]
+    fn check_schnorr_qc(
+        &mut self,
+        vks: &[VerKeyVar],
+        stake_amts: &[Variable],
+        digest: Variable,
+        bit_vec: &[BoolVar],
+        msg: &[Variable],
+        sigs: &[SignatureVar],
+        threshold: Variable,
+    ) -> Result<(), CircuitError>;
+}
+
+impl<F, P> QCSchnorrGadget<F, P> for PlonkCircuit<F>
+where
+    F: RescueParameter,
+    P: TECurveConfig<BaseField = F>,
+{
+    fn check_schnorr_signatures(
+        &mut self,
+        vks: &[VerKeyVar],
+        bit_vec: &[BoolVar],
+        msg: &[Variable],
+        sigs: &[SignatureVar],
+    ) -> Result<(), CircuitError> {
+        if vks.len() != bit_vec.len() || vks.len() != sigs.len() {
+            return Err(CircuitError::ParameterError(format!(
+                "bit vector len {} or the number of signatures {} != the number of stake keys {}",
+                bit_vec.len(),
+                sigs.len(),
+                vks.len(),
+            )));
+        }
+        for ((vk, sig), &bit) in vks.iter().zip(sigs.iter()).zip(bit_vec.iter()) {
+            let valid = SignatureGadget::<F, P>::check_signature_validity(self, vk, msg, sig)?;
+            // bit => valid
+            let not_bit = self.logic_neg(bit)?;
+            self.logic_or_gate(not_bit, valid)?;
+        }
+        Ok(())
+    }
+
+    fn check_schnorr_qc(
+        &mut self,
+        vks: &[VerKeyVar],
+        stake_amts: &[Variable],
+        digest: Variable,
+        bit_vec: &[BoolVar],
+        msg: &[Variable],
+        sigs: &[SignatureVar],
+        threshold: Variable,
+    ) -> Result<(), CircuitError> {
+        let points: Vec<PointVariable> = vks.iter().map(|vk| vk.0).collect();
+        self.check_stake_table_digest(&points, stake_amts, digest)?;
+        QCSchnorrGadget::<F, P>::check_schnorr_signatures(self, vks, bit_vec, msg, sigs)?;
+        self.check_threshold(stake_amts, bit_vec, threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::{
+        bit_vector::{QCParams, StakeTableEntry},
+        policy::ThresholdPolicy,
+        schnorr::SchnorrQC,
+        QuorumCertificate,
+    };
+    use ark_bn254::Fr as Fr254;
+    use ark_ed_on_bn254::EdwardsConfig;
+    use ark_std::UniformRand;
+    use bitvec::prelude::*;
+    use ethereum_types::U256;
+    use generic_array::GenericArray;
+    use jf_primitives::signatures::schnorr::KeyPair;
+    use typenum::U3;
+
+    type QC = SchnorrQC<EdwardsConfig, U3>;
+
+    #[test]
+    fn test_schnorr_qc_circuit() -> Result<(), CircuitError> {
+        let mut rng = jf_utils::test_rng();
+        let key_pairs: Vec<_> = (0..5)
+            .map(|_| KeyPair::<EdwardsConfig>::generate(&mut rng))
+            .collect();
+        let stake_amts: Vec<u8> = (1..=5).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .zip(stake_amts.iter())
+                .map(|(kp, &amount)| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(amount),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(6u8)),
+            (),
+        )
+        .unwrap();
+        let msg: GenericArray<_, U3> = (0..3).map(|_| Fr254::rand(&mut rng)).collect();
+        let signers = bitvec![0, 1, 0, 1, 0];
+        let sigs: Vec<_> = [1, 3]
+            .iter()
+            .map(|&i| QC::sign(&(), &msg, key_pairs[i].sign_key_ref(), &mut rng).unwrap())
+            .collect();
+        let qc = QC::assemble(&qc_pp, &signers, &sigs).unwrap();
+        assert!(QC::check(&qc_pp, &msg, &qc).is_ok());
+        let selector: Vec<bool> = signers.iter().by_vals().collect();
+        let expanded_sigs = expand_signatures(&selector, &qc.0)?;
+        assert!(expand_signatures(&selector, &qc.0[1..]).is_err());
+
+        let stake_amts: Vec<Fr254> = stake_amts.iter().map(|&amt| Fr254::from(amt)).collect();
+        let vks: Vec<_> = key_pairs.iter().map(|kp| kp.ver_key()).collect();
+        let digest = compute_schnorr_stake_table_hash(&stake_amts, &vks);
+
+        let mut circuit = PlonkCircuit::<Fr254>::new_turbo_plonk();
+        // public input
+        let digest_var = circuit.create_public_variable(digest)?;
+        let msg_vars: Vec<Variable> = msg
+            .iter()
+            .map(|&m| circuit.create_public_variable(m).unwrap())
+            .collect();
+        let threshold_var = circuit.create_public_variable(Fr254::from(6u8))?;
+        let mut public_input = vec![digest];
+        public_input.extend_from_slice(&msg);
+        public_input.push(Fr254::from(6u8));
+
+        // add witness
+        let vk_vars: Vec<VerKeyVar> = vks
+            .iter()
+            .map(|vk| circuit.create_signature_vk_variable(vk).unwrap())
+            .collect();
+        let stake_amt_vars: Vec<Variable> = stake_amts
+            .iter()
+            .map(|&amt| circuit.create_variable(amt).unwrap())
+            .collect();
+        let selector_vars: Vec<BoolVar> = selector
+            .iter()
+            .map(|&b| circuit.create_boolean_variable(b).unwrap())
+            .collect();
+        let sig_vars: Vec<SignatureVar> = expanded_sigs
+            .iter()
+            .map(|sig| {
+                SignatureGadget::<_, EdwardsConfig>::create_signature_variable(&mut circuit, sig)
+                    .unwrap()
+            })
+            .collect();
+        // add circuit gadgets
+        QCSchnorrGadget::<_, EdwardsConfig>::check_schnorr_qc(
+            &mut circuit,
+            &vk_vars,
+            &stake_amt_vars,
+            digest_var,
+            &selector_vars,
+            &msg_vars,
+            &sig_vars,
+            threshold_var,
+        )?;
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_ok());
+
+        // bad path: wrong stake table digest
+        let mut bad_input = public_input.clone();
+        bad_input[0] += Fr254::from(1u8);
+        assert!(circuit.check_circuit_satisfiability(&bad_input).is_err());
+
+        // bad path: wrong message
+        let mut bad_input = public_input.clone();
+        bad_input[1] = Fr254::from(0u8);
+        assert!(circuit.check_circuit_satisfiability(&bad_input).is_err());
+
+        // bad path: bad threshold
+        let mut bad_input = public_input.clone();
+        bad_input[4] = Fr254::from(7u8);
+        assert!(circuit.check_circuit_satisfiability(&bad_input).is_err());
+
+        // bad path: a stake key or amount not in the committed stake table
+        let other_key = KeyPair::<EdwardsConfig>::generate(&mut rng).ver_key();
+        let key_x: Variable = vk_vars[0].0.get_x();
+        let old_x = circuit.witness(key_x)?;
+        *circuit.witness_mut(key_x) = other_key.to_affine().x;
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_err());
+        *circuit.witness_mut(key_x) = old_x;
+        *circuit.witness_mut(stake_amt_vars[1]) = Fr254::from(6u8);
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_err());
+        *circuit.witness_mut(stake_amt_vars[1]) = stake_amts[1];
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_ok());
+
+        // bad path: selecting a non-signer
+        *circuit.witness_mut(selector_vars[4].into()) = Fr254::from(1u8);
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_err());
+        *circuit.witness_mut(selector_vars[4].into()) = Fr254::from(0u8);
+        assert!(circuit.check_circuit_satisfiability(&public_input).is_ok());
+
+        // check input parameter errors
+        assert!(
+            QCSchnorrGadget::<_, EdwardsConfig>::check_schnorr_signatures(
+                &mut circuit,
+                &vk_vars,
+                &selector_vars,
+                &msg_vars,
+                &sig_vars[1..],
+            )
+            .is_err()
+        );
+        assert!(
+            QCSchnorrGadget::<_, EdwardsConfig>::check_schnorr_signatures(
+                &mut circuit,
+                &vk_vars,
+                &selector_vars[1..],
+                &msg_vars,
+                &sig_vars,
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+//! Plonk proving and verifying keys of the QC circuit, see [`crate::circuit::qc_circuit`].
+//! The keys are generated for a maximum stake table size `N` and are tagged with the shape of the
+//! circuit, so that keys of another size or another version of the circuit are rejected. As the
+//! tag of a key can be edited, the verifier also checks it against the verifying key itself. The
+//! expected shape can be computed once with [`qc_circuit_shape`] and pinned as a constant with
+//! [`QcCircuitShape::new`].
+
+use crate::circuit::qc_circuit::{QcCircuit, QcCircuitKey};
+use ark_ec::{
+    pairing::Pairing,
+    short_weierstrass::{Affine, SWCurveConfig},
+};
+use ark_std::{
+    format,
+    rand::{CryptoRng, RngCore},
+    vec,
+};
+use jf_plonk::{
+    errors::PlonkError,
+    proof_system::{
+        structs::{Proof, ProvingKey, VerifyingKey},
+        PlonkKzgSnark, UniversalSNARK,
+    },
+    transcript::StandardTranscript,
+};
+use jf_primitives::rescue::RescueParameter;
+use jf_relation::{
+    errors::CircuitError,
+    gadgets::{ecc::SWToTEConParam, EmulationConfig},
+    Arithmetization, Circuit, PlonkCircuit,
+};
+use jf_utils::canonical;
+use serde::{Deserialize, Serialize};
+
+/// Version of the QC circuit, to be bumped on any change of its constraints.
+pub const QC_CIRCUIT_VERSION: u32 = 2;
+
+/// The shape of a QC circuit, identifying the keys it is compatible with.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct QcCircuitShape {
+    /// Version of the circuit
+    pub version: u32,
+    /// Maximum stake table size
+    pub max_size: usize,
+    /// Number of public inputs
+    pub num_inputs: usize,
+    /// Size of the evaluation domain, i.e. the number of gates after padding
+    pub domain_size: usize,
+}
+
+impl QcCircuitShape {
+    /// The shape of the current version of the QC circuit, e.g. to pin the output of
+    /// [`qc_circuit_shape`] as a constant.
+    pub const fn new(max_size: usize, num_inputs: usize, domain_size: usize) -> Self {
+        Self {
+            version: QC_CIRCUIT_VERSION,
+            max_size,
+            num_inputs,
+            domain_size,
+        }
+    }
+
+    /// The shape of a finalized QC circuit.
+    fn of<F: RescueParameter>(
+        circuit: &PlonkCircuit<F>,
+        max_size: usize,
+    ) -> Result<Self, PlonkError> {
+        Ok(Self::new(
+            max_size,
+            circuit.num_inputs(),
+            circuit.eval_domain_size()?,
+        ))
+    }
+
+    /// Checks that `self` is the expected shape.
+    pub fn check(&self, expected: &Self) -> Result<(), PlonkError> {
+        if self != expected {
+            return Err(CircuitError::ParameterError(format!(
+                "circuit shape {:?} != expected shape {:?}",
+                self, expected
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a Plonk verifying key is of the shape `self`, i.e. that it has the same
+    /// number of public inputs and evaluation domain size.
+    pub fn check_verifying_key<P: Pairing>(&self, vk: &VerifyingKey<P>) -> Result<(), PlonkError> {
+        if vk.num_inputs != self.num_inputs || vk.domain_size != self.domain_size {
+            return Err(CircuitError::ParameterError(format!(
+                "verifying key with {} public inputs and domain size {} not of shape {:?}",
+                vk.num_inputs, vk.domain_size, self
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Proving key of the QC circuit.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(bound = "")]
+pub struct QcProvingKey<P: Pairing> {
+    /// Shape of the circuit
+    pub shape: QcCircuitShape,
+    /// The Plonk proving key
+    #[serde(with = "canonical")]
+    pub pk: ProvingKey<P>,
+}
+
+/// Verifying key of the QC circuit.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(bound = "")]
+pub struct QcVerifyingKey<P: Pairing> {
+    /// Shape of the circuit
+    pub shape: QcCircuitShape,
+    /// The Plonk verifying key
+    #[serde(with = "canonical")]
+    pub vk: VerifyingKey<P>,
+}
+
+/// Builds the finalized QC circuit of a stake table of size `max_size` with dummy witnesses.
+/// The constraints do not depend on the witnesses, so it has the shape of any QC circuit of
+/// this size.
+fn dummy_circuit<F, E, K>(max_size: usize, coef: E) -> Result<PlonkCircuit<F>, PlonkError>
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+    K: QcCircuitKey<F, E> + Default,
+{
+    let qc = QcCircuit::new(
+        vec![K::default(); max_size],
+        vec![F::zero(); max_size],
+        vec![false; max_size],
+        K::default(),
+        F::zero(),
+        coef,
+    );
+    let mut circuit = qc.build()?;
+    circuit.finalize_for_arithmetization()?;
+    Ok(circuit)
+}
+
+/// The shape of the QC circuit of a stake table of size `max_size`.
+/// * `coef` - the internal curve parameter of the stake keys.
+pub fn qc_circuit_shape<F, E, K>(max_size: usize, coef: E) -> Result<QcCircuitShape, PlonkError>
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+    K: QcCircuitKey<F, E> + Default,
+{
+    let circuit = dummy_circuit::<F, E, K>(max_size, coef)?;
+    QcCircuitShape::of(&circuit, max_size)
+}
+
+/// The degree of the universal SRS needed by the QC circuit of a stake table of size `max_size`.
+/// * `coef` - the internal curve parameter of the stake keys.
+pub fn qc_srs_size<F, E, K>(max_size: usize, coef: E) -> Result<usize, PlonkError>
+where
+    F: RescueParameter,
+    E: EmulationConfig<F>,
+    K: QcCircuitKey<F, E> + Default,
+{
+    Ok(dummy_circuit::<F, E, K>(max_size, coef)?.srs_size()?)
+}
+
+/// Generates the proving and verifying keys of the QC circuit.
+/// * `P` - the pairing curve of the proof system, whose scalar field is the circuit field
+/// * `B`, `C` - the base field and the G1 curve config of `P`
+/// * `E` - the field of the stake keys, emulated in the circuit
+/// * `K` - the stake key type
+/// * `srs` - the universal SRS, of degree at least `qc_srs_size(max_size, coef)`
+/// * `max_size` - the maximum stake table size
+/// * `coef` - the internal curve parameter of the stake keys
+pub fn qc_preprocess<P, B, C, E, K>(
+    srs: &<PlonkKzgSnark<P> as UniversalSNARK<P>>::UniversalSRS,
+    max_size: usize,
+    coef: E,
+) -> Result<(QcProvingKey<P>, QcVerifyingKey<P>), PlonkError>
+where
+    P: Pairing<BaseField = B, G1Affine = Affine<C>>,
+    B: RescueParameter + SWToTEConParam,
+    C: SWCurveConfig<BaseField = B>,
+    P::ScalarField: RescueParameter,
+    E: EmulationConfig<P::ScalarField>,
+    K: QcCircuitKey<P::ScalarField, E> + Default,
+{
+    let circuit = dummy_circuit::<P::ScalarField, E, K>(max_size, coef)?;
+    let shape = QcCircuitShape::of(&circuit, max_size)?;
+    let (pk, vk) = PlonkKzgSnark::<P>::preprocess(srs, &circuit)?;
+    Ok((QcProvingKey { shape, pk }, QcVerifyingKey { shape, vk }))
+}
+
+/// Proves a QC circuit.
+/// * `pk` - the proving key, of the shape of `qc`
+/// * `qc` - the QC circuit, with a stake table of the maximum size of `pk`
+pub fn qc_prove<P, B, C, E, K, R>(
+    rng: &mut R,
+    pk: &QcProvingKey<P>,
+    qc: &QcCircuit<P::ScalarField, E, K>,
+) -> Result<Proof<P>, PlonkError>
+where
+    P: Pairing<BaseField = B, G1Affine = Affine<C>>,
+    B: RescueParameter + SWToTEConParam,
+    C: SWCurveConfig<BaseField = B>,
+    P::ScalarField: RescueParameter,
+    E: EmulationConfig<P::ScalarField>,
+    K: QcCircuitKey<P::ScalarField, E>,
+    R: CryptoRng + RngCore,
+{
+    let mut circuit = qc.build()?;
+    circuit.finalize_for_arithmetization()?;
+    QcCircuitShape::of(&circuit, qc.stake_keys.len())?.check(&pk.shape)?;
+    PlonkKzgSnark::<P>::prove::<_, _, StandardTranscript>(rng, &circuit, &pk.pk, None)
+}
+
+/// Verifies a proof of a QC circuit.
+/// * `vk` - the verifying key, whose tag and Plonk key are checked against the expected shape
+/// * `shape` - the expected circuit shape, see [`qc_circuit_shape`] and [`QcCircuitShape::new`]
+/// * `public_inputs` - see `QcCircuit::public_inputs`
+/// * `proof` - the proof
+pub fn qc_verify<P, B, C>(
+    vk: &QcVerifyingKey<P>,
+    shape: &QcCircuitShape,
+    public_inputs: &[P::ScalarField],
+    proof: &Proof<P>,
+) -> Result<(), PlonkError>
+where
+    P: Pairing<BaseField = B, G1Affine = Affine<C>>,
+    B: RescueParameter + SWToTEConParam,
+    C: SWCurveConfig<BaseField = B>,
+{
+    vk.shape.check(shape)?;
+    shape.check_verifying_key(&vk.vk)?;
+    if public_inputs.len() != shape.num_inputs {
+        return Err(CircuitError::ParameterError(format!(
+            "the number of public inputs {} != {}",
+            public_inputs.len(),
+            shape.num_inputs
+        ))
+        .into());
+    }
+    PlonkKzgSnark::<P>::verify::<StandardTranscript>(&vk.vk, public_inputs, proof, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bn254::{g1::Config as Param254, Bn254, Fq as Fq254, Fr as Fr254};
+    use ark_ec::{short_weierstrass::Projective, CurveGroup};
+    use ark_std::{vec::Vec, UniformRand, Zero};
+    use jf_relation::gadgets::ecc::emulated::SWPoint;
+
+    type Key = SWPoint<Fq254>;
+
+    fn test_qc(size: usize) -> QcCircuit<Fr254, Fq254, Key> {
+        let mut rng = jf_utils::test_rng();
+        let points: Vec<Projective<Param254>> = (0..size)
+            .map(|_| Projective::<Param254>::rand(&mut rng))
+            .collect();
+        let agg_key = points
+            .iter()
+            .fold(Projective::<Param254>::zero(), |acc, p| acc + p);
+        QcCircuit::new(
+            points.iter().map(|p| p.into_affine().into()).collect(),
+            (1..=size as u64).map(Fr254::from).collect(),
+            vec![true; size],
+            agg_key.into_affine().into(),
+            Fr254::from(size as u64),
+            Fq254::zero(),
+        )
+    }
+
+    #[test]
+    fn test_qc_snark() -> Result<(), PlonkError> {
+        let mut rng = jf_utils::test_rng();
+        let max_size = 2;
+        let srs_size = qc_srs_size::<Fr254, Fq254, Key>(max_size, Fq254::zero())?;
+        let srs = PlonkKzgSnark::<Bn254>::universal_setup_for_testing(srs_size, &mut rng)?;
+        let (pk, vk) = qc_preprocess::<Bn254, _, _, Fq254, Key>(&srs, max_size, Fq254::zero())?;
+        let shape = qc_circuit_shape::<Fr254, Fq254, Key>(max_size, Fq254::zero())?;
+        assert_eq!(pk.shape, shape);
+        assert_eq!(vk.shape, shape);
+        assert_eq!(shape.version, QC_CIRCUIT_VERSION);
+        assert_eq!(shape.domain_size, vk.vk.domain_size);
+        // the shape can be pinned without building the circuit
+        let pinned_shape = QcCircuitShape::new(max_size, shape.num_inputs, shape.domain_size);
+        assert_eq!(pinned_shape, shape);
+        assert_eq!(
+            pk,
+            bincode::deserialize(&bincode::serialize(&pk).unwrap()).unwrap()
+        );
+        let vk: QcVerifyingKey<Bn254> =
+            bincode::deserialize(&bincode::serialize(&vk).unwrap()).unwrap();
+
+        // happy path
+        let qc = test_qc(max_size);
+        let public_inputs = qc.public_inputs();
+        assert_eq!(public_inputs.len(), shape.num_inputs);
+        let proof = qc_prove(&mut rng, &pk, &qc)?;
+        assert!(qc_verify(&vk, &shape, &public_inputs, &proof).is_ok());
+        assert!(qc_verify(&vk, &pinned_shape, &public_inputs, &proof).is_ok());
+
+        // bad path: wrong public inputs
+        let mut bad_inputs = public_inputs.clone();
+        bad_inputs[0] += Fr254::from(1u8);
+        assert!(qc_verify(&vk, &shape, &bad_inputs, &proof).is_err());
+        assert!(qc_verify(&vk, &shape, &public_inputs[1..], &proof).is_err());
+
+        // bad path: shape mismatches
+        // a stake table of another size
+        assert!(qc_prove(&mut rng, &pk, &test_qc(max_size + 1)).is_err());
+        // keys of another size or version
+        let other_shape = qc_circuit_shape::<Fr254, Fq254, Key>(max_size + 1, Fq254::zero())?;
+        assert_ne!(other_shape, shape);
+        assert!(qc_verify(&vk, &other_shape, &public_inputs, &proof).is_err());
+        let mut bad_vk = vk.clone();
+        bad_vk.shape.version += 1;
+        assert!(qc_verify(&bad_vk, &shape, &public_inputs, &proof).is_err());
+        let mut bad_pk = pk;
+        bad_pk.shape.max_size += 1;
+        assert!(qc_prove(&mut rng, &bad_pk, &qc).is_err());
+        // the key of another circuit tagged with the expected shape
+        let srs_size = qc_srs_size::<Fr254, Fq254, Key>(4 * max_size, Fq254::zero())?;
+        let srs = PlonkKzgSnark::<Bn254>::universal_setup_for_testing(srs_size, &mut rng)?;
+        let (_, mut other_vk) =
+            qc_preprocess::<Bn254, _, _, Fq254, Key>(&srs, 4 * max_size, Fq254::zero())?;
+        assert!(shape.check_verifying_key(&other_vk.vk).is_err());
+        other_vk.shape = shape;
+        assert!(qc_verify(&other_vk, &shape, &public_inputs, &proof).is_err());
+        let mut bad_vk = vk;
+        bad_vk.vk.num_inputs += 1;
+        assert!(qc_verify(&bad_vk, &shape, &public_inputs, &proof).is_err());
+
+        Ok(())
+    }
+}
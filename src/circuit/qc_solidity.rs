@@ -0,0 +1,402 @@
+//! EVM encoding of the QC SNARK, see [`crate::circuit::qc_snark`].
+//!
+//! Every value is encoded as a sequence of `uint256` words:
+//! * a field element of at most 256 bits as its big-endian canonical representation,
+//! * a point as its affine coordinates `(x, y)`, the point at infinity as zeros, and the
+//!   coordinates over a quadratic extension in the EIP-197 order `(c1, c0)`.
+//!
+//! Verifying keys, proofs and public inputs are static tuples of words, so their ABI encoding,
+//! i.e. the output of `abi.encode`, is the concatenation of their words.
+
+use crate::circuit::qc_snark::QcVerifyingKey;
+use ark_ec::{pairing::Pairing, AffineRepr};
+use ark_ff::{BigInteger, Field, PrimeField};
+use ark_std::{format, string::String, vec, vec::Vec};
+use ethereum_types::U256;
+use jf_plonk::{
+    errors::PlonkError,
+    proof_system::structs::{Proof, VerifyingKey},
+};
+use jf_relation::errors::CircuitError;
+
+/// Byte length of an EVM word.
+pub const WORD_SIZE: usize = 32;
+
+/// Converts a field element into a word.
+pub fn field_to_word<F: PrimeField>(f: &F) -> Result<U256, PlonkError> {
+    if F::MODULUS_BIT_SIZE as usize > 8 * WORD_SIZE {
+        return Err(CircuitError::ParameterError(format!(
+            "field elements of {} bits do not fit in a word",
+            F::MODULUS_BIT_SIZE
+        ))
+        .into());
+    }
+    Ok(U256::from_little_endian(&f.into_bigint().to_bytes_le()))
+}
+
+/// Converts an affine point into words, see the module documentation.
+pub fn point_to_words<A: AffineRepr>(p: &A) -> Result<Vec<U256>, PlonkError> {
+    match p.xy() {
+        Some((x, y)) => {
+            let mut words = coordinate_to_words(x)?;
+            words.extend(coordinate_to_words(y)?);
+            Ok(words)
+        }
+        None => Ok(vec![
+            U256::zero();
+            2 * A::BaseField::extension_degree() as usize
+        ]),
+    }
+}
+
+fn coordinate_to_words<F: Field>(c: &F) -> Result<Vec<U256>, PlonkError> {
+    let mut words = c
+        .to_base_prime_field_elements()
+        .map(|f| field_to_word(&f))
+        .collect::<Result<Vec<_>, _>>()?;
+    words.reverse();
+    Ok(words)
+}
+
+/// Concatenates words into their ABI encoding.
+pub fn words_to_bytes(words: &[U256]) -> Vec<u8> {
+    let mut bytes = vec![0u8; WORD_SIZE * words.len()];
+    for (word, chunk) in words.iter().zip(bytes.chunks_mut(WORD_SIZE)) {
+        word.to_big_endian(chunk);
+    }
+    bytes
+}
+
+/// Named words of a verifying key, in the order of its encoding.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct NamedWords(Vec<(String, U256)>);
+
+impl NamedWords {
+    fn push_usize(&mut self, name: &str, value: usize) {
+        self.0.push((String::from(name), U256::from(value)));
+    }
+
+    fn push_field<F: PrimeField>(&mut self, name: &str, f: &F) -> Result<(), PlonkError> {
+        self.0.push((String::from(name), field_to_word(f)?));
+        Ok(())
+    }
+
+    /// Pushes the coordinates of a point as `{name}_X`, `{name}_Y`, suffixed with the index of
+    /// the coefficient over an extension field.
+    fn push_point<A: AffineRepr>(&mut self, name: &str, p: &A) -> Result<(), PlonkError> {
+        let degree = A::BaseField::extension_degree() as usize;
+        let words = point_to_words(p)?;
+        for (i, word) in words.into_iter().enumerate() {
+            let coord = if i < degree { "X" } else { "Y" };
+            let name = if degree == 1 {
+                format!("{}_{}", name, coord)
+            } else {
+                format!("{}_{}_{}", name, coord, degree - 1 - i % degree)
+            };
+            self.0.push((name, word));
+        }
+        Ok(())
+    }
+
+    fn words(&self) -> Vec<U256> {
+        self.0.iter().map(|(_, word)| *word).collect()
+    }
+}
+
+fn verifying_key_named_words<P: Pairing>(vk: &VerifyingKey<P>) -> Result<NamedWords, PlonkError> {
+    let mut words = NamedWords::default();
+    words.push_usize("DOMAIN_SIZE", vk.domain_size);
+    words.push_usize("NUM_INPUTS", vk.num_inputs);
+    for (i, comm) in vk.sigma_comms.iter().enumerate() {
+        words.push_point(&format!("SIGMA_{}", i), &comm.0)?;
+    }
+    for (i, comm) in vk.selector_comms.iter().enumerate() {
+        words.push_point(&format!("SELECTOR_{}", i), &comm.0)?;
+    }
+    for (i, k) in vk.k.iter().enumerate() {
+        words.push_field(&format!("K_{}", i), k)?;
+    }
+    words.push_point("H", &vk.open_key.h)?;
+    words.push_point("BETA_H", &vk.open_key.beta_h)?;
+    if let Some(plookup_vk) = &vk.plookup_vk {
+        words.push_point("RANGE_TABLE", &plookup_vk.range_table_comm.0)?;
+        words.push_point("KEY_TABLE", &plookup_vk.key_table_comm.0)?;
+        words.push_point("TABLE_DOM_SEP", &plookup_vk.table_dom_sep_comm.0)?;
+        words.push_point("Q_DOM_SEP", &plookup_vk.q_dom_sep_comm.0)?;
+    }
+    Ok(words)
+}
+
+/// ABI encoding of a Plonk verifying key: the domain size, the number of public inputs, the
+/// sigma and selector commitments, the coset representatives `k`, the `h` and `beta_h` elements of
+/// the opening key and, for UltraPlonk, the lookup table commitments.
+pub fn encode_verifying_key<P: Pairing>(vk: &VerifyingKey<P>) -> Result<Vec<u8>, PlonkError> {
+    Ok(words_to_bytes(&verifying_key_named_words(vk)?.words()))
+}
+
+/// ABI encoding of a Plonk proof: the wire, permutation product, split quotient and opening
+/// commitments, then the polynomial evaluations and, for UltraPlonk, the lookup proof.
+pub fn encode_proof<P: Pairing>(proof: &Proof<P>) -> Result<Vec<u8>, PlonkError> {
+    let mut words = vec![];
+    for comm in proof.wires_poly_comms.iter() {
+        words.extend(point_to_words(&comm.0)?);
+    }
+    words.extend(point_to_words(&proof.prod_perm_poly_comm.0)?);
+    for comm in proof.split_quot_poly_comms.iter() {
+        words.extend(point_to_words(&comm.0)?);
+    }
+    words.extend(point_to_words(&proof.opening_proof.0)?);
+    words.extend(point_to_words(&proof.shifted_opening_proof.0)?);
+
+    let evals = &proof.poly_evals;
+    for eval in evals
+        .wires_evals
+        .iter()
+        .chain(evals.wire_sigma_evals.iter())
+    {
+        words.push(field_to_word(eval)?);
+    }
+    words.push(field_to_word(&evals.perm_next_eval)?);
+
+    if let Some(plookup_proof) = &proof.plookup_proof {
+        for comm in plookup_proof.h_poly_comms.iter() {
+            words.extend(point_to_words(&comm.0)?);
+        }
+        words.extend(point_to_words(&plookup_proof.prod_lookup_poly_comm.0)?);
+        let evals = &plookup_proof.poly_evals;
+        for eval in [
+            &evals.range_table_eval,
+            &evals.key_table_eval,
+            &evals.table_dom_sep_eval,
+            &evals.q_dom_sep_eval,
+            &evals.h_1_eval,
+            &evals.q_lookup_eval,
+            &evals.prod_next_eval,
+            &evals.range_table_next_eval,
+            &evals.key_table_next_eval,
+            &evals.table_dom_sep_next_eval,
+            &evals.h_1_next_eval,
+            &evals.h_2_next_eval,
+            &evals.q_lookup_next_eval,
+            &evals.w_3_next_eval,
+            &evals.w_4_next_eval,
+        ] {
+            words.push(field_to_word(eval)?);
+        }
+    }
+    Ok(words_to_bytes(&words))
+}
+
+/// ABI encoding of the public inputs of the QC circuit, in the order of the circuit, see
+/// [`crate::circuit::qc_circuit`].
+/// * `stake_table_digest` - the stake table digest.
+/// * `agg_key_limbs` - the native limbs of the aggregated key of the signers.
+/// * `threshold` - the quorum threshold.
+pub fn encode_qc_public_inputs<F: PrimeField>(
+    stake_table_digest: &F,
+    agg_key_limbs: &[F],
+    threshold: &F,
+) -> Result<Vec<u8>, PlonkError> {
+    let words = [stake_table_digest]
+        .into_iter()
+        .chain(agg_key_limbs.iter())
+        .chain([threshold])
+        .map(field_to_word)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(words_to_bytes(&words))
+}
+
+/// Generates a Solidity library holding the verifying key of the QC circuit as constants, named
+/// after the words of [`encode_verifying_key`], together with the version and the maximum stake
+/// table size of the circuit.
+/// * `library` - the name of the library.
+/// * `vk` - the verifying key of the QC circuit.
+pub fn solidity_verifier_constants<P: Pairing>(
+    library: &str,
+    vk: &QcVerifyingKey<P>,
+) -> Result<String, PlonkError> {
+    let mut words = NamedWords::default();
+    words.push_usize("QC_CIRCUIT_VERSION", vk.shape.version as usize);
+    words.push_usize("MAX_STAKE_TABLE_SIZE", vk.shape.max_size);
+    words.0.extend(verifying_key_named_words(&vk.vk)?.0);
+    render_solidity_library(library, &words)
+}
+
+fn render_solidity_library(library: &str, words: &NamedWords) -> Result<String, PlonkError> {
+    let is_identifier = library.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && library
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(CircuitError::ParameterError(format!(
+            "{:?} is not a Solidity identifier",
+            library
+        ))
+        .into());
+    }
+    let mut source = String::from(
+        "// SPDX-License-Identifier: MIT\n\
+         // Generated by hotshot-primitives, do not edit.\n\
+         \n\
+         pragma solidity ^0.8.0;\n\
+         \n",
+    );
+    source += &format!("library {} {{\n", library);
+    for (name, word) in words.0.iter() {
+        source += &format!(
+            "    uint256 internal constant {} =\n        {};\n",
+            name,
+            word_to_hex(word)
+        );
+    }
+    source += "}\n";
+    Ok(source)
+}
+
+fn word_to_hex(word: &U256) -> String {
+    words_to_bytes(&[*word])
+        .iter()
+        .fold(String::from("0x"), |acc, b| acc + &format!("{:02x}", b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{
+        qc_circuit::QcCircuit,
+        qc_snark::{qc_preprocess, qc_prove, qc_srs_size},
+    };
+    use ark_bn254::{g1::Config as Param254, Bn254, Fq as Fq254, Fr as Fr254, G1Affine, G2Affine};
+    use ark_ec::{short_weierstrass::Projective, CurveGroup};
+    use ark_std::{UniformRand, Zero};
+    use jf_plonk::proof_system::{PlonkKzgSnark, UniversalSNARK};
+    use jf_relation::gadgets::{ecc::emulated::SWPoint, SerializableEmulatedStruct};
+
+    const GOLDEN_WORDS: &str = include_str!("../../testdata/solidity/words.txt");
+    const GOLDEN_PUBLIC_INPUTS: &str = include_str!("../../testdata/solidity/public_inputs.txt");
+    const GOLDEN_LIBRARY: &str = include_str!("../../testdata/solidity/TestVerifyingKey.sol");
+
+    fn to_hex_lines(bytes: &[u8]) -> String {
+        bytes
+            .chunks(WORD_SIZE)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_golden_encodings() -> Result<(), PlonkError> {
+        let mut words = vec![field_to_word(&Fr254::from(1u8))?];
+        words.push(field_to_word(&-Fr254::from(1u8))?);
+        words.extend(point_to_words(&G1Affine::generator())?);
+        words.extend(point_to_words(&G1Affine::zero())?);
+        words.extend(point_to_words(&G2Affine::generator())?);
+        words.extend(point_to_words(&G2Affine::zero())?);
+        assert_eq!(to_hex_lines(&words_to_bytes(&words)), GOLDEN_WORDS);
+
+        let public_inputs = encode_qc_public_inputs(
+            &Fr254::from(0xabcdu64),
+            &[Fr254::from(1u8), Fr254::from(u64::MAX), -Fr254::from(2u8)],
+            &Fr254::from(100u8),
+        )?;
+        assert_eq!(to_hex_lines(&public_inputs), GOLDEN_PUBLIC_INPUTS);
+
+        let mut words = NamedWords::default();
+        words.push_usize("DOMAIN_SIZE", 8);
+        words.push_usize("NUM_INPUTS", 5);
+        words.push_point("SIGMA_0", &G1Affine::generator())?;
+        words.push_field("K_0", &Fr254::from(1u8))?;
+        words.push_point("H", &G2Affine::generator())?;
+        assert_eq!(
+            render_solidity_library("TestVerifyingKey", &words)?,
+            GOLDEN_LIBRARY
+        );
+        assert!(render_solidity_library("0Test", &words).is_err());
+        assert!(render_solidity_library("Test Key", &words).is_err());
+        assert!(render_solidity_library("", &words).is_err());
+
+        // fields wider than a word
+        assert!(field_to_word(&ark_bls12_381::Fq::from(1u8)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_qc_snark_encoding() -> Result<(), PlonkError> {
+        let mut rng = jf_utils::test_rng();
+        let max_size = 2;
+        let srs_size = qc_srs_size::<Fr254, Fq254, SWPoint<Fq254>>(max_size, Fq254::zero())?;
+        let srs = PlonkKzgSnark::<Bn254>::universal_setup_for_testing(srs_size, &mut rng)?;
+        let (pk, vk) =
+            qc_preprocess::<Bn254, _, _, Fq254, SWPoint<Fq254>>(&srs, max_size, Fq254::zero())?;
+
+        let points: Vec<Projective<Param254>> = (0..max_size)
+            .map(|_| Projective::<Param254>::rand(&mut rng))
+            .collect();
+        let agg_key: SWPoint<Fq254> = points
+            .iter()
+            .fold(Projective::<Param254>::zero(), |acc, p| acc + p)
+            .into_affine()
+            .into();
+        let qc = QcCircuit::new(
+            points.iter().map(|p| p.into_affine().into()).collect(),
+            vec![Fr254::from(1u8); max_size],
+            vec![true; max_size],
+            agg_key,
+            Fr254::from(max_size as u64),
+            Fq254::zero(),
+        );
+        let proof = qc_prove(&mut rng, &pk, &qc)?;
+
+        // verifying key
+        let named_words = verifying_key_named_words(&vk.vk)?;
+        let encoded_vk = encode_verifying_key(&vk.vk)?;
+        assert_eq!(encoded_vk, words_to_bytes(&named_words.words()));
+        assert_eq!(named_words.0[0].1, U256::from(vk.vk.domain_size));
+        assert_eq!(named_words.0[1].1, U256::from(vk.shape.num_inputs));
+        let sigma_0 = vk.vk.sigma_comms[0].0;
+        assert_eq!(
+            &encoded_vk[2 * WORD_SIZE..4 * WORD_SIZE],
+            &words_to_bytes(&point_to_words(&sigma_0)?)[..]
+        );
+        let library = solidity_verifier_constants("QcVerifyingKey", &vk)?;
+        assert!(library.contains("library QcVerifyingKey {"));
+        for (name, word) in named_words.0.iter() {
+            assert!(library.contains(&format!(
+                "uint256 internal constant {} =\n        {};",
+                name,
+                word_to_hex(word)
+            )));
+        }
+        assert!(library.contains("QC_CIRCUIT_VERSION"));
+        assert!(library.contains("MAX_STAKE_TABLE_SIZE"));
+
+        // proof
+        let encoded_proof = encode_proof(&proof)?;
+        assert_eq!(encoded_proof.len() % WORD_SIZE, 0);
+        assert_eq!(
+            &encoded_proof[..2 * WORD_SIZE],
+            &words_to_bytes(&point_to_words(&proof.wires_poly_comms[0].0)?)[..]
+        );
+
+        // public inputs
+        let public_inputs = qc.public_inputs();
+        let encoded_inputs = encode_qc_public_inputs(
+            &qc.public_inputs()[0],
+            &qc.agg_key.serialize_to_native_elements(),
+            &qc.threshold,
+        )?;
+        let words = public_inputs
+            .iter()
+            .map(field_to_word)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(encoded_inputs, words_to_bytes(&words));
+        assert_eq!(encoded_inputs.len(), WORD_SIZE * vk.shape.num_inputs);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+//! Witness generation for the stake table of the QC circuits.
+//!
+//! A [`StakeTableWitness`] is built from a version of a [`StakeTable`] or from the entries of
+//! [`QCParams`], and holds both the stake keys and amounts allocated in the circuits and the
+//! native digest they are checked against, see [`compute_stake_table_hash`] and
+//! `QCKeyAggregateGadget::check_stake_table_digest`. The stake amounts are converted with
+//! checked bounds, see [`stake_amount_to_field`]: an amount of more than `STAKE_AMOUNT_BIT_LEN`
+//! bits, which `check_threshold` would reject, is an error instead of being reduced modulo the
+//! field.
+//!
+//! The witness of a version of a stake table comes with the Merkle commitment of that version,
+//! against which every entry is checked, so that the digest checked by the QC circuits and the
+//! commitment of the stake table describe the same entries in the same order.
+
+use crate::{
+    circuit::{qc_circuit::QcCircuitKey, qc_keyagg::pad_stake_table},
+    qc::{
+        bit_vector::QCParams,
+        digest::{compute_stake_table_hash, stake_amount_to_field},
+        keys::StakeKey,
+        stake::StakeAmount,
+    },
+    stake_table::{EncodedPublicKey, MerkleCommitment, STVersion, StakeTable},
+};
+use ark_std::{format, vec::Vec};
+use jf_primitives::rescue::RescueParameter;
+use jf_relation::{
+    errors::CircuitError,
+    gadgets::{EmulationConfig, SerializableEmulatedStruct},
+    Circuit, PlonkCircuit, Variable,
+};
+
+/// The stake table witnesses of the QC circuits, together with their native digest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StakeTableWitness<F, K> {
+    /// Stake keys
+    pub stake_keys: Vec<K>,
+    /// Stake amounts
+    pub stake_amts: Vec<F>,
+    /// Digest of the stake table, see [`compute_stake_table_hash`]
+    pub digest: F,
+}
+
+/// The variables allocated by [`StakeTableWitness::create_variables`].
+#[derive(Clone, Debug)]
+pub struct StakeTableVars<V> {
+    /// Stake keys
+    pub stake_keys: Vec<V>,
+    /// Stake amounts
+    pub stake_amts: Vec<Variable>,
+}
+
+impl<F, K> StakeTableWitness<F, K>
+where
+    F: RescueParameter,
+    K: SerializableEmulatedStruct<F>,
+{
+    /// Creates the witnesses of a list of stake table entries.
+    /// * `entries` - the stake keys and their stake amounts.
+    pub fn new<S: StakeAmount>(
+        entries: impl IntoIterator<Item = (K, S)>,
+    ) -> Result<Self, CircuitError> {
+        let mut stake_keys = Vec::new();
+        let mut stake_amts = Vec::new();
+        for (key, amount) in entries {
+            stake_keys.push(key);
+            stake_amts.push(
+                stake_amount_to_field(&amount)
+                    .map_err(|e| CircuitError::ParameterError(format!("{}", e)))?,
+            );
+        }
+        let digest = compute_stake_table_hash(&stake_amts, &stake_keys);
+        Ok(Self {
+            stake_keys,
+            stake_amts,
+            digest,
+        })
+    }
+
+    /// Creates the witnesses of the stake table of QC parameters.
+    /// * `params` - the QC parameters, whose entries hold keys of type `K`, see [`StakeKey`].
+    pub fn from_qc_params<V, P, S>(params: &QCParams<V, P, S>) -> Result<Self, CircuitError>
+    where
+        K: Clone,
+        V: StakeKey<K>,
+        S: StakeAmount,
+    {
+        Self::new(
+            params
+                .stake_entries()
+                .iter()
+                .map(|entry| (entry.key::<K>().clone(), entry.stake_amount)),
+        )
+    }
+
+    /// Creates the witnesses of a version of a stake table, in the order of registration.
+    /// * `stake_table` - the stake table.
+    /// * `version` - the version of the stake table.
+    /// * `decode` - maps the encoded keys of the stake table to the stake keys.
+    /// * `returns` - an error if an entry does not verify against the commitment of `version`,
+    ///     the witnesses and this commitment otherwise.
+    pub fn from_stake_table<D>(
+        stake_table: &StakeTable,
+        version: STVersion,
+        decode: D,
+    ) -> Result<(Self, MerkleCommitment), CircuitError>
+    where
+        D: Fn(&EncodedPublicKey) -> Result<K, CircuitError>,
+    {
+        let comm = stake_table.commitment(version);
+        let entries = stake_table
+            .entries(version)
+            .map_err(|e| CircuitError::ParameterError(format!("{}", e)))?;
+        if entries.len() != comm.size() {
+            return Err(CircuitError::ParameterError(format!(
+                "{} stake table entries but a commitment of size {}",
+                entries.len(),
+                comm.size()
+            )));
+        }
+        let entries = entries
+            .iter()
+            .enumerate()
+            .map(|(index, (key, amount))| {
+                // the entry is at its index in the committed tree
+                let proof = stake_table
+                    .lookup(version, key)
+                    .and_then(|proof| proof.verify(&comm).map(|_| proof))
+                    .map_err(|e| CircuitError::ParameterError(format!("{}", e)))?;
+                if *proof.index() != index || proof.get_key_value() != Some((key, amount)) {
+                    return Err(CircuitError::ParameterError(format!(
+                        "stake table entry {} does not match the commitment",
+                        index
+                    )));
+                }
+                Ok((decode(key)?, *amount))
+            })
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        Ok((Self::new(entries)?, comm))
+    }
+
+    /// Pads the stake table to a fixed capacity, see `pad_stake_table`.
+    /// * `capacity` - the size of the padded stake table.
+    pub fn pad(&self, capacity: usize) -> Result<Self, CircuitError>
+    where
+        K: Default + Clone,
+    {
+        let (stake_amts, stake_keys) =
+            pad_stake_table(&self.stake_amts, &self.stake_keys, capacity)?;
+        let digest = compute_stake_table_hash(&stake_amts, &stake_keys);
+        Ok(Self {
+            stake_keys,
+            stake_amts,
+            digest,
+        })
+    }
+
+    /// Allocates the stake keys and amounts as witnesses in `circuit`.
+    pub fn create_variables<E>(
+        &self,
+        circuit: &mut PlonkCircuit<F>,
+    ) -> Result<StakeTableVars<K::Var>, CircuitError>
+    where
+        E: EmulationConfig<F>,
+        K: QcCircuitKey<F, E>,
+    {
+        let stake_keys = self
+            .stake_keys
+            .iter()
+            .map(|key| key.create_variable(circuit))
+            .collect::<Result<Vec<_>, _>>()?;
+        let stake_amts = self
+            .stake_amts
+            .iter()
+            .map(|&amt| circuit.create_variable(amt))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(StakeTableVars {
+            stake_keys,
+            stake_amts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        circuit::qc_keyagg::{compute_padded_stake_table_hash, QCKeyAggregateGadget},
+        qc::{bit_vector::StakeTableEntry, digest::STAKE_AMOUNT_BIT_LEN, policy::ThresholdPolicy},
+    };
+    use ark_bls12_377::{g1::Config as Param377, Fq as Fq377};
+    use ark_bn254::{g1::Config as Param254, Fq as Fq254, Fr as Fr254};
+    use ark_ec::{
+        short_weierstrass::{Projective, SWCurveConfig},
+        CurveGroup,
+    };
+    use ark_std::{collections::HashMap, rand::Rng, vec, UniformRand};
+    use ethereum_types::U256;
+    use jf_relation::gadgets::ecc::emulated::SWPoint;
+    use jf_utils::to_bytes;
+
+    #[test]
+    fn test_stake_table_witness_parity() -> Result<(), CircuitError> {
+        test_stake_table_witness_parity_helper::<Fq254, Param254>()?;
+        test_stake_table_witness_parity_helper::<Fq377, Param377>()
+    }
+
+    fn test_stake_table_witness_parity_helper<E, P>() -> Result<(), CircuitError>
+    where
+        E: EmulationConfig<Fr254>,
+        P: SWCurveConfig<BaseField = E>,
+    {
+        let mut rng = jf_utils::test_rng();
+        for _ in 0..4 {
+            let size = rng.gen_range(1..10);
+            let entries: Vec<(SWPoint<E>, U256)> = (0..size)
+                .map(|_| {
+                    let key = Projective::<P>::rand(&mut rng).into_affine().into();
+                    (key, U256::from(rng.gen::<u128>()))
+                })
+                .collect();
+
+            // from QC parameters
+            let params = QCParams::new(
+                entries
+                    .iter()
+                    .map(|(key, amount)| StakeTableEntry {
+                        stake_key: *key,
+                        stake_amount: *amount,
+                    })
+                    .collect(),
+                ThresholdPolicy::Absolute(U256::one()),
+                (),
+            )
+            .unwrap();
+            let witness = StakeTableWitness::<Fr254, SWPoint<E>>::from_qc_params(&params)?;
+            assert_eq!(witness, StakeTableWitness::new(entries.clone())?);
+            assert_eq!(
+                witness.digest,
+                compute_stake_table_hash(&witness.stake_amts, &witness.stake_keys)
+            );
+
+            // from a stake table
+            let mut stake_table = StakeTable::new(3);
+            let mut keys = HashMap::new();
+            for (i, (key, amount)) in entries.iter().enumerate() {
+                let encoded_key = EncodedPublicKey(to_bytes!(&Fr254::from(i as u64)).unwrap());
+                stake_table.register(&encoded_key, *amount).unwrap();
+                keys.insert(encoded_key, *key);
+            }
+            let decode = |key: &EncodedPublicKey| {
+                keys.get(key)
+                    .copied()
+                    .ok_or_else(|| CircuitError::ParameterError(format!("unknown key {:?}", key)))
+            };
+            let (pending_witness, pending_comm) =
+                StakeTableWitness::from_stake_table(&stake_table, STVersion::PENDING, decode)?;
+            assert_eq!(pending_witness, witness);
+            assert_eq!(pending_comm, stake_table.commitment(STVersion::PENDING));
+            assert_eq!(pending_comm.size(), size);
+            // the stake amounts of the witness are the ones in the committed tree
+            for (i, (encoded_key, amount)) in stake_table
+                .entries(STVersion::PENDING)
+                .unwrap()
+                .iter()
+                .enumerate()
+            {
+                let proof = stake_table.lookup(STVersion::PENDING, encoded_key).unwrap();
+                assert!(proof.verify(&pending_comm).is_ok());
+                assert_eq!(*proof.index(), i);
+                assert_eq!(
+                    witness.stake_amts[i],
+                    stake_amount_to_field::<Fr254, _>(proof.get_value().unwrap()).unwrap()
+                );
+                assert_eq!(
+                    witness.stake_amts[i],
+                    stake_amount_to_field::<Fr254, _>(amount).unwrap()
+                );
+            }
+            stake_table.advance();
+            let (new_key, _) = entries[0];
+            let encoded_key = EncodedPublicKey(to_bytes!(&Fr254::from(size as u64)).unwrap());
+            stake_table.register(&encoded_key, U256::from(1u8)).unwrap();
+            keys.insert(encoded_key, new_key);
+            let decode = |key: &EncodedPublicKey| {
+                keys.get(key)
+                    .copied()
+                    .ok_or_else(|| CircuitError::ParameterError(format!("unknown key {:?}", key)))
+            };
+            // the frozen version is the former pending one, with the same commitment
+            assert_eq!(
+                StakeTableWitness::from_stake_table(&stake_table, STVersion::FROZEN, decode)?,
+                (witness.clone(), pending_comm)
+            );
+            let (new_witness, new_comm) =
+                StakeTableWitness::from_stake_table(&stake_table, STVersion::PENDING, decode)?;
+            assert_ne!(new_witness.digest, witness.digest);
+            assert_ne!(new_comm, pending_comm);
+            assert_eq!(new_comm, stake_table.commitment(STVersion::PENDING));
+            assert_eq!(new_comm.size(), size + 1);
+            assert!(StakeTableWitness::<Fr254, SWPoint<E>>::from_stake_table(
+                &stake_table,
+                STVersion::PENDING,
+                |_| Err(CircuitError::ParameterError("undecodable key".into()))
+            )
+            .is_err());
+
+            // the native digest is the one checked in the circuit
+            let padded_witness = witness.pad(size + 2)?;
+            assert_eq!(
+                padded_witness.digest,
+                compute_padded_stake_table_hash(
+                    &witness.stake_amts,
+                    &witness.stake_keys,
+                    size + 2
+                )?
+            );
+            for witness in [witness, padded_witness] {
+                let mut circuit = PlonkCircuit::<Fr254>::new_turbo_plonk();
+                let digest_var = circuit.create_public_variable(witness.digest)?;
+                let vars = witness.create_variables::<E>(&mut circuit)?;
+                circuit.check_stake_table_digest(&vars.stake_keys, &vars.stake_amts, digest_var)?;
+                assert!(circuit
+                    .check_circuit_satisfiability(&[witness.digest])
+                    .is_ok());
+                assert!(circuit
+                    .check_circuit_satisfiability(&[witness.digest + Fr254::from(1u8)])
+                    .is_err());
+            }
+        }
+
+        // bad path: stake amounts out of bounds
+        let key = Projective::<P>::rand(&mut rng).into_affine().into();
+        assert!(StakeTableWitness::<Fr254, SWPoint<E>>::new(vec![(
+            key,
+            U256::one() << STAKE_AMOUNT_BIT_LEN
+        )])
+        .is_err());
+        Ok(())
+    }
+}
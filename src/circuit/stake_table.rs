@@ -0,0 +1,1192 @@
+//! Circuit implementation of the stake table Merkle tree, see [`crate::stake_table`].
+//! The tree is 3-ary, each node is hashed with the fixed length Rescue hash of rate 3. A leaf is
+//! hashed as `H(0, key, value)` where `key` is the field element encoded in `EncodedPublicKey`.
+//! Empty subtrees have a zero commitment.
+//!
+//! The state transition circuit also tracks the number of registered keys and a key index, a
+//! Merkle tree of the same shape committing to the registered keys as a sorted list, so that a
+//! registration is proven to be at the next free position and of a key not registered yet.
+
+use crate::{
+    circuit::qc_keyagg::STAKE_AMOUNT_BIT_LEN,
+    stake_table::{
+        config::{u256_to_field, Digest, FieldType, TREE_BRANCH},
+        error::StakeTableError,
+        EncodedPublicKey, MerkleCommitment, MerklePathEntry, MerkleProof, STVersion, StakeTable,
+    },
+};
+use ark_ff::PrimeField;
+use ark_serialize::CanonicalDeserialize;
+use ark_std::{collections::BTreeMap, format, vec, vec::Vec, Zero};
+use ethereum_types::U256;
+use jf_primitives::{circuit::rescue::RescueNativeGadget, crhf::CRHF, rescue::RescueParameter};
+use jf_relation::{errors::CircuitError, BoolVar, Circuit, PlonkCircuit, Variable};
+
+/// Witness of a stake table Merkle proof, from the leaf to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProofWitness<F> {
+    /// The leaf key
+    pub key: F,
+    /// The leaf value, i.e. the stake amount
+    pub value: F,
+    /// The branch taken at each level, from the bottom up, each less than `TREE_BRANCH`
+    pub positions: Vec<usize>,
+    /// The siblings at each level, from the bottom up
+    pub siblings: Vec<[F; TREE_BRANCH - 1]>,
+}
+
+impl MerkleProofWitness<FieldType> {
+    /// Builds the witness of a native Merkle proof.
+    pub fn from_proof(proof: &MerkleProof) -> Result<Self, CircuitError> {
+        let (key, value) = match proof.path.first() {
+            Some(MerklePathEntry::Leaf { key, value }) => (key, value),
+            _ => {
+                return Err(CircuitError::ParameterError(
+                    "the Merkle path does not start with a leaf".into(),
+                ))
+            }
+        };
+        let key = FieldType::deserialize_compressed(&key.0[..]).map_err(|e| {
+            CircuitError::ParameterError(format!("the leaf key is not a field element: {}", e))
+        })?;
+        let mut positions = Vec::new();
+        let mut siblings = Vec::new();
+        for entry in proof.path.iter().skip(1) {
+            match entry {
+                MerklePathEntry::Branch {
+                    pos,
+                    siblings: entry_siblings,
+                } if *pos < TREE_BRANCH => {
+                    positions.push(*pos);
+                    siblings.push(*entry_siblings);
+                }
+                _ => {
+                    return Err(CircuitError::ParameterError(
+                        "malformed Merkle path entry".into(),
+                    ))
+                }
+            }
+        }
+        Ok(Self {
+            key,
+            value: u256_to_field(value),
+            positions,
+            siblings,
+        })
+    }
+}
+
+/// Circuit variable of a Merkle path node.
+#[derive(Debug, Clone)]
+pub struct MerkleNodeVar {
+    /// Whether the node is the left child
+    pub is_left: BoolVar,
+    /// Whether the node is the right child
+    pub is_right: BoolVar,
+    /// The siblings of the node, from left to right
+    pub siblings: [Variable; TREE_BRANCH - 1],
+}
+
+/// Circuit variable of a stake table Merkle proof.
+#[derive(Debug, Clone)]
+pub struct MerkleProofVar {
+    /// The leaf key
+    pub key: Variable,
+    /// The leaf value
+    pub value: Variable,
+    /// The path, from the bottom up
+    pub path: Vec<MerkleNodeVar>,
+}
+
+/// Plonk circuit gadget for the stake table Merkle tree.
+pub trait StakeTableMerkleGadget<F>
+where
+    F: RescueParameter,
+{
+    /// Allocates the witness of a Merkle proof.
+    /// * `proof` - the proof witness, see [`MerkleProofWitness::from_proof`].
+    fn create_merkle_proof_variable(
+        &mut self,
+        proof: &MerkleProofWitness<F>,
+    ) -> Result<MerkleProofVar, CircuitError>;
+
+    /// Computes the commitment of a Merkle node from a child commitment and its path node.
+    /// * `comm` - the commitment of the child.
+    /// * `node` - the position and the siblings of the child.
+    fn compute_merkle_node(
+        &mut self,
+        comm: Variable,
+        node: &MerkleNodeVar,
+    ) -> Result<Variable, CircuitError>;
+
+    /// Computes the Merkle root of a proof.
+    fn compute_merkle_root(&mut self, proof: &MerkleProofVar) -> Result<Variable, CircuitError>;
+
+    /// Merkle membership checking circuit
+    /// * `proof` - the Merkle proof.
+    /// * `root` - the digest of the stake table commitment.
+    fn enforce_merkle_proof(
+        &mut self,
+        proof: &MerkleProofVar,
+        root: Variable,
+    ) -> Result<(), CircuitError>;
+}
+
+impl<F> StakeTableMerkleGadget<F> for PlonkCircuit<F>
+where
+    F: RescueParameter,
+{
+    fn create_merkle_proof_variable(
+        &mut self,
+        proof: &MerkleProofWitness<F>,
+    ) -> Result<MerkleProofVar, CircuitError> {
+        if proof.positions.len() != proof.siblings.len() {
+            return Err(CircuitError::ParameterError(format!(
+                "the number of positions {} != the number of siblings {}",
+                proof.positions.len(),
+                proof.siblings.len(),
+            )));
+        }
+        let key = self.create_variable(proof.key)?;
+        let value = self.create_variable(proof.value)?;
+        let mut path = Vec::new();
+        for (&pos, siblings) in proof.positions.iter().zip(proof.siblings.iter()) {
+            if pos >= TREE_BRANCH {
+                return Err(CircuitError::ParameterError(format!(
+                    "position {} out of range",
+                    pos
+                )));
+            }
+            let is_left = self.create_boolean_variable(pos == 0)?;
+            let is_right = self.create_boolean_variable(pos == TREE_BRANCH - 1)?;
+            path.push(MerkleNodeVar {
+                is_left,
+                is_right,
+                siblings: [
+                    self.create_variable(siblings[0])?,
+                    self.create_variable(siblings[1])?,
+                ],
+            });
+        }
+        Ok(MerkleProofVar { key, value, path })
+    }
+
+    fn compute_merkle_node(
+        &mut self,
+        comm: Variable,
+        node: &MerkleNodeVar,
+    ) -> Result<Variable, CircuitError> {
+        // a node is not both the left and the right child
+        let both = self.logic_and(node.is_left, node.is_right)?;
+        self.enforce_false(both.into())?;
+        // left: [comm, s0, s1], middle: [s0, comm, s1], right: [s0, s1, comm]
+        let [s0, s1] = node.siblings;
+        let left = self.conditional_select(node.is_left, s0, comm)?;
+        let middle = self.conditional_select(node.is_right, comm, s1)?;
+        let middle = self.conditional_select(node.is_left, middle, s0)?;
+        let right = self.conditional_select(node.is_right, s1, comm)?;
+        Ok(RescueNativeGadget::<F>::rescue_sponge_no_padding(self, &[left, middle, right], 1)?[0])
+    }
+
+    fn compute_merkle_root(&mut self, proof: &MerkleProofVar) -> Result<Variable, CircuitError> {
+        let mut comm = compute_leaf(self, proof.key, proof.value)?;
+        for node in proof.path.iter() {
+            comm = self.compute_merkle_node(comm, node)?;
+        }
+        Ok(comm)
+    }
+
+    fn enforce_merkle_proof(
+        &mut self,
+        proof: &MerkleProofVar,
+        root: Variable,
+    ) -> Result<(), CircuitError> {
+        let computed_root = self.compute_merkle_root(proof)?;
+        self.enforce_equal(computed_root, root)
+    }
+}
+
+/// Commitment of a leaf.
+fn compute_leaf<F: RescueParameter>(
+    circuit: &mut PlonkCircuit<F>,
+    key: Variable,
+    value: Variable,
+) -> Result<Variable, CircuitError> {
+    let zero = circuit.zero();
+    Ok(RescueNativeGadget::<F>::rescue_sponge_no_padding(circuit, &[zero, key, value], 1)?[0])
+}
+
+/// The public input of a Merkle membership circuit of a tree of height `comm.tree_height()`.
+pub fn merkle_commitment_public_input(comm: &MerkleCommitment) -> FieldType {
+    *comm.digest()
+}
+
+/// An operation on the pending stake table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StakeTableOp {
+    /// See `StakeTable::register`
+    Register { key: EncodedPublicKey, value: U256 },
+    /// See `StakeTable::update`
+    Update {
+        key: EncodedPublicKey,
+        delta: U256,
+        negative: bool,
+    },
+    /// See `StakeTable::set_value`
+    SetValue { key: EncodedPublicKey, value: U256 },
+}
+
+/// Witness of the update of the key index by a registration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyIndexWitness<F> {
+    /// The registered key preceding the new one in the sorted list, its successor and its path in
+    /// the key index before the registration. Unused if there is no registered key yet.
+    pub low_leaf: MerkleProofWitness<F>,
+    /// The siblings of the new key in the key index, after updating the low leaf, from the
+    /// bottom up
+    pub siblings: Vec<[F; TREE_BRANCH - 1]>,
+}
+
+impl<F: Zero + Copy> KeyIndexWitness<F> {
+    /// The witness of an operation that is not a registration, or of the first registration.
+    fn dummy(height: usize) -> Self {
+        Self {
+            low_leaf: MerkleProofWitness {
+                key: F::zero(),
+                value: F::zero(),
+                positions: vec![0; height],
+                siblings: vec![[F::zero(); TREE_BRANCH - 1]; height],
+            },
+            siblings: vec![[F::zero(); TREE_BRANCH - 1]; height],
+        }
+    }
+}
+
+/// Witness of a stake table operation.
+/// The old and the new leaf share the Merkle path, as the operation only changes this leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StakeTableOpWitness<F> {
+    /// Whether the leaf is empty before the operation, i.e. the key is registered by it
+    pub is_register: bool,
+    /// The key, the old value (zero if registered by the operation) and the path of the leaf
+    pub proof: MerkleProofWitness<F>,
+    /// The new value of the leaf
+    pub new_value: F,
+    /// The update of the key index, a dummy one if the key is not registered by the operation
+    pub key_index: KeyIndexWitness<F>,
+}
+
+/// The public state of the pending stake table in the state transition circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StakeTableState<F> {
+    /// Digest of the stake table
+    pub root: F,
+    /// Digest of the key index
+    pub key_root: F,
+    /// Number of registered keys, i.e. the position of the next registration
+    pub num_keys: usize,
+}
+
+impl<F: PrimeField> StakeTableState<F> {
+    /// The public input of the state in the circuit, see
+    /// [`StakeTableTransitionGadget::create_stake_table_state_variable`].
+    pub fn public_input(&self) -> Vec<F> {
+        vec![self.root, self.key_root, F::from(self.num_keys as u64)]
+    }
+}
+
+/// Returns the state of the pending stake table.
+pub fn stake_table_state(st: &StakeTable) -> Result<StakeTableState<FieldType>, StakeTableError> {
+    Ok(StakeTableState {
+        root: *st.commitment(STVersion::PENDING).digest(),
+        key_root: KeyIndex::from_stake_table(st)?.root(),
+        num_keys: st.num_keys(STVersion::PENDING),
+    })
+}
+
+/// The registered keys of the pending stake table as a sorted circular list, committed in a
+/// Merkle tree of the same shape as the stake table. The leaf at the position of a key is
+/// `H(0, key, next)` where `next` is the next larger registered key, or the smallest one for the
+/// largest key. A node whose children are all empty is empty, with a zero commitment.
+struct KeyIndex {
+    /// Height of the tree
+    height: usize,
+    /// The registered keys in increasing order, with their position
+    sorted_keys: BTreeMap<FieldType, usize>,
+    /// The keys and their successors, in the order of registration
+    leaves: Vec<(FieldType, FieldType)>,
+    /// The commitments of the nodes of each level, from the leaves up. The keys are registered at
+    /// consecutive positions, so only a prefix of each level is not empty.
+    nodes: Vec<Vec<FieldType>>,
+}
+
+impl KeyIndex {
+    /// Builds the key index of the pending stake table.
+    fn from_stake_table(st: &StakeTable) -> Result<Self, StakeTableError> {
+        let keys = st
+            .entries(STVersion::PENDING)?
+            .iter()
+            .map(|(key, _)| {
+                FieldType::deserialize_compressed(&key.0[..])
+                    .map_err(|_| StakeTableError::MalformedKey)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let sorted_keys: BTreeMap<_, _> =
+            keys.iter().enumerate().map(|(i, key)| (*key, i)).collect();
+        let sorted: Vec<_> = sorted_keys.iter().collect();
+        let mut leaves = vec![(FieldType::zero(), FieldType::zero()); keys.len()];
+        for (i, (key, index)) in sorted.iter().enumerate() {
+            let (next, _) = sorted[(i + 1) % sorted.len()];
+            leaves[**index] = (**key, *next);
+        }
+
+        let height = st.commitment(STVersion::PENDING).tree_height();
+        let mut nodes = Vec::new();
+        let mut level = leaves
+            .iter()
+            .map(|(key, next)| hash_key_leaf(*key, *next))
+            .collect::<Result<Vec<_>, _>>()?;
+        for _ in 0..height {
+            let parents = level
+                .chunks(TREE_BRANCH)
+                .map(hash_key_node)
+                .collect::<Result<Vec<_>, _>>()?;
+            nodes.push(level);
+            level = parents;
+        }
+        nodes.push(level);
+        Ok(Self {
+            height,
+            sorted_keys,
+            leaves,
+            nodes,
+        })
+    }
+
+    /// The commitment of the `index`-th node of `level`.
+    fn node(&self, level: usize, index: usize) -> FieldType {
+        self.nodes[level]
+            .get(index)
+            .copied()
+            .unwrap_or_else(FieldType::zero)
+    }
+
+    /// The digest of the key index.
+    fn root(&self) -> FieldType {
+        self.node(self.height, 0)
+    }
+
+    /// The branches and the siblings of the path of the `index`-th leaf, from the bottom up.
+    fn path(&self, index: usize) -> (Vec<usize>, Vec<[FieldType; TREE_BRANCH - 1]>) {
+        let mut positions = Vec::new();
+        let mut siblings = Vec::new();
+        let mut index = index;
+        for level in 0..self.height {
+            let pos = index % TREE_BRANCH;
+            let first = index - pos;
+            let mut level_siblings = [FieldType::zero(); TREE_BRANCH - 1];
+            for (sibling, child) in level_siblings
+                .iter_mut()
+                .zip((first..first + TREE_BRANCH).filter(|child| *child != index))
+            {
+                *sibling = self.node(level, child);
+            }
+            positions.push(pos);
+            siblings.push(level_siblings);
+            index /= TREE_BRANCH;
+        }
+        (positions, siblings)
+    }
+
+    /// Sets the `index`-th leaf, which is either registered or the next free one.
+    fn set_leaf(
+        &mut self,
+        index: usize,
+        key: FieldType,
+        next: FieldType,
+    ) -> Result<(), StakeTableError> {
+        if index == self.leaves.len() {
+            self.leaves.push((key, next));
+        } else {
+            self.leaves[index] = (key, next);
+        }
+        let mut index = index;
+        let mut comm = hash_key_leaf(key, next)?;
+        for level in 0..=self.height {
+            if index == self.nodes[level].len() {
+                self.nodes[level].push(comm);
+            } else {
+                self.nodes[level][index] = comm;
+            }
+            if level == self.height {
+                break;
+            }
+            index /= TREE_BRANCH;
+            let children: Vec<_> = (index * TREE_BRANCH..(index + 1) * TREE_BRANCH)
+                .map(|child| self.node(level, child))
+                .collect();
+            comm = hash_key_node(&children)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts `key` in the sorted list at the next free position, and returns the witness of
+    /// the update.
+    fn register(&mut self, key: FieldType) -> Result<KeyIndexWitness<FieldType>, StakeTableError> {
+        if self.sorted_keys.contains_key(&key) {
+            return Err(StakeTableError::ExistingKey);
+        }
+        // the low leaf is the largest key smaller than `key`, or the largest key if there is none
+        let low_index = self
+            .sorted_keys
+            .range(..key)
+            .next_back()
+            .or_else(|| self.sorted_keys.iter().next_back())
+            .map(|(_, index)| *index);
+        let mut witness = KeyIndexWitness::dummy(self.height);
+        let next = match low_index {
+            Some(low_index) => {
+                let (low_key, low_next) = self.leaves[low_index];
+                let (positions, siblings) = self.path(low_index);
+                witness.low_leaf = MerkleProofWitness {
+                    key: low_key,
+                    value: low_next,
+                    positions,
+                    siblings,
+                };
+                self.set_leaf(low_index, low_key, key)?;
+                low_next
+            }
+            None => key,
+        };
+        let index = self.leaves.len();
+        witness.siblings = self.path(index).1;
+        self.set_leaf(index, key, next)?;
+        self.sorted_keys.insert(key, index);
+        Ok(witness)
+    }
+}
+
+/// Commitment of a leaf of the key index.
+fn hash_key_leaf(key: FieldType, next: FieldType) -> Result<FieldType, StakeTableError> {
+    Ok(Digest::evaluate([FieldType::zero(), key, next])
+        .map_err(|_| StakeTableError::RescueError)?[0])
+}
+
+/// Commitment of a node of the key index from its (possibly fewer) non-empty children.
+fn hash_key_node(children: &[FieldType]) -> Result<FieldType, StakeTableError> {
+    if children.iter().all(|child| child.is_zero()) {
+        return Ok(FieldType::zero());
+    }
+    let mut input = [FieldType::zero(); TREE_BRANCH];
+    input[..children.len()].copy_from_slice(children);
+    Ok(Digest::evaluate(input).map_err(|_| StakeTableError::RescueError)?[0])
+}
+
+/// Applies `ops` to the pending stake table, and returns the witnesses of the state transition
+/// circuit, see [`StakeTableTransitionGadget::check_stake_table_transition`].
+/// On error, the operations before the failing one remain applied.
+pub fn apply_stake_table_ops(
+    st: &mut StakeTable,
+    ops: &[StakeTableOp],
+) -> Result<Vec<StakeTableOpWitness<FieldType>>, StakeTableError> {
+    let mut key_index = KeyIndex::from_stake_table(st)?;
+    let mut witnesses = Vec::new();
+    for op in ops {
+        let key = match op {
+            StakeTableOp::Register { key, value: _ }
+            | StakeTableOp::Update {
+                key,
+                delta: _,
+                negative: _,
+            }
+            | StakeTableOp::SetValue { key, value: _ } => key,
+        };
+        let key_field = FieldType::deserialize_compressed(&key.0[..])
+            .map_err(|_| StakeTableError::MalformedKey)?;
+        let (positions, siblings, old_value) = st.pending_location(key)?;
+        let new_value = match op {
+            StakeTableOp::Register { key, value } => {
+                st.register(key, *value)?;
+                *value
+            }
+            StakeTableOp::Update {
+                key,
+                delta,
+                negative,
+            } => st.update(key, *delta, *negative)?,
+            StakeTableOp::SetValue { key, value } => {
+                st.set_value(key, *value)?;
+                *value
+            }
+        };
+        let key_index_witness = if old_value.is_none() {
+            key_index.register(key_field)?
+        } else {
+            KeyIndexWitness::dummy(positions.len())
+        };
+        witnesses.push(StakeTableOpWitness {
+            is_register: old_value.is_none(),
+            proof: MerkleProofWitness {
+                key: key_field,
+                value: u256_to_field(&old_value.unwrap_or_default()),
+                positions,
+                siblings,
+            },
+            new_value: u256_to_field(&new_value),
+            key_index: key_index_witness,
+        });
+    }
+    Ok(witnesses)
+}
+
+/// Circuit variable of the update of the key index by a registration.
+#[derive(Debug, Clone)]
+pub struct KeyIndexVar {
+    /// The low leaf and its path in the key index
+    pub low_leaf: MerkleProofVar,
+    /// The siblings of the new key in the key index, from the bottom up
+    pub siblings: Vec<[Variable; TREE_BRANCH - 1]>,
+}
+
+/// Circuit variable of a stake table operation.
+#[derive(Debug, Clone)]
+pub struct StakeTableOpVar {
+    /// Whether the leaf is empty before the operation
+    pub is_register: BoolVar,
+    /// The key, the old value and the path of the leaf
+    pub proof: MerkleProofVar,
+    /// The new value of the leaf
+    pub new_value: Variable,
+    /// The update of the key index
+    pub key_index: KeyIndexVar,
+}
+
+/// Circuit variable of the state of the stake table.
+#[derive(Debug, Clone, Copy)]
+pub struct StakeTableStateVar {
+    /// Digest of the stake table
+    pub root: Variable,
+    /// Digest of the key index
+    pub key_root: Variable,
+    /// Number of registered keys
+    pub num_keys: Variable,
+}
+
+/// Plonk circuit gadget for the stake table state transitions.
+/// Besides how the leaves change, the circuit checks that a key is registered at the next free
+/// position and that it is not registered yet, using the key index of the state.
+pub trait StakeTableTransitionGadget<F>
+where
+    F: RescueParameter,
+{
+    /// Allocates a stake table state as public inputs, see [`StakeTableState::public_input`].
+    fn create_stake_table_state_variable(
+        &mut self,
+        state: &StakeTableState<F>,
+    ) -> Result<StakeTableStateVar, CircuitError>;
+
+    /// Allocates the witness of a stake table operation.
+    /// * `op` - the operation witness, see [`apply_stake_table_ops`].
+    fn create_stake_table_op_variable(
+        &mut self,
+        op: &StakeTableOpWitness<F>,
+    ) -> Result<StakeTableOpVar, CircuitError>;
+
+    /// Single operation checking circuit: the leaf of `op` is in the tree of root `state.root`,
+    /// its new value is a stake amount of `STAKE_AMOUNT_BIT_LEN` bits, and a registered key is at
+    /// position `state.num_keys` and strictly between a key of the key index and its successor.
+    /// * `state` - the state of the stake table before the operation.
+    /// * `op` - the operation.
+    /// * Returns the state of the stake table after the operation.
+    fn apply_stake_table_op(
+        &mut self,
+        state: &StakeTableStateVar,
+        op: &StakeTableOpVar,
+    ) -> Result<StakeTableStateVar, CircuitError>;
+
+    /// State transition checking circuit: applying `ops` in order takes `old_state` to
+    /// `new_state`.
+    /// * `old_state` - the state of the stake table before the operations.
+    /// * `ops` - the operations.
+    /// * `new_state` - the state of the stake table after the operations.
+    fn check_stake_table_transition(
+        &mut self,
+        old_state: &StakeTableStateVar,
+        ops: &[StakeTableOpVar],
+        new_state: &StakeTableStateVar,
+    ) -> Result<(), CircuitError>;
+}
+
+impl<F> StakeTableTransitionGadget<F> for PlonkCircuit<F>
+where
+    F: RescueParameter,
+{
+    fn create_stake_table_state_variable(
+        &mut self,
+        state: &StakeTableState<F>,
+    ) -> Result<StakeTableStateVar, CircuitError> {
+        Ok(StakeTableStateVar {
+            root: self.create_public_variable(state.root)?,
+            key_root: self.create_public_variable(state.key_root)?,
+            num_keys: self.create_public_variable(F::from(state.num_keys as u64))?,
+        })
+    }
+
+    fn create_stake_table_op_variable(
+        &mut self,
+        op: &StakeTableOpWitness<F>,
+    ) -> Result<StakeTableOpVar, CircuitError> {
+        let height = op.proof.positions.len();
+        if op.key_index.low_leaf.positions.len() != height || op.key_index.siblings.len() != height
+        {
+            return Err(CircuitError::ParameterError(
+                "the paths of the operation have different heights".into(),
+            ));
+        }
+        let siblings = op
+            .key_index
+            .siblings
+            .iter()
+            .map(|siblings| {
+                Ok([
+                    self.create_variable(siblings[0])?,
+                    self.create_variable(siblings[1])?,
+                ])
+            })
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        Ok(StakeTableOpVar {
+            is_register: self.create_boolean_variable(op.is_register)?,
+            proof: self.create_merkle_proof_variable(&op.proof)?,
+            new_value: self.create_variable(op.new_value)?,
+            key_index: KeyIndexVar {
+                low_leaf: self.create_merkle_proof_variable(&op.key_index.low_leaf)?,
+                siblings,
+            },
+        })
+    }
+
+    fn apply_stake_table_op(
+        &mut self,
+        state: &StakeTableStateVar,
+        op: &StakeTableOpVar,
+    ) -> Result<StakeTableStateVar, CircuitError> {
+        // the new stake does not go negative
+        self.enforce_in_range(op.new_value, STAKE_AMOUNT_BIT_LEN)?;
+
+        let (old_root, root) = update_leaf(
+            self,
+            op.is_register,
+            op.proof.key,
+            op.proof.value,
+            op.new_value,
+            &op.proof.path,
+        )?;
+        self.enforce_equal(old_root, state.root)?;
+
+        // a key is registered at the next free position: the position at each level is
+        // `1 - is_left + is_right`
+        let mut coeffs = Vec::new();
+        let mut vars = Vec::new();
+        let mut constant = F::zero();
+        let mut power = F::one();
+        for node in op.proof.path.iter() {
+            constant += power;
+            coeffs.push(-power);
+            coeffs.push(power);
+            vars.push(node.is_left.into());
+            vars.push(node.is_right.into());
+            power *= F::from(TREE_BRANCH as u64);
+        }
+        let index = self.lin_comb(&coeffs, &constant, &vars)?;
+        enforce_equal_if(self, op.is_register, index, state.num_keys)?;
+        let num_keys = self.add(state.num_keys, op.is_register.into())?;
+
+        // a registered key is strictly between the key of the low leaf and its successor, i.e.
+        // not in the key index, unless there is no registered key yet
+        let is_first = self.is_zero(state.num_keys)?;
+        let not_first = self.logic_neg(is_first)?;
+        let has_low_leaf = self.logic_and(op.is_register, not_first)?;
+        let low_leaf = &op.key_index.low_leaf;
+        let false_var = self.false_var();
+        let (old_key_root, low_key_root) = update_leaf(
+            self,
+            false_var,
+            low_leaf.key,
+            low_leaf.value,
+            op.proof.key,
+            &low_leaf.path,
+        )?;
+        enforce_equal_if(self, has_low_leaf, old_key_root, state.key_root)?;
+        let above_low = self.is_lt(low_leaf.key, op.proof.key)?;
+        let below_next = self.is_lt(op.proof.key, low_leaf.value)?;
+        let between = self.logic_and(above_low, below_next)?;
+        // the successor of the largest key is the smallest one
+        let wraps = self.is_leq(low_leaf.value, low_leaf.key)?;
+        let outside = self.logic_or(above_low, below_next)?;
+        let is_new = self.conditional_select(wraps, between.into(), outside.into())?;
+        let one = self.one();
+        enforce_equal_if(self, has_low_leaf, is_new, one)?;
+        let key_root = self.conditional_select(has_low_leaf, state.key_root, low_key_root)?;
+
+        // the new key is added to the key index at its position in the stake table, pointing to
+        // the successor of the low leaf, or to itself if it is the first key
+        let next = self.conditional_select(is_first, low_leaf.value, op.proof.key)?;
+        let path: Vec<_> = op
+            .proof
+            .path
+            .iter()
+            .zip(op.key_index.siblings.iter())
+            .map(|(node, siblings)| MerkleNodeVar {
+                is_left: node.is_left,
+                is_right: node.is_right,
+                siblings: *siblings,
+            })
+            .collect();
+        let true_var = self.true_var();
+        let zero = self.zero();
+        let (empty_key_root, new_key_root) =
+            update_leaf(self, true_var, op.proof.key, zero, next, &path)?;
+        enforce_equal_if(self, op.is_register, empty_key_root, key_root)?;
+        let key_root = self.conditional_select(op.is_register, state.key_root, new_key_root)?;
+
+        Ok(StakeTableStateVar {
+            root,
+            key_root,
+            num_keys,
+        })
+    }
+
+    fn check_stake_table_transition(
+        &mut self,
+        old_state: &StakeTableStateVar,
+        ops: &[StakeTableOpVar],
+        new_state: &StakeTableStateVar,
+    ) -> Result<(), CircuitError> {
+        let mut state = *old_state;
+        for op in ops.iter() {
+            state = self.apply_stake_table_op(&state, op)?;
+        }
+        self.enforce_equal(state.root, new_state.root)?;
+        self.enforce_equal(state.key_root, new_state.key_root)?;
+        self.enforce_equal(state.num_keys, new_state.num_keys)
+    }
+}
+
+/// Computes the roots of a tree before and after changing the value of a leaf, the leaf being
+/// empty before if `is_register`.
+fn update_leaf<F: RescueParameter>(
+    circuit: &mut PlonkCircuit<F>,
+    is_register: BoolVar,
+    key: Variable,
+    old_value: Variable,
+    new_value: Variable,
+    path: &[MerkleNodeVar],
+) -> Result<(Variable, Variable), CircuitError> {
+    let zero = circuit.zero();
+    let old_leaf = compute_leaf(circuit, key, old_value)?;
+    let mut old_comm = circuit.conditional_select(is_register, old_leaf, zero)?;
+    let mut new_comm = compute_leaf(circuit, key, new_value)?;
+    // whether the subtree of the old tree is empty
+    let mut is_empty = is_register;
+    for node in path.iter() {
+        let [s0, s1] = node.siblings;
+        let s0_empty = circuit.is_zero(s0)?;
+        let s1_empty = circuit.is_zero(s1)?;
+        let siblings_empty = circuit.logic_and(s0_empty, s1_empty)?;
+        is_empty = circuit.logic_and(is_empty, siblings_empty)?;
+        let comm = circuit.compute_merkle_node(old_comm, node)?;
+        old_comm = circuit.conditional_select(is_empty, comm, zero)?;
+        new_comm = circuit.compute_merkle_node(new_comm, node)?;
+    }
+    Ok((old_comm, new_comm))
+}
+
+/// Enforces `a == b` if `cond` is true.
+fn enforce_equal_if<F: RescueParameter>(
+    circuit: &mut PlonkCircuit<F>,
+    cond: BoolVar,
+    a: Variable,
+    b: Variable,
+) -> Result<(), CircuitError> {
+    let diff = circuit.sub(a, b)?;
+    let diff = circuit.mul(cond.into(), diff)?;
+    circuit.enforce_constant(diff, F::zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::vec;
+    use jf_utils::to_bytes;
+
+    #[test]
+    fn test_merkle_proof_circuit() -> Result<(), CircuitError> {
+        let height = 3;
+        let mut st = StakeTable::new(height);
+        let keys = (0..10)
+            .map(|i| EncodedPublicKey(to_bytes!(&FieldType::from(i)).unwrap()))
+            .collect::<Vec<_>>();
+        for (i, key) in keys.iter().enumerate() {
+            st.register(key, U256::from(100 + i)).unwrap();
+        }
+        let comm = st.commitment(STVersion::PENDING);
+
+        // the circuit agrees with `MerkleProof::verify` for every position
+        for key in keys.iter() {
+            let proof = st.lookup(STVersion::PENDING, key).unwrap();
+            assert!(proof.verify(&comm).is_ok());
+            let witness = MerkleProofWitness::from_proof(&proof)?;
+            assert_eq!(witness.positions.len(), height);
+
+            let mut circuit = PlonkCircuit::<FieldType>::new_turbo_plonk();
+            let root_var = circuit.create_public_variable(merkle_commitment_public_input(&comm))?;
+            let proof_var = circuit.create_merkle_proof_variable(&witness)?;
+            circuit.enforce_merkle_proof(&proof_var, root_var)?;
+            assert!(circuit
+                .check_circuit_satisfiability(&[*comm.digest()])
+                .is_ok());
+
+            // bad path: wrong root
+            assert!(circuit
+                .check_circuit_satisfiability(&[*comm.digest() + FieldType::from(1u8)])
+                .is_err());
+        }
+
+        let proof = st.lookup(STVersion::PENDING, &keys[4]).unwrap();
+        let witness = MerkleProofWitness::from_proof(&proof)?;
+        let build = |witness: &MerkleProofWitness<FieldType>| -> Result<_, CircuitError> {
+            let mut circuit = PlonkCircuit::<FieldType>::new_turbo_plonk();
+            let root_var = circuit.create_public_variable(*comm.digest())?;
+            let proof_var = circuit.create_merkle_proof_variable(witness)?;
+            circuit.enforce_merkle_proof(&proof_var, root_var)?;
+            Ok((circuit, proof_var))
+        };
+
+        // bad path: wrong value
+        let mut bad_witness = witness.clone();
+        bad_witness.value += FieldType::from(1u8);
+        let (circuit, _) = build(&bad_witness)?;
+        assert!(circuit
+            .check_circuit_satisfiability(&[*comm.digest()])
+            .is_err());
+        // bad path: wrong key
+        let mut bad_witness = witness.clone();
+        bad_witness.key = FieldType::from(5u8);
+        let (circuit, _) = build(&bad_witness)?;
+        assert!(circuit
+            .check_circuit_satisfiability(&[*comm.digest()])
+            .is_err());
+        // bad path: wrong position
+        let mut bad_witness = witness.clone();
+        bad_witness.positions[0] = (bad_witness.positions[0] + 1) % TREE_BRANCH;
+        let (circuit, _) = build(&bad_witness)?;
+        assert!(circuit
+            .check_circuit_satisfiability(&[*comm.digest()])
+            .is_err());
+        // bad path: a node claimed to be both the left and the right child
+        let (mut circuit, proof_var) = build(&witness)?;
+        assert!(circuit
+            .check_circuit_satisfiability(&[*comm.digest()])
+            .is_ok());
+        *circuit.witness_mut(proof_var.path[0].is_left.into()) = FieldType::from(1u8);
+        *circuit.witness_mut(proof_var.path[0].is_right.into()) = FieldType::from(1u8);
+        assert!(circuit
+            .check_circuit_satisfiability(&[*comm.digest()])
+            .is_err());
+        // bad path: a proof of another version of the stake table
+        st.advance();
+        st.set_value(&keys[4], U256::from(1u8)).unwrap();
+        let (circuit, _) = build(&witness)?;
+        let new_comm = st.commitment(STVersion::PENDING);
+        assert!(proof.verify(&new_comm).is_err());
+        assert!(circuit
+            .check_circuit_satisfiability(&[*new_comm.digest()])
+            .is_err());
+
+        // check input parameter errors
+        let mut bad_witness = witness.clone();
+        bad_witness.positions[1] = TREE_BRANCH;
+        assert!(build(&bad_witness).is_err());
+        let mut bad_witness = witness;
+        bad_witness.siblings.pop();
+        assert!(build(&bad_witness).is_err());
+        let mut bad_proof = proof.clone();
+        bad_proof.path.remove(0);
+        assert!(MerkleProofWitness::from_proof(&bad_proof).is_err());
+        let mut bad_proof = proof;
+        bad_proof.path.swap(0, 1);
+        assert!(MerkleProofWitness::from_proof(&bad_proof).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stake_table_transition_circuit() -> Result<(), CircuitError> {
+        let height = 2;
+        let mut st = StakeTable::new(height);
+        // registered out of order, so that the key index is not in the order of registration
+        let key_fields = [5u8, 2, 7, 0, 3, 8, 1, 6].map(FieldType::from);
+        let keys = key_fields
+            .iter()
+            .map(|key| EncodedPublicKey(to_bytes!(key).unwrap()))
+            .collect::<Vec<_>>();
+        let check = |old_state: &StakeTableState<FieldType>,
+                     witnesses: &[StakeTableOpWitness<FieldType>],
+                     new_state: &StakeTableState<FieldType>|
+         -> Result<_, CircuitError> {
+            let mut circuit = PlonkCircuit::<FieldType>::new_turbo_plonk();
+            let old_state_var = circuit.create_stake_table_state_variable(old_state)?;
+            let new_state_var = circuit.create_stake_table_state_variable(new_state)?;
+            let op_vars = witnesses
+                .iter()
+                .map(|op| circuit.create_stake_table_op_variable(op))
+                .collect::<Result<Vec<_>, _>>()?;
+            circuit.check_stake_table_transition(&old_state_var, &op_vars, &new_state_var)?;
+            let mut public_input = old_state.public_input();
+            public_input.extend(new_state.public_input());
+            Ok(circuit.check_circuit_satisfiability(&public_input))
+        };
+        // a single operation, whatever the state after it
+        let check_op = |state: &StakeTableState<FieldType>,
+                        witness: &StakeTableOpWitness<FieldType>|
+         -> Result<_, CircuitError> {
+            let mut circuit = PlonkCircuit::<FieldType>::new_turbo_plonk();
+            let state_var = circuit.create_stake_table_state_variable(state)?;
+            let op_var = circuit.create_stake_table_op_variable(witness)?;
+            circuit.apply_stake_table_op(&state_var, &op_var)?;
+            Ok(circuit.check_circuit_satisfiability(&state.public_input()))
+        };
+
+        // registrations into an empty stake table
+        let ops = keys[..4]
+            .iter()
+            .enumerate()
+            .map(|(i, key)| StakeTableOp::Register {
+                key: key.clone(),
+                value: U256::from(100 + i),
+            })
+            .collect::<Vec<_>>();
+        let old_state = stake_table_state(&st).unwrap();
+        assert_eq!(
+            old_state,
+            StakeTableState {
+                root: FieldType::from(0u8),
+                key_root: FieldType::from(0u8),
+                num_keys: 0,
+            }
+        );
+        let witnesses = apply_stake_table_ops(&mut st, &ops).unwrap();
+        let new_state = stake_table_state(&st).unwrap();
+        assert_eq!(new_state.root, *st.commitment(STVersion::PENDING).digest());
+        assert_eq!(new_state.num_keys, 4);
+        assert!(witnesses.iter().all(|op| op.is_register));
+        assert!(check(&old_state, &witnesses, &new_state)?.is_ok());
+
+        // a mixed batch, registering into a partially filled subtree and into an empty one
+        let ops = vec![
+            StakeTableOp::Update {
+                key: keys[1].clone(),
+                delta: U256::from(10u8),
+                negative: false,
+            },
+            StakeTableOp::Update {
+                key: keys[2].clone(),
+                delta: U256::from(50u8),
+                negative: true,
+            },
+            StakeTableOp::SetValue {
+                key: keys[0].clone(),
+                value: U256::from(7u8),
+            },
+            StakeTableOp::Register {
+                key: keys[4].clone(),
+                value: U256::from(1u8),
+            },
+            StakeTableOp::Register {
+                key: keys[5].clone(),
+                value: U256::from(2u8),
+            },
+            StakeTableOp::Register {
+                key: keys[6].clone(),
+                value: U256::from(3u8),
+            },
+            StakeTableOp::Update {
+                key: keys[6].clone(),
+                delta: U256::from(3u8),
+                negative: true,
+            },
+        ];
+        let old_state = new_state;
+        let witnesses = apply_stake_table_ops(&mut st, &ops).unwrap();
+        let new_state = stake_table_state(&st).unwrap();
+        assert_eq!(st.total_stakes(STVersion::PENDING), U256::from(276u32));
+        assert_eq!(new_state.num_keys, 7);
+        assert!(check(&old_state, &witnesses, &new_state)?.is_ok());
+        // every prefix of the batch is a valid transition too
+        let mut prefix_st = StakeTable::new(height);
+        for (i, key) in keys[..4].iter().enumerate() {
+            prefix_st.register(key, U256::from(100 + i)).unwrap();
+        }
+        let mut prefix_state = stake_table_state(&prefix_st).unwrap();
+        assert_eq!(prefix_state, old_state);
+        for (op, witness) in ops.iter().zip(witnesses.iter()) {
+            let old_prefix_state = prefix_state;
+            assert_eq!(
+                &apply_stake_table_ops(&mut prefix_st, &[op.clone()]).unwrap()[0],
+                witness
+            );
+            prefix_state = stake_table_state(&prefix_st).unwrap();
+            assert!(check(&old_prefix_state, &[witness.clone()], &prefix_state)?.is_ok());
+        }
+        assert_eq!(prefix_state, new_state);
+
+        // bad path: wrong states
+        assert!(check(&old_state, &witnesses, &old_state)?.is_err());
+        assert!(check(&new_state, &witnesses, &new_state)?.is_err());
+        let mut bad_state = new_state;
+        bad_state.num_keys += 1;
+        assert!(check(&old_state, &witnesses, &bad_state)?.is_err());
+        let mut bad_state = new_state;
+        bad_state.key_root += FieldType::from(1u8);
+        assert!(check(&old_state, &witnesses, &bad_state)?.is_err());
+        let mut bad_state = old_state;
+        bad_state.num_keys -= 1;
+        assert!(check(&bad_state, &witnesses, &new_state)?.is_err());
+        // bad path: operations in another order
+        let mut bad_witnesses = witnesses.clone();
+        bad_witnesses.swap(0, 1);
+        assert!(check(&old_state, &bad_witnesses, &new_state)?.is_err());
+        let mut bad_witnesses = witnesses.clone();
+        bad_witnesses.swap(3, 4);
+        assert!(check(&old_state, &bad_witnesses, &new_state)?.is_err());
+        // bad path: missing operation
+        assert!(check(&old_state, &witnesses[1..], &new_state)?.is_err());
+        // bad path: wrong new value
+        let mut bad_witnesses = witnesses.clone();
+        bad_witnesses[0].new_value += FieldType::from(1u8);
+        assert!(check(&old_state, &bad_witnesses, &new_state)?.is_err());
+        // bad path: wrong old value
+        let mut bad_witnesses = witnesses.clone();
+        bad_witnesses[1].proof.value += FieldType::from(1u8);
+        assert!(check(&old_state, &bad_witnesses, &new_state)?.is_err());
+        // bad path: overwriting a registered key as a registration
+        let mut bad_witnesses = witnesses.clone();
+        bad_witnesses[2].is_register = true;
+        assert!(check(&old_state, &bad_witnesses, &new_state)?.is_err());
+
+        // bad path: a negative stake, i.e. wrapping around the field
+        let mut circuit = PlonkCircuit::<FieldType>::new_turbo_plonk();
+        let state_var = circuit.create_stake_table_state_variable(&old_state)?;
+        let op_var = circuit.create_stake_table_op_variable(&witnesses[1])?;
+        circuit.apply_stake_table_op(&state_var, &op_var)?;
+        assert!(circuit
+            .check_circuit_satisfiability(&old_state.public_input())
+            .is_ok());
+        *circuit.witness_mut(op_var.new_value) = -FieldType::from(1u8);
+        assert!(circuit
+            .check_circuit_satisfiability(&old_state.public_input())
+            .is_err());
+
+        // the next registration is at position 7 of 9, whose siblings are the same as the ones
+        // of position 8 in both trees
+        let state = new_state;
+        let witness = apply_stake_table_ops(
+            &mut st.clone(),
+            &[StakeTableOp::Register {
+                key: keys[7].clone(),
+                value: U256::from(1u8),
+            }],
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(witness.proof.positions, vec![1, 2]);
+        assert!(check_op(&state, &witness)?.is_ok());
+        // bad path: a registration at another free position
+        let mut bad_witness = witness.clone();
+        bad_witness.proof.positions[0] = 2;
+        assert!(check_op(&state, &bad_witness)?.is_err());
+        // bad path: registering a key again, whichever the low leaf
+        let key_index = KeyIndex::from_stake_table(&st).unwrap();
+        assert_eq!(key_index.root(), state.key_root);
+        for (low_index, (low_key, low_next)) in key_index.leaves.iter().enumerate() {
+            let (positions, siblings) = key_index.path(low_index);
+            let mut bad_witness = witness.clone();
+            bad_witness.key_index.low_leaf = MerkleProofWitness {
+                key: *low_key,
+                value: *low_next,
+                positions,
+                siblings,
+            };
+            for key in key_fields[..7].iter() {
+                bad_witness.proof.key = *key;
+                assert!(check_op(&state, &bad_witness)?.is_err());
+            }
+        }
+        // bad path: a low leaf not in the key index
+        let mut bad_witness = witness.clone();
+        bad_witness.key_index.low_leaf.value += FieldType::from(1u8);
+        assert!(check_op(&state, &bad_witness)?.is_err());
+        // bad path: the first registration must not skip positions either
+        let empty_state = stake_table_state(&StakeTable::new(height)).unwrap();
+        let mut bad_witness = apply_stake_table_ops(
+            &mut StakeTable::new(height),
+            &[StakeTableOp::Register {
+                key: keys[0].clone(),
+                value: U256::from(1u8),
+            }],
+        )
+        .unwrap()
+        .remove(0);
+        assert!(check_op(&empty_state, &bad_witness)?.is_ok());
+        bad_witness.proof.positions[0] = 1;
+        assert!(check_op(&empty_state, &bad_witness)?.is_err());
+
+        // native errors leave the witnesses of the failing operation out
+        let mut bad_st = st.clone();
+        assert!(apply_stake_table_ops(
+            &mut bad_st,
+            &[StakeTableOp::Update {
+                key: keys[3].clone(),
+                delta: U256::from(1000u32),
+                negative: true,
+            }]
+        )
+        .is_err());
+        assert!(apply_stake_table_ops(
+            &mut bad_st,
+            &[StakeTableOp::Register {
+                key: keys[3].clone(),
+                value: U256::from(1u8),
+            }]
+        )
+        .is_err());
+        assert!(apply_stake_table_ops(
+            &mut bad_st,
+            &[StakeTableOp::SetValue {
+                key: keys[7].clone(),
+                value: U256::from(1u8),
+            }]
+        )
+        .is_err());
+        assert!(apply_stake_table_ops(
+            &mut bad_st,
+            &[StakeTableOp::Register {
+                key: EncodedPublicKey(vec![0xff; 32]),
+                value: U256::from(1u8),
+            }]
+        )
+        .is_err());
+        assert_eq!(bad_st, st);
+
+        // check input parameter errors
+        let mut bad_witness = witness;
+        bad_witness.key_index.siblings.pop();
+        let mut circuit = PlonkCircuit::<FieldType>::new_turbo_plonk();
+        assert!(circuit
+            .create_stake_table_op_variable(&bad_witness)
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
 //! Implementation for BitVectorQC that uses BLS signature + Bit vector.
 //! See more details in HotShot paper.
 
-use crate::qc::QuorumCertificate;
+use crate::qc::{
+    keys::StakeKey,
+    policy::ThresholdPolicy,
+    signer_set::SignerSet,
+    stake::{sum_stakes, StakeAmount},
+    QuorumCertificate,
+};
 use ark_std::{
     fmt::Debug,
     format,
@@ -15,41 +21,265 @@ use ethereum_types::U256;
 use generic_array::GenericArray;
 use jf_primitives::errors::PrimitivesError;
 use jf_primitives::errors::PrimitivesError::ParameterError;
-use jf_primitives::signatures::AggregateableSignatureSchemes;
-use serde::{Deserialize, Serialize};
+use jf_primitives::signatures::{AggregateableSignatureSchemes, SignatureScheme};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use typenum::U32;
 
 /// An implementation of QC using BLS signature and a bit-vector.
+/// * `K` - the keys of a stake table entry, from which the `A` verification key is picked
+/// * `S` - the type of the stake amounts
 #[derive(Serialize, Deserialize)]
-pub struct BitVectorQC<A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>>(
-    PhantomData<A>,
-);
+pub struct BitVectorQC<
+    A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>,
+    K = <A as SignatureScheme>::VerificationKey,
+    S = U256,
+>(PhantomData<(A, K, S)>);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StakeTableEntry<V, S = U256> {
+    pub stake_key: V,
+    pub stake_amount: S,
+}
+
+impl<V, S> StakeTableEntry<V, S> {
+    /// The key of the entry used by a given signature scheme, see [`StakeKey`].
+    pub fn key<K>(&self) -> &K
+    where
+        V: StakeKey<K>,
+    {
+        StakeKey::<K>::key(&self.stake_key)
+    }
+}
+
+/// The parameters of a QC: the stake table entries and the quorum threshold derived from a
+/// policy, see `QCParams::new`. Deserialization checks that the threshold matches the policy.
+#[derive(Serialize, PartialEq, Debug, Clone)]
+pub struct QCParams<V, P, S = U256> {
+    stake_entries: Vec<StakeTableEntry<V, S>>,
+    threshold: S,
+    policy: ThresholdPolicy<S>,
+    agg_sig_pp: P,
+}
+
+impl<V, P, S> QCParams<V, P, S> {
+    /// The stake table entries
+    pub fn stake_entries(&self) -> &[StakeTableEntry<V, S>] {
+        &self.stake_entries
+    }
+
+    /// The minimal stake reaching the quorum
+    pub fn threshold(&self) -> &S {
+        &self.threshold
+    }
+
+    /// The policy the threshold is derived from
+    pub fn policy(&self) -> &ThresholdPolicy<S> {
+        &self.policy
+    }
+
+    /// Public parameters for aggregate signature
+    pub fn agg_sig_pp(&self) -> &P {
+        &self.agg_sig_pp
+    }
+}
+
+impl<V, P, S: StakeAmount> QCParams<V, P, S> {
+    /// Builds the QC parameters, with the threshold derived from `policy`.
+    /// * `stake_entries` - the stake table entries
+    /// * `policy` - the threshold policy
+    /// * `agg_sig_pp` - public parameters for aggregate signature
+    pub fn new(
+        stake_entries: Vec<StakeTableEntry<V, S>>,
+        policy: ThresholdPolicy<S>,
+        agg_sig_pp: P,
+    ) -> Result<Self, PrimitivesError> {
+        let threshold = policy.threshold(total_stake(&stake_entries)?)?;
+        Ok(Self {
+            stake_entries,
+            threshold,
+            policy,
+            agg_sig_pp,
+        })
+    }
+
+    /// Checks that the threshold is the one derived from the policy, done on deserialization.
+    pub fn check_policy(&self) -> Result<(), PrimitivesError> {
+        let expected = self.policy.threshold(total_stake(&self.stake_entries)?)?;
+        if self.threshold != expected {
+            return Err(ParameterError(format!(
+                "threshold {} does not match the policy threshold {}",
+                self.threshold, expected
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Sum of the stake amounts, or an error on overflow.
+fn total_stake<V, S: StakeAmount>(
+    stake_entries: &[StakeTableEntry<V, S>],
+) -> Result<S, PrimitivesError> {
+    sum_stakes(stake_entries.iter().map(|entry| &entry.stake_amount))
+}
+
+impl<'de, V, P, S> Deserialize<'de> for QCParams<V, P, S>
+where
+    V: Deserialize<'de>,
+    P: Deserialize<'de>,
+    S: StakeAmount,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "QCParams")]
+        struct UncheckedQCParams<V, P, S> {
+            stake_entries: Vec<StakeTableEntry<V, S>>,
+            threshold: S,
+            policy: ThresholdPolicy<S>,
+            agg_sig_pp: P,
+        }
+
+        let unchecked = UncheckedQCParams::deserialize(deserializer)?;
+        let params = Self {
+            stake_entries: unchecked.stake_entries,
+            threshold: unchecked.threshold,
+            policy: unchecked.policy,
+            agg_sig_pp: unchecked.agg_sig_pp,
+        };
+        params.check_policy().map_err(D::Error::custom)?;
+        Ok(params)
+    }
+}
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub struct StakeTableEntry<V> {
+/// A stake table entry together with its index.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IndexedStakeTableEntry<V, S = U256> {
+    pub index: usize,
     pub stake_key: V,
-    pub stake_amount: U256,
+    pub stake_amount: S,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub struct QCParams<V, P> {
-    pub stake_entries: Vec<StakeTableEntry<V>>,
-    pub threshold: U256,
-    pub agg_sig_pp: P,
+/// Detailed trace of a QC.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct QCTrace<V, S = U256> {
+    /// Entries that signed, with their weights
+    pub signers: Vec<IndexedStakeTableEntry<V, S>>,
+    /// Entries that did not sign
+    pub non_signers: Vec<IndexedStakeTableEntry<V, S>>,
+    /// Total weight of the signers
+    pub signed_weight: S,
+    /// By how much the signed weight exceeds the threshold
+    pub threshold_margin: S,
 }
 
-impl<A> QuorumCertificate<A> for BitVectorQC<A>
+impl<A, K, S> BitVectorQC<A, K, S>
 where
     A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>,
+    K: StakeKey<A::VerificationKey> + Clone + Serialize + for<'a> Deserialize<'a>,
+    S: StakeAmount,
 {
-    type QCProverParams = QCParams<A::VerificationKey, A::PublicParameter>;
+    /// Trace the signers, their stakes and the non-signers given a qc.
+    /// * `qc_vp` - public parameters for validating the QC
+    /// * `message` - message to check the aggregated signature against
+    /// * `qc` - quorum certificate
+    /// * `returns` - the trace if the qc is valid, an error otherwise.
+    pub fn trace_signers(
+        qc_vp: &QCParams<K, A::PublicParameter, S>,
+        message: &GenericArray<A::MessageUnit, <Self as QuorumCertificate<A>>::MessageLength>,
+        qc: &<Self as QuorumCertificate<A>>::QC,
+    ) -> Result<QCTrace<K, S>, PrimitivesError> {
+        Self::check(qc_vp, message, qc)?;
+        Self::trace_signers_unchecked(qc_vp, qc)
+    }
+
+    /// Same as `trace_signers` without verifying the aggregated signature.
+    /// Should only be called on a qc which already passed `check`.
+    pub fn trace_signers_unchecked(
+        qc_vp: &QCParams<K, A::PublicParameter, S>,
+        qc: &<Self as QuorumCertificate<A>>::QC,
+    ) -> Result<QCTrace<K, S>, PrimitivesError> {
+        let (_sig, signers) = qc;
+        let signers = signers.to_checked_bitvec(qc_vp.stake_entries.len())?;
+        let (signers, non_signers): (Vec<_>, Vec<_>) = qc_vp
+            .stake_entries
+            .iter()
+            .zip(signers.iter())
+            .enumerate()
+            .map(|(index, (entry, b))| {
+                (
+                    *b,
+                    IndexedStakeTableEntry {
+                        index,
+                        stake_key: entry.stake_key.clone(),
+                        stake_amount: entry.stake_amount,
+                    },
+                )
+            })
+            .partition(|(b, _)| *b);
+        let signers: Vec<_> = signers.into_iter().map(|(_, entry)| entry).collect();
+        let non_signers: Vec<_> = non_signers.into_iter().map(|(_, entry)| entry).collect();
+        let signed_weight = sum_stakes(signers.iter().map(|entry| &entry.stake_amount))?;
+        if signed_weight < qc_vp.threshold {
+            return Err(ParameterError(format!(
+                "total_weight {} less than threshold {}",
+                signed_weight, qc_vp.threshold,
+            )));
+        }
+        let threshold_margin = signed_weight.try_sub(&qc_vp.threshold)?;
+        Ok(QCTrace {
+            signers,
+            non_signers,
+            signed_weight,
+            threshold_margin,
+        })
+    }
+
+    /// Same as `assemble` except that the total weight is not checked against the threshold.
+    /// The result is a partial QC, which can be merged with other ones, see `merge`.
+    pub fn assemble_partial(
+        qc_pp: &QCParams<K, A::PublicParameter, S>,
+        signers: &BitSlice,
+        sigs: &[A::Signature],
+    ) -> Result<<Self as QuorumCertificate<A>>::QC, PrimitivesError> {
+        if signers.len() != qc_pp.stake_entries.len() {
+            return Err(ParameterError(format!(
+                "bit vector len {} != the number of stake entries {}",
+                signers.len(),
+                qc_pp.stake_entries.len(),
+            )));
+        }
+        let mut ver_keys = vec![];
+        for (entry, b) in qc_pp.stake_entries.iter().zip(signers.iter()) {
+            if *b {
+                ver_keys.push(entry.key::<A::VerificationKey>().clone());
+            }
+        }
+        if ver_keys.len() != sigs.len() {
+            return Err(ParameterError(format!(
+                "the number of ver_keys {} != the number of partial signatures {}",
+                ver_keys.len(),
+                sigs.len(),
+            )));
+        }
+        let sig = A::aggregate(&qc_pp.agg_sig_pp, &ver_keys[..], sigs)?;
+
+        Ok((sig, SignerSet::compact(signers)?))
+    }
+}
+
+impl<A, K, S> QuorumCertificate<A> for BitVectorQC<A, K, S>
+where
+    A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>,
+    K: StakeKey<A::VerificationKey> + Clone + Serialize + for<'a> Deserialize<'a>,
+    S: StakeAmount,
+{
+    type QCProverParams = QCParams<K, A::PublicParameter, S>;
 
     // TODO: later with SNARKs we'll use a smaller verifier parameter
-    type QCVerifierParams = QCParams<A::VerificationKey, A::PublicParameter>;
+    type QCVerifierParams = QCParams<K, A::PublicParameter, S>;
 
-    type QC = (A::Signature, BitVec);
+    type QC = (A::Signature, SignerSet);
     type MessageLength = U32;
-    type QuorumSize = U256;
+    type QuorumSize = S;
 
     fn sign<R: CryptoRng + RngCore>(
         agg_sig_pp: &A::PublicParameter,
@@ -72,40 +302,21 @@ where
                 qc_pp.stake_entries.len(),
             )));
         }
-        let total_weight: U256 =
+        let total_weight = sum_stakes(
             qc_pp
                 .stake_entries
                 .iter()
                 .zip(signers.iter())
-                .fold(U256::zero(), |acc, (entry, b)| {
-                    if *b {
-                        acc + entry.stake_amount
-                    } else {
-                        acc
-                    }
-                });
+                .filter(|(_, b)| **b)
+                .map(|(entry, _)| &entry.stake_amount),
+        )?;
         if total_weight < qc_pp.threshold {
             return Err(ParameterError(format!(
                 "total_weight {} less than threshold {}",
                 total_weight, qc_pp.threshold,
             )));
         }
-        let mut ver_keys = vec![];
-        for (entry, b) in qc_pp.stake_entries.iter().zip(signers.iter()) {
-            if *b {
-                ver_keys.push(entry.stake_key.clone());
-            }
-        }
-        if ver_keys.len() != sigs.len() {
-            return Err(ParameterError(format!(
-                "the number of ver_keys {} != the number of partial signatures {}",
-                ver_keys.len(),
-                sigs.len(),
-            )));
-        }
-        let sig = A::aggregate(&qc_pp.agg_sig_pp, &ver_keys[..], sigs)?;
-
-        Ok((sig, signers.into()))
+        Self::assemble_partial(qc_pp, signers, sigs)
     }
 
     fn check(
@@ -114,25 +325,15 @@ where
         qc: &Self::QC,
     ) -> Result<Self::QuorumSize, PrimitivesError> {
         let (sig, signers) = qc;
-        if signers.len() != qc_vp.stake_entries.len() {
-            return Err(ParameterError(format!(
-                "signers bit vector len {} != the number of stake entries {}",
-                signers.len(),
-                qc_vp.stake_entries.len(),
-            )));
-        }
-        let total_weight: U256 =
+        let signers = signers.to_checked_bitvec(qc_vp.stake_entries.len())?;
+        let total_weight = sum_stakes(
             qc_vp
                 .stake_entries
                 .iter()
                 .zip(signers.iter())
-                .fold(U256::zero(), |acc, (entry, b)| {
-                    if *b {
-                        acc + entry.stake_amount
-                    } else {
-                        acc
-                    }
-                });
+                .filter(|(_, b)| **b)
+                .map(|(entry, _)| &entry.stake_amount),
+        )?;
         if total_weight < qc_vp.threshold {
             return Err(ParameterError(format!(
                 "total_weight {} less than threshold {}",
@@ -142,7 +343,7 @@ where
         let mut ver_keys = vec![];
         for (entry, b) in qc_vp.stake_entries.iter().zip(signers.iter()) {
             if *b {
-                ver_keys.push(entry.stake_key.clone());
+                ver_keys.push(entry.key::<A::VerificationKey>().clone());
             }
         }
         A::multi_sig_verify(&qc_vp.agg_sig_pp, &ver_keys[..], message, sig)?;
@@ -155,31 +356,18 @@ where
         message: &GenericArray<<A>::MessageUnit, Self::MessageLength>,
         qc: &Self::QC,
     ) -> Result<Vec<<A>::VerificationKey>, PrimitivesError> {
-        let (_sig, signers) = qc;
-        if signers.len() != qc_vp.stake_entries.len() {
-            return Err(ParameterError(format!(
-                "signers bit vector len {} != the number of stake entries {}",
-                signers.len(),
-                qc_vp.stake_entries.len(),
-            )));
-        }
-
-        Self::check(qc_vp, message, qc)?;
-
-        let signer_pks: Vec<_> = qc_vp
-            .stake_entries
-            .iter()
-            .zip(signers.iter())
-            .filter(|(_, b)| **b)
-            .map(|(pk, _)| pk.stake_key.clone())
-            .collect();
-        Ok(signer_pks)
+        Ok(Self::trace_signers(qc_vp, message, qc)?
+            .signers
+            .into_iter()
+            .map(|signer| StakeKey::<A::VerificationKey>::key(&signer.stake_key).clone())
+            .collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::qc::signer_set::SignerSetEncoding;
     use jf_primitives::signatures::bls_over_bn254::{BLSOverBN254CurveSignatureScheme, KeyPair};
     use jf_primitives::signatures::SignatureScheme;
 
@@ -202,11 +390,12 @@ mod tests {
                 stake_key: key_pair3.ver_key(),
                 stake_amount: U256::from(7u8),
             };
-            let qc_pp = QCParams {
-                stake_entries: vec![entry1, entry2, entry3],
-                threshold: U256::from(10u8),
+            let qc_pp = QCParams::new(
+                vec![entry1, entry2, entry3],
+                ThresholdPolicy::Absolute(U256::from(10u8)),
                 agg_sig_pp,
-            };
+            )
+            .unwrap();
             let msg = [72u8; 32];
             let sig1 = BitVectorQC::<$aggsig>::sign(
                 &agg_sig_pp,
@@ -244,6 +433,36 @@ mod tests {
                 vec![key_pair2.ver_key(), key_pair3.ver_key()],
             );
 
+            let qc_trace = BitVectorQC::<$aggsig>::trace_signers(&qc_pp, &msg.into(), &qc).unwrap();
+            assert_eq!(
+                qc_trace
+                    .signers
+                    .iter()
+                    .map(|entry| (entry.index, entry.stake_key.clone(), entry.stake_amount))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (1, key_pair2.ver_key(), U256::from(5u8)),
+                    (2, key_pair3.ver_key(), U256::from(7u8))
+                ],
+            );
+            assert_eq!(
+                qc_trace
+                    .non_signers
+                    .iter()
+                    .map(|entry| entry.index)
+                    .collect::<Vec<_>>(),
+                vec![0]
+            );
+            assert_eq!(qc_trace.signed_weight, U256::from(12u8));
+            assert_eq!(qc_trace.threshold_margin, U256::from(2u8));
+            assert_eq!(
+                BitVectorQC::<$aggsig>::trace_signers_unchecked(&qc_pp, &qc).unwrap(),
+                qc_trace
+            );
+            assert!(
+                BitVectorQC::<$aggsig>::trace_signers(&qc_pp, &[70u8; 32].into(), &qc).is_err()
+            );
+
             // Check the QC and the QCParams can be serialized / deserialized
             assert_eq!(
                 qc,
@@ -255,6 +474,19 @@ mod tests {
                 bincode::deserialize(&bincode::serialize(&qc_pp).unwrap()).unwrap()
             );
 
+            // every signer set encoding is accepted
+            for encoding in SignerSetEncoding::ALL {
+                let qc_enc = (
+                    qc.0.clone(),
+                    SignerSet::encode(signers.as_bitslice(), encoding).unwrap(),
+                );
+                assert!(BitVectorQC::<$aggsig>::check(&qc_pp, &msg.into(), &qc_enc).is_ok());
+                assert_eq!(
+                    BitVectorQC::<$aggsig>::trace(&qc_pp, &msg.into(), &qc_enc).unwrap(),
+                    vec![key_pair2.ver_key(), key_pair3.ver_key()],
+                );
+            }
+
             // bad paths
             // number of signatures unmatch
             assert!(BitVectorQC::<$aggsig>::assemble(
@@ -283,17 +515,38 @@ mod tests {
             assert!(BitVectorQC::<$aggsig>::check(
                 &qc_pp,
                 &msg.into(),
-                &(qc.0.clone(), active_bad)
+                &(qc.0.clone(), active_bad.into())
             )
             .is_err());
             assert!(BitVectorQC::<$aggsig>::check(
                 &qc_pp,
                 &msg.into(),
-                &(qc.0.clone(), active_bad_2)
+                &(qc.0.clone(), active_bad_2.into())
             )
             .is_err());
             let bad_msg = [70u8; 32];
             assert!(BitVectorQC::<$aggsig>::check(&qc_pp, &bad_msg.into(), &qc).is_err());
+            assert!(BitVectorQC::<$aggsig>::trace_signers_unchecked(
+                &qc_pp,
+                &(qc.0.clone(), bitvec![1, 1, 0].into())
+            )
+            .is_err());
+            // a signer set with a huge length is rejected before being decoded
+            let huge_signers = SignerSet::Signers {
+                len: u32::MAX,
+                indices: vec![],
+            };
+            assert!(BitVectorQC::<$aggsig>::check(
+                &qc_pp,
+                &msg.into(),
+                &(qc.0.clone(), huge_signers.clone())
+            )
+            .is_err());
+            assert!(BitVectorQC::<$aggsig>::trace_signers_unchecked(
+                &qc_pp,
+                &(qc.0.clone(), huge_signers)
+            )
+            .is_err());
 
             let bad_sig = &sig1;
             assert!(
@@ -306,4 +559,78 @@ mod tests {
     fn test_quorum_certificate() {
         test_quorum_certificate!(BLSOverBN254CurveSignatureScheme);
     }
+
+    #[test]
+    fn test_generic_stake_amounts() {
+        use crate::qc::stake::FieldStake;
+        type BLS = BLSOverBN254CurveSignatureScheme;
+
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLS::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..3).map(|_| KeyPair::generate(&mut rng)).collect();
+        let msg = [72u8; 32];
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .map(|kp| {
+                BitVectorQC::<BLS>::sign(&agg_sig_pp, &msg.into(), kp.sign_key_ref(), &mut rng)
+                    .unwrap()
+            })
+            .collect();
+        let signers = bitvec![1, 1, 0];
+
+        // u64 stakes
+        type QCU64 = BitVectorQC<BLS, <BLS as SignatureScheme>::VerificationKey, u64>;
+        let mut qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: 1u64 << 62,
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(1u64 << 63),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let qc = QCU64::assemble(&qc_pp, &signers, &sigs[..2]).unwrap();
+        assert_eq!(QCU64::check(&qc_pp, &msg.into(), &qc).unwrap(), 1u64 << 63);
+        assert_eq!(
+            qc_pp,
+            bincode::deserialize(&bincode::serialize(&qc_pp).unwrap()).unwrap()
+        );
+        // overflowing sums are rejected instead of wrapping around
+        qc_pp.stake_entries[0].stake_amount = u64::MAX;
+        assert!(QCU64::check(&qc_pp, &msg.into(), &qc).is_err());
+        assert!(QCU64::trace_signers_unchecked(&qc_pp, &qc).is_err());
+
+        // field element stakes, as in the circuits
+        type Stake = FieldStake<ark_bn254::Fr>;
+        type QCField = BitVectorQC<BLS, <BLS as SignatureScheme>::VerificationKey, Stake>;
+        let mut qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: FieldStake(ark_bn254::Fr::from(5u8)),
+                })
+                .collect(),
+            ThresholdPolicy::SUPER_MAJORITY,
+            agg_sig_pp,
+        )
+        .unwrap();
+        assert_eq!(qc_pp.threshold, FieldStake(ark_bn254::Fr::from(11u8)));
+        let qc = QCField::assemble(&qc_pp, &bitvec![1, 1, 1], &sigs).unwrap();
+        assert_eq!(
+            QCField::check(&qc_pp, &msg.into(), &qc).unwrap(),
+            FieldStake(ark_bn254::Fr::from(15u8))
+        );
+        assert!(QCField::assemble(&qc_pp, &signers, &sigs[..2]).is_err());
+        assert_eq!(
+            qc_pp,
+            bincode::deserialize(&bincode::serialize(&qc_pp).unwrap()).unwrap()
+        );
+        // a sum wrapping around the modulus is rejected
+        qc_pp.stake_entries[0].stake_amount = FieldStake(-ark_bn254::Fr::from(1u8));
+        assert!(QCField::check(&qc_pp, &msg.into(), &qc).is_err());
+    }
 }
@@ -0,0 +1,472 @@
+//! Verification of a chain of QCs across epoch transitions, for light clients.
+//! Starting from a trusted stake table, each epoch's QC certifies the commitment of the next
+//! epoch's stake table. The QC message is bound to the current stake table commitment, see
+//! [`StakeTableBoundQC`], and its vote is [`epoch_transition_vote`] of the next commitment.
+//! The commitments are the public inputs binding the QC circuits to a stake table, so that a
+//! light client contract can follow the same chain.
+
+use crate::qc::{
+    bit_vector::{BitVectorQC, QCParams},
+    digest::{compute_stake_table_hash, stake_amount_to_field},
+    domain::StakeTableBoundQC,
+    keys::StakeKey,
+    stake::StakeAmount,
+    QuorumCertificate,
+};
+use ark_ff::PrimeField;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::{format, marker::PhantomData, vec::Vec};
+use ethereum_types::U256;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::{ParameterError, VerificationError};
+use jf_primitives::rescue::RescueParameter;
+use jf_primitives::signatures::AggregateableSignatureSchemes;
+use jf_relation::gadgets::SerializableEmulatedStruct;
+use jf_utils::canonical;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the vote certifying the next stake table.
+pub const EPOCH_TRANSITION_DOMAIN_TAG: &[u8] = b"HOTSHOT_EPOCH_TRANSITION_V1";
+
+/// Commitment of the QC parameters of an epoch, as recomputed by the QC circuits from their
+/// public inputs.
+#[derive(
+    Serialize,
+    Deserialize,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+    PartialEq,
+    Eq,
+    Debug,
+    Clone,
+    Copy,
+)]
+#[serde(bound = "")]
+pub struct QCParamsCommitment<F: PrimeField> {
+    /// Digest of the stake table, see [`compute_stake_table_hash`]
+    #[serde(with = "canonical")]
+    pub stake_table_digest: F,
+    /// Quorum threshold
+    #[serde(with = "canonical")]
+    pub threshold: F,
+}
+
+/// Commits to the stake entries and the threshold of an epoch.
+/// The keys are taken through [`StakeKey`] and hashed with their `SerializableEmulatedStruct`
+/// encoding, see [`compute_stake_table_hash`].
+/// * `qc_params` - the QC parameters
+/// * `returns` - an error if a stake amount or the threshold has more than
+///     `STAKE_AMOUNT_BIT_LEN` bits, the commitment otherwise.
+pub fn qc_params_commitment<F, K, V, P, S>(
+    qc_params: &QCParams<V, P, S>,
+) -> Result<QCParamsCommitment<F>, PrimitivesError>
+where
+    F: RescueParameter,
+    K: SerializableEmulatedStruct<F> + Clone,
+    V: StakeKey<K>,
+    S: StakeAmount,
+{
+    let stake_keys: Vec<K> = qc_params
+        .stake_entries()
+        .iter()
+        .map(|entry| entry.key::<K>().clone())
+        .collect();
+    let stake_amts = qc_params
+        .stake_entries()
+        .iter()
+        .map(|entry| stake_amount_to_field(&entry.stake_amount))
+        .collect::<Result<Vec<F>, _>>()?;
+    Ok(QCParamsCommitment {
+        stake_table_digest: compute_stake_table_hash(&stake_amts, &stake_keys),
+        threshold: stake_amount_to_field(qc_params.threshold())?,
+    })
+}
+
+/// The vote certifying that the next epoch's stake table has commitment `next_comm`.
+/// Validators sign it with `StakeTableBoundQC::sign_bound` under the current commitment.
+/// * `returns` - `tag || next_comm`, with the canonical (little-endian) serialization of the
+///     field elements.
+pub fn epoch_transition_vote<F: PrimeField>(
+    next_comm: &QCParamsCommitment<F>,
+) -> Result<Vec<u8>, PrimitivesError> {
+    let mut vote = EPOCH_TRANSITION_DOMAIN_TAG.to_vec();
+    next_comm
+        .serialize_compressed(&mut vote)
+        .map_err(|e| ParameterError(format!("failed to serialize the commitment: {}", e)))?;
+    Ok(vote)
+}
+
+/// The state trusted by a light client.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(bound(
+    serialize = "V: Serialize, P: Serialize, S: Serialize",
+    deserialize = "V: Deserialize<'de>, P: Deserialize<'de>, S: StakeAmount"
+))]
+pub struct TrustedState<V, P, F: PrimeField, S = U256> {
+    /// Number of epoch transitions since the initial state
+    pub epoch: u64,
+    /// Commitment of `qc_params`
+    pub comm: QCParamsCommitment<F>,
+    /// The QC parameters of the current epoch
+    pub qc_params: QCParams<V, P, S>,
+}
+
+/// Follows the epoch transitions of `BitVectorQC`s from a trusted state.
+/// * `K` - the keys of the stake table entries, hashed into the commitments, e.g.
+///     `ValidatorKeys`.
+/// * `F` - the field of the commitments.
+/// * `S` - the type of the stake amounts
+pub struct QcChainVerifier<A, K, F, S = U256>
+where
+    A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>,
+    F: PrimeField,
+{
+    state: TrustedState<K, A::PublicParameter, F, S>,
+    _phantom: PhantomData<A>,
+}
+
+impl<A, K, F, S> QcChainVerifier<A, K, F, S>
+where
+    A: AggregateableSignatureSchemes<MessageUnit = u8> + Serialize + for<'a> Deserialize<'a>,
+    K: StakeKey<A::VerificationKey>
+        + SerializableEmulatedStruct<F>
+        + Clone
+        + Serialize
+        + for<'a> Deserialize<'a>,
+    F: RescueParameter,
+    S: StakeAmount,
+{
+    /// Starts from trusted QC parameters.
+    pub fn new(qc_params: QCParams<K, A::PublicParameter, S>) -> Result<Self, PrimitivesError> {
+        qc_params.check_policy()?;
+        let comm = qc_params_commitment::<F, K, _, _, _>(&qc_params)?;
+        Ok(Self {
+            state: TrustedState {
+                epoch: 0,
+                comm,
+                qc_params,
+            },
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Starts from a trusted commitment.
+    /// * `comm` - the trusted commitment
+    /// * `qc_params` - the (untrusted) QC parameters opening `comm`
+    pub fn from_commitment(
+        comm: &QCParamsCommitment<F>,
+        qc_params: QCParams<K, A::PublicParameter, S>,
+    ) -> Result<Self, PrimitivesError> {
+        let verifier = Self::new(qc_params)?;
+        if verifier.state.comm != *comm {
+            return Err(VerificationError(
+                "the QC params do not match the trusted commitment".into(),
+            ));
+        }
+        Ok(verifier)
+    }
+
+    /// The current trusted state.
+    pub fn state(&self) -> &TrustedState<K, A::PublicParameter, F, S> {
+        &self.state
+    }
+
+    /// Follows a single epoch transition.
+    /// * `qc` - the QC of the current epoch certifying the next stake table
+    /// * `next_params` - the QC parameters of the next epoch
+    pub fn advance(
+        &mut self,
+        qc: &<BitVectorQC<A, K, S> as QuorumCertificate<A>>::QC,
+        next_params: QCParams<K, A::PublicParameter, S>,
+    ) -> Result<(), PrimitivesError> {
+        next_params.check_policy()?;
+        let next_comm = qc_params_commitment::<F, K, _, _, _>(&next_params)?;
+        BitVectorQC::<A, K, S>::check_bound(
+            &self.state.qc_params,
+            &self.state.comm,
+            &epoch_transition_vote(&next_comm)?,
+            qc,
+        )
+        .map_err(|e| {
+            VerificationError(format!(
+                "invalid QC for the transition from epoch {}: {}",
+                self.state.epoch, e
+            ))
+        })?;
+        self.state = TrustedState {
+            epoch: self.state.epoch + 1,
+            comm: next_comm,
+            qc_params: next_params,
+        };
+        Ok(())
+    }
+
+    /// Follows a sequence of epoch transitions.
+    /// * `transitions` - pairs of the QC of an epoch and the QC parameters of the next epoch
+    /// * `returns` - the final trusted state, or an error at the first invalid transition.
+    pub fn verify_chain(
+        mut self,
+        transitions: impl IntoIterator<
+            Item = (
+                <BitVectorQC<A, K, S> as QuorumCertificate<A>>::QC,
+                QCParams<K, A::PublicParameter, S>,
+            ),
+        >,
+    ) -> Result<TrustedState<K, A::PublicParameter, F, S>, PrimitivesError> {
+        for (qc, next_params) in transitions {
+            self.advance(&qc, next_params)?;
+        }
+        Ok(self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::{
+        bit_vector::StakeTableEntry, digest::STAKE_AMOUNT_BIT_LEN, keys::ValidatorKeys,
+        policy::ThresholdPolicy,
+    };
+    use ark_bn254::Fr as Fr254;
+    use ark_ed_on_bn254::EdwardsConfig;
+    use ark_std::vec;
+    use bitvec::prelude::*;
+    use ethereum_types::U256;
+    use jf_primitives::signatures::bls_over_bn254::{BLSOverBN254CurveSignatureScheme, KeyPair};
+    use jf_primitives::signatures::{schnorr, SignatureScheme};
+
+    type BLS = BLSOverBN254CurveSignatureScheme;
+    type Keys = ValidatorKeys<EdwardsConfig>;
+    type QC = BitVectorQC<BLS, Keys>;
+    type Verifier = QcChainVerifier<BLS, Keys, Fr254>;
+
+    #[test]
+    fn test_qc_chain() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLS::param_gen(Some(&mut rng)).unwrap();
+        // 4 epochs, each with 4 validators, the first two staying in the next epoch
+        let mut gen_key_pair = || {
+            (
+                KeyPair::generate(&mut rng),
+                schnorr::KeyPair::<EdwardsConfig>::generate(&mut rng).ver_key(),
+            )
+        };
+        let mut key_pairs: Vec<_> = (0..4).map(|_| gen_key_pair()).collect();
+        let mut epochs = vec![];
+        for _ in 0..4 {
+            epochs.push(key_pairs.clone());
+            key_pairs.truncate(2);
+            key_pairs.extend((0..2).map(|_| gen_key_pair()));
+        }
+        let params: Vec<_> = epochs
+            .iter()
+            .map(|kps| {
+                QCParams::new(
+                    kps.iter()
+                        .map(|(kp, schnorr_key)| StakeTableEntry {
+                            stake_key: Keys {
+                                bls_key: kp.ver_key(),
+                                schnorr_key: schnorr_key.clone(),
+                            },
+                            stake_amount: U256::from(10u8),
+                        })
+                        .collect(),
+                    ThresholdPolicy::SUPER_MAJORITY,
+                    agg_sig_pp,
+                )
+                .unwrap()
+            })
+            .collect();
+        let comms: Vec<_> = params
+            .iter()
+            .map(|pp| qc_params_commitment::<Fr254, Keys, _, _, _>(pp).unwrap())
+            .collect();
+        // the commitments are the public inputs of the QC circuits
+        let stake_amts = vec![Fr254::from(10u8); 4];
+        let keys: Vec<_> = params[0]
+            .stake_entries()
+            .iter()
+            .map(|entry| entry.stake_key.clone())
+            .collect();
+        assert_eq!(
+            comms[0].stake_table_digest,
+            compute_stake_table_hash(&stake_amts, &keys)
+        );
+        assert_eq!(comms[0].threshold, Fr254::from(27u8));
+        let signers = bitvec![1, 1, 1, 0];
+        let certify = |epoch: usize, next_comm: &QCParamsCommitment<Fr254>| {
+            let mut rng = jf_utils::test_rng();
+            let sigs: Vec<_> = epochs[epoch][..3]
+                .iter()
+                .map(|(kp, _)| {
+                    QC::sign_bound(
+                        &agg_sig_pp,
+                        &comms[epoch],
+                        &epoch_transition_vote(next_comm).unwrap(),
+                        kp.sign_key_ref(),
+                        &mut rng,
+                    )
+                    .unwrap()
+                })
+                .collect();
+            QC::assemble(&params[epoch], &signers, &sigs).unwrap()
+        };
+        let transitions: Vec<_> = (0..3)
+            .map(|i| (certify(i, &comms[i + 1]), params[i + 1].clone()))
+            .collect();
+
+        // happy path
+        let state = Verifier::new(params[0].clone())
+            .unwrap()
+            .verify_chain(transitions.clone())
+            .unwrap();
+        assert_eq!(state.epoch, 3);
+        assert_eq!(state.comm, comms[3]);
+        assert_eq!(state.qc_params, params[3]);
+        assert_eq!(
+            state,
+            bincode::deserialize(&bincode::serialize(&state).unwrap()).unwrap()
+        );
+        let state = Verifier::from_commitment(&comms[0], params[0].clone())
+            .unwrap()
+            .verify_chain(transitions.clone())
+            .unwrap();
+        assert_eq!(state.comm, comms[3]);
+        let mut verifier = Verifier::new(params[0].clone()).unwrap();
+        verifier
+            .advance(&transitions[0].0, transitions[0].1.clone())
+            .unwrap();
+        assert_eq!(verifier.state().epoch, 1);
+        assert_eq!(verifier.state().comm, comms[1]);
+
+        // bad paths
+        // wrong initial params
+        assert!(Verifier::from_commitment(&comms[1], params[0].clone()).is_err());
+        // skipping an epoch
+        assert!(Verifier::new(params[0].clone())
+            .unwrap()
+            .verify_chain(transitions[1..].to_vec())
+            .is_err());
+        // a next stake table not certified by the QC
+        let mut bad_transitions = transitions.clone();
+        bad_transitions[1].1 = params[3].clone();
+        assert!(Verifier::new(params[0].clone())
+            .unwrap()
+            .verify_chain(bad_transitions)
+            .is_err());
+        let mut bad_transitions = transitions.clone();
+        // the same stake entries under another threshold
+        bad_transitions[2].1 = QCParams::new(
+            params[3].stake_entries().to_vec(),
+            ThresholdPolicy::Absolute(U256::from(10u8)),
+            agg_sig_pp,
+        )
+        .unwrap();
+        assert!(Verifier::new(params[0].clone())
+            .unwrap()
+            .verify_chain(bad_transitions)
+            .is_err());
+        // a QC certifying the right table, formed under another stake table
+        let mut bad_transitions = transitions.clone();
+        bad_transitions[1].0 = certify(0, &comms[2]);
+        assert!(Verifier::new(params[0].clone())
+            .unwrap()
+            .verify_chain(bad_transitions)
+            .is_err());
+        // a failed transition leaves the state unchanged
+        let mut verifier = Verifier::new(params[0].clone()).unwrap();
+        assert!(verifier
+            .advance(&transitions[1].0, transitions[1].1.clone())
+            .is_err());
+        assert_eq!(verifier.state().epoch, 0);
+        assert_eq!(verifier.state().comm, comms[0]);
+        // stake amounts fitting in the circuits but a threshold that does not
+        let max_stake = (U256::one() << STAKE_AMOUNT_BIT_LEN) - 1;
+        let entries: Vec<_> = params[0]
+            .stake_entries()
+            .iter()
+            .map(|entry| StakeTableEntry {
+                stake_key: entry.stake_key.clone(),
+                stake_amount: max_stake,
+            })
+            .collect();
+        let fitting_params = QCParams::new(
+            entries.clone(),
+            ThresholdPolicy::Absolute(max_stake),
+            agg_sig_pp,
+        )
+        .unwrap();
+        assert!(qc_params_commitment::<Fr254, Keys, _, _, _>(&fitting_params).is_ok());
+        let oversized_params = QCParams::new(
+            entries,
+            ThresholdPolicy::Absolute(max_stake + 1),
+            agg_sig_pp,
+        )
+        .unwrap();
+        assert!(qc_params_commitment::<Fr254, Keys, _, _, _>(&oversized_params).is_err());
+        assert!(Verifier::new(oversized_params).is_err());
+    }
+
+    #[test]
+    fn test_qc_chain_u64_stakes() {
+        type U64Verifier = QcChainVerifier<BLS, Keys, Fr254, u64>;
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLS::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..4).map(|_| KeyPair::generate(&mut rng)).collect();
+        let params: Vec<_> = [&key_pairs[..3], &key_pairs[1..]]
+            .iter()
+            .map(|kps| {
+                QCParams::new(
+                    kps.iter()
+                        .map(|kp| StakeTableEntry {
+                            stake_key: Keys {
+                                bls_key: kp.ver_key(),
+                                schnorr_key: schnorr::KeyPair::<EdwardsConfig>::generate(&mut rng)
+                                    .ver_key(),
+                            },
+                            stake_amount: 10u64,
+                        })
+                        .collect(),
+                    ThresholdPolicy::SUPER_MAJORITY,
+                    agg_sig_pp,
+                )
+                .unwrap()
+            })
+            .collect();
+        let comms: Vec<_> = params
+            .iter()
+            .map(|pp| qc_params_commitment::<Fr254, Keys, _, _, _>(pp).unwrap())
+            .collect();
+        assert_eq!(comms[0].threshold, Fr254::from(21u8));
+        let sigs: Vec<_> = key_pairs[..3]
+            .iter()
+            .map(|kp| {
+                BitVectorQC::<BLS, Keys, u64>::sign_bound(
+                    &agg_sig_pp,
+                    &comms[0],
+                    &epoch_transition_vote(&comms[1]).unwrap(),
+                    kp.sign_key_ref(),
+                    &mut rng,
+                )
+                .unwrap()
+            })
+            .collect();
+        let qc =
+            BitVectorQC::<BLS, Keys, u64>::assemble(&params[0], &bitvec![1, 1, 1], &sigs).unwrap();
+
+        let state = U64Verifier::new(params[0].clone())
+            .unwrap()
+            .verify_chain([(qc.clone(), params[1].clone())])
+            .unwrap();
+        assert_eq!(state.epoch, 1);
+        assert_eq!(state.comm, comms[1]);
+        assert_eq!(
+            state,
+            bincode::deserialize(&bincode::serialize(&state).unwrap()).unwrap()
+        );
+        assert!(U64Verifier::new(params[0].clone())
+            .unwrap()
+            .verify_chain([(qc, params[0].clone())])
+            .is_err());
+    }
+}
@@ -0,0 +1,87 @@
+//! Native digest of the stake table, as recomputed by the QC circuits.
+//! Each stake table entry is hashed as the native elements of its key followed by its stake
+//! amount, see `QCKeyAggregateGadget::check_stake_table_digest`. The stake amounts are range
+//! checked in the circuits, so they are converted with [`stake_amount_to_field`], which rejects
+//! an amount of more than [`STAKE_AMOUNT_BIT_LEN`] bits instead of reducing it modulo the field.
+
+use crate::qc::stake::StakeAmount;
+use ark_ff::{BigInteger, PrimeField};
+use ark_std::{format, vec, vec::Vec};
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::rescue::{sponge::RescueCRHF, RescueParameter};
+use jf_relation::gadgets::SerializableEmulatedStruct;
+
+/// Bit length of the stake amounts, every stake amount is range checked in the circuits.
+pub const STAKE_AMOUNT_BIT_LEN: usize = 128;
+
+/// Converts a stake amount into a field element, or returns an error if it has more than
+/// `STAKE_AMOUNT_BIT_LEN` bits.
+pub fn stake_amount_to_field<F: PrimeField, S: StakeAmount>(
+    amount: &S,
+) -> Result<F, PrimitivesError> {
+    let value: F = amount.to_field()?;
+    if value.into_bigint().num_bits() as usize > STAKE_AMOUNT_BIT_LEN {
+        return Err(ParameterError(format!(
+            "stake amount {} exceeds {} bits",
+            amount, STAKE_AMOUNT_BIT_LEN
+        )));
+    }
+    Ok(value)
+}
+
+/// Digest a list of verification keys and their associated stake amounts
+/// * `stack_amts` - stake amounts
+/// * `keys` - list of verification keys
+pub fn compute_stake_table_hash<F: RescueParameter, T: SerializableEmulatedStruct<F>>(
+    stake_amts: &[F],
+    keys: &[T],
+) -> F {
+    let keys: Vec<Vec<F>> = keys
+        .iter()
+        .map(|key| key.serialize_to_native_elements())
+        .collect();
+    hash_stake_table_entries(stake_amts, &keys[..])
+}
+
+/// Digest the native elements of a list of stake table entries.
+/// Every key must be encoded with the same fixed layout, e.g. by a `SerializableEmulatedStruct`
+/// implementation, so that the hash input is parsed back unambiguously.
+/// * `stack_amts` - stake amounts
+/// * `keys` - the native elements of each stake key
+pub(crate) fn hash_stake_table_entries<F: RescueParameter>(stake_amts: &[F], keys: &[Vec<F>]) -> F {
+    let mut input_vec = vec![];
+    for (&amt, key) in stake_amts.iter().zip(keys.iter()) {
+        input_vec.extend_from_slice(key);
+        input_vec.push(amt);
+    }
+    RescueCRHF::sponge_with_bit_padding(&input_vec[..], 1)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::stake::FieldStake;
+    use ark_bn254::Fr as Fr254;
+    use ethereum_types::U256;
+
+    #[test]
+    fn test_stake_amount_to_field() {
+        let max = (U256::one() << STAKE_AMOUNT_BIT_LEN) - 1;
+        assert_eq!(
+            stake_amount_to_field::<Fr254, _>(&max).unwrap(),
+            Fr254::from(u128::MAX)
+        );
+        assert_eq!(
+            stake_amount_to_field::<Fr254, _>(&U256::zero()).unwrap(),
+            Fr254::from(0u8)
+        );
+        assert!(stake_amount_to_field::<Fr254, _>(&(max + 1)).is_err());
+        assert!(stake_amount_to_field::<Fr254, _>(&U256::MAX).is_err());
+        assert_eq!(
+            stake_amount_to_field::<Fr254, _>(&u128::MAX).unwrap(),
+            Fr254::from(u128::MAX)
+        );
+        assert!(stake_amount_to_field::<Fr254, _>(&FieldStake(-Fr254::from(1u8))).is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! Distributed key generation for Shamir-shared BLS keys over BN254.
+//! Joint-Feldman (Pedersen) DKG: every party deals a random polynomial of degree `threshold - 1`
+//! together with Feldman commitments to its coefficients in G2. Each party sums up the shares
+//! received from the qualified dealers, the group key is the sum of the constant term commitments.
+//! Party `j` holds the evaluation at `j + 1`.
+
+use crate::qc::{
+    convert_canonical,
+    threshold::{ThresholdBLSScheme, ThresholdQCParams},
+};
+use ark_bn254::{Fr, G2Affine, G2Projective};
+use ark_ec::{CurveGroup, Group};
+use ark_std::{
+    collections::BTreeSet,
+    format,
+    rand::{CryptoRng, RngCore},
+    vec::Vec,
+    UniformRand, Zero,
+};
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::{ParameterError, VerificationError};
+use jf_primitives::signatures::bls_over_bn254::{KeyPair, VerKey};
+use jf_primitives::signatures::SignatureScheme;
+use jf_utils::canonical;
+use serde::{Deserialize, Serialize};
+
+/// Parameters of a DKG instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkgParams {
+    /// Number of participants
+    pub num_parties: usize,
+    /// Number of shares required to sign
+    pub threshold: usize,
+}
+
+impl DkgParams {
+    /// Returns a new set of DKG parameters.
+    /// Return an error if `threshold` is zero or larger than `num_parties`.
+    pub fn new(num_parties: usize, threshold: usize) -> Result<Self, PrimitivesError> {
+        if threshold == 0 || threshold > num_parties {
+            return Err(ParameterError(format!(
+                "threshold {} should be within [1, {}]",
+                threshold, num_parties
+            )));
+        }
+        Ok(Self {
+            num_parties,
+            threshold,
+        })
+    }
+}
+
+/// Feldman commitments broadcast by a dealer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealingCommitment {
+    /// Index of the dealer
+    pub dealer: usize,
+    /// Commitments `g2^{a_k}` to the polynomial coefficients `a_0, ..., a_{threshold - 1}`
+    #[serde(with = "canonical")]
+    pub coef_comms: Vec<G2Affine>,
+}
+
+impl DealingCommitment {
+    /// Evaluate the committed polynomial in the exponent at the point of party `receiver`.
+    fn eval(&self, receiver: usize) -> G2Projective {
+        let x = party_point(receiver);
+        self.coef_comms
+            .iter()
+            .rev()
+            .fold(G2Projective::zero(), |acc, comm| acc * x + comm)
+    }
+}
+
+/// A secret share sent privately from a dealer to a receiver.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealingShare {
+    /// Index of the dealer
+    pub dealer: usize,
+    /// Index of the receiver
+    pub receiver: usize,
+    /// Evaluation of the dealer's polynomial at the receiver's point
+    #[serde(with = "canonical")]
+    pub share: Fr,
+}
+
+/// Evaluation point of party `index`.
+pub(crate) fn party_point(index: usize) -> Fr {
+    Fr::from(index as u64 + 1)
+}
+
+/// Deal a fresh random secret.
+/// * `params` - DKG parameters
+/// * `dealer` - index of the dealer
+/// * `returns` - the commitment to broadcast, and the shares to send to each party (indexed by receiver).
+pub fn deal<R: CryptoRng + RngCore>(
+    params: &DkgParams,
+    dealer: usize,
+    prng: &mut R,
+) -> Result<(DealingCommitment, Vec<DealingShare>), PrimitivesError> {
+    if dealer >= params.num_parties {
+        return Err(ParameterError(format!(
+            "dealer index {} out of range {}",
+            dealer, params.num_parties
+        )));
+    }
+    let coefs: Vec<Fr> = (0..params.threshold).map(|_| Fr::rand(prng)).collect();
+    let coef_comms = G2Projective::normalize_batch(
+        &coefs
+            .iter()
+            .map(|coef| G2Projective::generator() * coef)
+            .collect::<Vec<_>>(),
+    );
+    let shares = (0..params.num_parties)
+        .map(|receiver| {
+            let x = party_point(receiver);
+            DealingShare {
+                dealer,
+                receiver,
+                share: coefs.iter().rev().fold(Fr::zero(), |acc, c| acc * x + c),
+            }
+        })
+        .collect();
+    Ok((DealingCommitment { dealer, coef_comms }, shares))
+}
+
+/// Verify a received share against the dealer's commitment.
+/// A party should complain about (and the others disqualify) a dealer whose share fails.
+pub fn verify_share(
+    params: &DkgParams,
+    comm: &DealingCommitment,
+    share: &DealingShare,
+) -> Result<(), PrimitivesError> {
+    if comm.coef_comms.len() != params.threshold {
+        return Err(ParameterError(format!(
+            "dealer {} committed to {} coefficients, expecting {}",
+            comm.dealer,
+            comm.coef_comms.len(),
+            params.threshold
+        )));
+    }
+    if comm.dealer != share.dealer || share.receiver >= params.num_parties {
+        return Err(ParameterError(format!(
+            "share from dealer {} to party {} does not match commitment of dealer {}",
+            share.dealer, share.receiver, comm.dealer
+        )));
+    }
+    if comm.eval(share.receiver) != G2Projective::generator() * share.share {
+        return Err(VerificationError(format!(
+            "invalid share from dealer {} to party {}",
+            share.dealer, share.receiver
+        )));
+    }
+    Ok(())
+}
+
+/// Return an error if there are more qualified dealers than parties, if a dealer is not a party,
+/// or if a dealer appears more than once among the qualified dealers, since its contribution
+/// would be counted several times.
+fn check_qualified_dealers(
+    params: &DkgParams,
+    qualified: &[DealingCommitment],
+) -> Result<(), PrimitivesError> {
+    if qualified.len() > params.num_parties {
+        return Err(ParameterError(format!(
+            "{} qualified dealers for {} parties",
+            qualified.len(),
+            params.num_parties
+        )));
+    }
+    let mut dealers = BTreeSet::new();
+    for comm in qualified.iter() {
+        if comm.dealer >= params.num_parties {
+            return Err(ParameterError(format!(
+                "dealer index {} out of range {}",
+                comm.dealer, params.num_parties
+            )));
+        }
+        if !dealers.insert(comm.dealer) {
+            return Err(ParameterError(format!(
+                "dealer {} is qualified more than once",
+                comm.dealer
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Derive the signing key share of party `receiver` from the shares of the qualified dealers.
+/// * `qualified` - commitments of the qualified dealers, each party at most once
+/// * `shares` - shares received from the qualified dealers, in the same order
+pub fn derive_key_pair(
+    params: &DkgParams,
+    receiver: usize,
+    qualified: &[DealingCommitment],
+    shares: &[DealingShare],
+) -> Result<KeyPair, PrimitivesError> {
+    if qualified.is_empty() || qualified.len() != shares.len() {
+        return Err(ParameterError(format!(
+            "the number of qualified dealers {} != the number of shares {}",
+            qualified.len(),
+            shares.len()
+        )));
+    }
+    check_qualified_dealers(params, qualified)?;
+    let mut sk = Fr::zero();
+    for (comm, share) in qualified.iter().zip(shares.iter()) {
+        if share.receiver != receiver {
+            return Err(ParameterError(format!(
+                "share for party {} provided to party {}",
+                share.receiver, receiver
+            )));
+        }
+        verify_share(params, comm, share)?;
+        sk += share.share;
+    }
+    Ok(KeyPair::generate_with_sign_key(sk))
+}
+
+/// Derive the public QC parameters (group key and verification key of each share)
+/// from the commitments of the qualified dealers, each party at most once.
+pub fn derive_qc_params(
+    params: &DkgParams,
+    qualified: &[DealingCommitment],
+) -> Result<ThresholdQCParams, PrimitivesError> {
+    if qualified.is_empty() {
+        return Err(ParameterError("no qualified dealer".into()));
+    }
+    check_qualified_dealers(params, qualified)?;
+    if let Some(comm) = qualified
+        .iter()
+        .find(|comm| comm.coef_comms.len() != params.threshold)
+    {
+        return Err(ParameterError(format!(
+            "dealer {} committed to {} coefficients, expecting {}",
+            comm.dealer,
+            comm.coef_comms.len(),
+            params.threshold
+        )));
+    }
+    let group_key = qualified
+        .iter()
+        .fold(G2Projective::zero(), |acc, comm| acc + comm.coef_comms[0]);
+    let share_keys = (0..params.num_parties)
+        .map(|receiver| {
+            convert_canonical::<_, VerKey>(
+                &qualified
+                    .iter()
+                    .fold(G2Projective::zero(), |acc, comm| acc + comm.eval(receiver)),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ThresholdQCParams {
+        group_key: convert_canonical::<_, VerKey>(&group_key)?,
+        share_keys,
+        threshold: params.threshold,
+        agg_sig_pp: ThresholdBLSScheme::param_gen::<ark_std::rand::rngs::StdRng>(None)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dkg_shares() {
+        let mut rng = jf_utils::test_rng();
+        assert!(DkgParams::new(3, 0).is_err());
+        assert!(DkgParams::new(3, 4).is_err());
+        let params = DkgParams::new(4, 3).unwrap();
+        assert!(deal(&params, 4, &mut rng).is_err());
+
+        let (comm, shares) = deal(&params, 1, &mut rng).unwrap();
+        assert_eq!(comm.coef_comms.len(), 3);
+        assert_eq!(shares.len(), 4);
+        for share in shares.iter() {
+            assert!(verify_share(&params, &comm, share).is_ok());
+        }
+        // bad paths
+        let mut bad_share = shares[2].clone();
+        bad_share.share += Fr::from(1u64);
+        assert!(verify_share(&params, &comm, &bad_share).is_err());
+        let mut bad_share = shares[2].clone();
+        bad_share.receiver = 3;
+        assert!(verify_share(&params, &comm, &bad_share).is_err());
+        let mut bad_share = shares[2].clone();
+        bad_share.dealer = 0;
+        assert!(verify_share(&params, &comm, &bad_share).is_err());
+        let mut bad_comm = comm.clone();
+        bad_comm.coef_comms.pop();
+        assert!(verify_share(&params, &bad_comm, &shares[0]).is_err());
+
+        // a single dealer: the derived share keys match the derived key pairs
+        let qc_params = derive_qc_params(&params, &[comm.clone()]).unwrap();
+        for (receiver, share) in shares.iter().enumerate() {
+            let key_pair =
+                derive_key_pair(&params, receiver, &[comm.clone()], &[share.clone()]).unwrap();
+            assert_eq!(key_pair.ver_key(), qc_params.share_keys[receiver]);
+        }
+        assert!(derive_key_pair(&params, 0, &[comm.clone()], &[shares[1].clone()]).is_err());
+
+        // a dealer qualified twice is rejected
+        assert!(derive_qc_params(&params, &[comm.clone(), comm.clone()]).is_err());
+        assert!(derive_key_pair(
+            &params,
+            0,
+            &[comm.clone(), comm],
+            &[shares[0].clone(), shares[0].clone()]
+        )
+        .is_err());
+
+        // a dealer that is not a party is rejected
+        let mut bad_comm = comm.clone();
+        bad_comm.dealer = params.num_parties;
+        assert!(derive_qc_params(&params, &[bad_comm.clone()]).is_err());
+        let mut bad_share = shares[0].clone();
+        bad_share.dealer = params.num_parties;
+        assert!(derive_key_pair(&params, 0, &[bad_comm], &[bad_share]).is_err());
+
+        // more qualified dealers than parties are rejected
+        let comms: Vec<_> = (0..=params.num_parties)
+            .map(|dealer| DealingCommitment {
+                dealer,
+                coef_comms: comm.coef_comms.clone(),
+            })
+            .collect();
+        assert!(derive_qc_params(&params, &comms[..params.num_parties]).is_ok());
+        assert!(derive_qc_params(&params, &comms).is_err());
+    }
+}
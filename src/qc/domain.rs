@@ -0,0 +1,395 @@
+//! Domain separation for QC messages.
+//! Votes are bound to the stake table they are counted against, so that a QC formed
+//! under one stake table (epoch) cannot be replayed against another one.
+//! Arbitrary length messages are hashed into the fixed length QC message with [`hash_to_message`].
+//! The 32-byte digests are mapped into the message type of the QC scheme with [`FromDigest`].
+
+use crate::qc::QuorumCertificate;
+use ark_ff::PrimeField;
+use ark_serialize::CanonicalSerialize;
+use ark_std::{
+    format,
+    rand::{CryptoRng, RngCore},
+    vec::Vec,
+};
+use generic_array::GenericArray;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::signatures::SignatureScheme;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use typenum::{U1, U32};
+
+/// Domain tag prefixed to every stake-table-bound QC message.
+pub const QC_VOTE_DOMAIN_TAG: &[u8] = b"HOTSHOT_QC_VOTE_V1";
+
+/// Domain tag prefixed to every variable length message before hashing.
+pub const QC_MESSAGE_DOMAIN_TAG: &[u8] = b"HOTSHOT_QC_MESSAGE_V1";
+
+/// Hash an arbitrary length message into a 32-byte QC message.
+/// All HotShot components shall use this function to derive the signed message.
+/// * `msg` - the original message
+/// * `returns` - `keccak256(len(tag) || tag || msg)` where `tag = QC_MESSAGE_DOMAIN_TAG` and the length is 8-byte little-endian.
+pub fn hash_to_message(msg: &[u8]) -> GenericArray<u8, U32> {
+    let mut hasher = Keccak256::new();
+    hasher.update((QC_MESSAGE_DOMAIN_TAG.len() as u64).to_le_bytes());
+    hasher.update(QC_MESSAGE_DOMAIN_TAG);
+    hasher.update(msg);
+    hasher.finalize()
+}
+
+/// Conversion of a 32-byte digest, e.g. the output of [`hash_to_message`], into the fixed
+/// length message of a QC scheme.
+pub trait FromDigest {
+    /// Maps `digest` into the message.
+    fn from_digest(digest: GenericArray<u8, U32>) -> Self;
+}
+
+/// Byte-oriented schemes, e.g. BLS, sign the digest as is.
+impl FromDigest for GenericArray<u8, U32> {
+    fn from_digest(digest: GenericArray<u8, U32>) -> Self {
+        digest
+    }
+}
+
+/// Field-oriented schemes, e.g. Schnorr, sign the digest reduced into a single field element.
+impl<F: PrimeField> FromDigest for GenericArray<F, U1> {
+    fn from_digest(digest: GenericArray<u8, U32>) -> Self {
+        GenericArray::from([F::from_le_bytes_mod_order(&digest)])
+    }
+}
+
+/// Hash a domain tag, a stake table commitment and a vote into a 32-byte QC message.
+/// * `domain_tag` - domain separation tag
+/// * `st_comm` - commitment of the stake table, e.g. a `MerkleCommitment` or the output of `compute_stake_table_hash`
+/// * `vote` - the original message
+/// * `returns` - `keccak256(len(tag) || tag || len(comm) || comm || vote)`, lengths are 8-byte little-endian.
+pub fn stake_table_bound_message<C: CanonicalSerialize>(
+    domain_tag: &[u8],
+    st_comm: &C,
+    vote: &[u8],
+) -> Result<GenericArray<u8, U32>, PrimitivesError> {
+    let mut comm_bytes = Vec::new();
+    st_comm.serialize_compressed(&mut comm_bytes).map_err(|e| {
+        ParameterError(format!(
+            "failed to serialize the stake table commitment: {}",
+            e
+        ))
+    })?;
+    let mut hasher = Keccak256::new();
+    hasher.update((domain_tag.len() as u64).to_le_bytes());
+    hasher.update(domain_tag);
+    hasher.update((comm_bytes.len() as u64).to_le_bytes());
+    hasher.update(&comm_bytes);
+    hasher.update(vote);
+    Ok(hasher.finalize())
+}
+
+/// Signing and checking of votes bound to a stake table commitment.
+/// Implemented for every QC scheme whose messages can be derived from a digest, see [`FromDigest`].
+pub trait StakeTableBoundQC<A>: QuorumCertificate<A>
+where
+    A: SignatureScheme + Serialize + for<'a> Deserialize<'a>,
+    GenericArray<A::MessageUnit, <Self as QuorumCertificate<A>>::MessageLength>: FromDigest,
+{
+    /// Produces a partial signature on `vote` bound to the stake table `st_comm`.
+    /// * `agg_sig_pp` - public parameters for aggregate signature
+    /// * `st_comm` - commitment of the stake table the vote is counted against
+    /// * `vote` - the original message
+    /// * `sk` - user signing key
+    /// * `returns` - a "simple" signature
+    fn sign_bound<C: CanonicalSerialize, R: CryptoRng + RngCore>(
+        agg_sig_pp: &A::PublicParameter,
+        st_comm: &C,
+        vote: &[u8],
+        sk: &A::SigningKey,
+        prng: &mut R,
+    ) -> Result<A::Signature, PrimitivesError> {
+        let message = stake_table_bound_message(QC_VOTE_DOMAIN_TAG, st_comm, vote)?;
+        Self::sign(agg_sig_pp, &FromDigest::from_digest(message), sk, prng)
+    }
+
+    /// Checks a QC on `vote` bound to the stake table `st_comm`.
+    /// A QC produced under a different stake table commitment is rejected.
+    /// * `qc_vp` - public parameters for validating the QC
+    /// * `st_comm` - commitment of the stake table the QC is expected to be formed under
+    /// * `vote` - the original message
+    /// * `qc` - quorum certificate
+    /// * `returns` - the quorum size if the qc is valid, an error otherwise.
+    fn check_bound<C: CanonicalSerialize>(
+        qc_vp: &Self::QCVerifierParams,
+        st_comm: &C,
+        vote: &[u8],
+        qc: &Self::QC,
+    ) -> Result<Self::QuorumSize, PrimitivesError> {
+        let message = stake_table_bound_message(QC_VOTE_DOMAIN_TAG, st_comm, vote)?;
+        Self::check(qc_vp, &FromDigest::from_digest(message), qc)
+    }
+}
+
+impl<A, Q> StakeTableBoundQC<A> for Q
+where
+    A: SignatureScheme + Serialize + for<'a> Deserialize<'a>,
+    Q: QuorumCertificate<A>,
+    GenericArray<A::MessageUnit, Q::MessageLength>: FromDigest,
+{
+}
+
+/// Signing, checking and tracing of arbitrary length messages.
+/// Messages are first hashed with [`hash_to_message`].
+/// Implemented for every QC scheme whose messages can be derived from a digest, see [`FromDigest`].
+pub trait VariableLengthQC<A>: QuorumCertificate<A>
+where
+    A: SignatureScheme + Serialize + for<'a> Deserialize<'a>,
+    GenericArray<A::MessageUnit, <Self as QuorumCertificate<A>>::MessageLength>: FromDigest,
+{
+    /// Produces a partial signature on an arbitrary length message.
+    /// * `agg_sig_pp` - public parameters for aggregate signature
+    /// * `msg` - message to be signed
+    /// * `sk` - user signing key
+    /// * `returns` - a "simple" signature
+    fn sign_bytes<R: CryptoRng + RngCore>(
+        agg_sig_pp: &A::PublicParameter,
+        msg: &[u8],
+        sk: &A::SigningKey,
+        prng: &mut R,
+    ) -> Result<A::Signature, PrimitivesError> {
+        Self::sign(
+            agg_sig_pp,
+            &FromDigest::from_digest(hash_to_message(msg)),
+            sk,
+            prng,
+        )
+    }
+
+    /// Checks an aggregated signature over an arbitrary length message.
+    /// * `qc_vp` - public parameters for validating the QC
+    /// * `msg` - message to check the aggregated signature against
+    /// * `qc` - quorum certificate
+    /// * `returns` - the quorum size if the qc is valid, an error otherwise.
+    fn check_bytes(
+        qc_vp: &Self::QCVerifierParams,
+        msg: &[u8],
+        qc: &Self::QC,
+    ) -> Result<Self::QuorumSize, PrimitivesError> {
+        Self::check(qc_vp, &FromDigest::from_digest(hash_to_message(msg)), qc)
+    }
+
+    /// Trace the list of signers given a qc over an arbitrary length message.
+    fn trace_bytes(
+        qc_vp: &Self::QCVerifierParams,
+        msg: &[u8],
+        qc: &Self::QC,
+    ) -> Result<Vec<A::VerificationKey>, PrimitivesError> {
+        Self::trace(qc_vp, &FromDigest::from_digest(hash_to_message(msg)), qc)
+    }
+}
+
+impl<A, Q> VariableLengthQC<A> for Q
+where
+    A: SignatureScheme + Serialize + for<'a> Deserialize<'a>,
+    Q: QuorumCertificate<A>,
+    GenericArray<A::MessageUnit, Q::MessageLength>: FromDigest,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::bit_vector::{BitVectorQC, QCParams, StakeTableEntry};
+    use crate::qc::policy::ThresholdPolicy;
+    use crate::qc::schnorr::SchnorrQC;
+    use crate::stake_table::MerkleCommitment;
+    use ark_ed_on_bn254::EdwardsConfig;
+    use ark_std::{format, string::String, vec};
+    use bitvec::prelude::*;
+    use ethereum_types::U256;
+    use jf_primitives::signatures::bls_over_bn254::{BLSOverBN254CurveSignatureScheme, KeyPair};
+    use jf_primitives::signatures::schnorr;
+
+    type QC = BitVectorQC<BLSOverBN254CurveSignatureScheme>;
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_hash_to_message_vectors() {
+        // Test vectors shared with other HotShot components.
+        assert_eq!(
+            to_hex(&hash_to_message(b"")),
+            "45d0354eef1500771b63cfb435ccedc89fa40a4e89e141b4d939ae2c88440c27"
+        );
+        assert_eq!(
+            to_hex(&hash_to_message(b"hotshot")),
+            "a5ef6bcf4ac287a5362859299bb99a11cf520a81fb5fec0b34f57e14d326e7c8"
+        );
+        let msg: Vec<u8> = (0u8..100).collect();
+        assert_eq!(
+            to_hex(&hash_to_message(&msg)),
+            "ec833bc8da756b4d7b14abbcf83bafaa5eb4f43927308d02fd30fc1b675cc49a"
+        );
+    }
+
+    #[test]
+    fn test_variable_length_qc() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLSOverBN254CurveSignatureScheme::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..3).map(|_| KeyPair::generate(&mut rng)).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(1u8),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(2u8)),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let msg: Vec<u8> = (0u8..100).collect();
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .take(2)
+            .map(|kp| QC::sign_bytes(&agg_sig_pp, &msg, kp.sign_key_ref(), &mut rng).unwrap())
+            .collect();
+        let qc = QC::assemble(&qc_pp, bitvec![1, 1, 0].as_bitslice(), &sigs).unwrap();
+
+        // happy path
+        assert_eq!(QC::check_bytes(&qc_pp, &msg, &qc).unwrap(), U256::from(2u8));
+        assert!(QC::check(&qc_pp, &hash_to_message(&msg), &qc).is_ok());
+        assert_eq!(
+            QC::trace_bytes(&qc_pp, &msg, &qc).unwrap(),
+            vec![key_pairs[0].ver_key(), key_pairs[1].ver_key()]
+        );
+
+        // bad paths
+        assert!(QC::check_bytes(&qc_pp, &msg[..99], &qc).is_err());
+        assert!(QC::check_bytes(&qc_pp, b"", &qc).is_err());
+    }
+
+    #[test]
+    fn test_stake_table_bound_qc() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLSOverBN254CurveSignatureScheme::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..3).map(|_| KeyPair::generate(&mut rng)).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(1u8),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(2u8)),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let st_comm = MerkleCommitment::new(ark_bn254::Fr::from(7u64), 3, 3);
+        let other_st_comm = MerkleCommitment::new(ark_bn254::Fr::from(8u64), 3, 3);
+        let vote = b"view 42: block 0xabcd";
+
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .map(|kp| {
+                QC::sign_bound(&agg_sig_pp, &st_comm, vote, kp.sign_key_ref(), &mut rng).unwrap()
+            })
+            .collect();
+        let qc = QC::assemble(&qc_pp, bitvec![1, 1, 1].as_bitslice(), &sigs).unwrap();
+
+        // happy path
+        assert_eq!(
+            QC::check_bound(&qc_pp, &st_comm, vote, &qc).unwrap(),
+            U256::from(3u8)
+        );
+        // the bound message is what actually gets signed
+        let message = stake_table_bound_message(QC_VOTE_DOMAIN_TAG, &st_comm, vote).unwrap();
+        assert!(QC::check(&qc_pp, &message, &qc).is_ok());
+
+        // bad paths
+        // replay against a different stake table
+        assert!(QC::check_bound(&qc_pp, &other_st_comm, vote, &qc).is_err());
+        // a stake table hash of another type does not collide with the Merkle commitment
+        assert!(QC::check_bound(&qc_pp, st_comm.digest(), vote, &qc).is_err());
+        // a different vote
+        assert!(QC::check_bound(&qc_pp, &st_comm, b"view 43: block 0xabcd", &qc).is_err());
+        // the raw vote is not a valid message
+        let mut raw = [0u8; 32];
+        raw[..vote.len()].copy_from_slice(vote);
+        assert!(QC::check(&qc_pp, &raw.into(), &qc).is_err());
+        // domain tags separate the messages
+        assert_ne!(
+            message,
+            stake_table_bound_message(b"ANOTHER_TAG", &st_comm, vote).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_schnorr_domain_qc() {
+        type SchnorrDomainQC = SchnorrQC<EdwardsConfig, U1>;
+        let mut rng = jf_utils::test_rng();
+        let key_pairs: Vec<_> = (0..3)
+            .map(|_| schnorr::KeyPair::<EdwardsConfig>::generate(&mut rng))
+            .collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(1u8),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(2u8)),
+            (),
+        )
+        .unwrap();
+        let st_comm = MerkleCommitment::new(ark_bn254::Fr::from(7u64), 3, 3);
+        let other_st_comm = MerkleCommitment::new(ark_bn254::Fr::from(8u64), 3, 3);
+        let vote = b"view 42: block 0xabcd";
+        let signers = bitvec![1, 0, 1];
+
+        // stake table bound votes
+        let sigs: Vec<_> = [0, 2]
+            .iter()
+            .map(|&i| {
+                SchnorrDomainQC::sign_bound(
+                    &(),
+                    &st_comm,
+                    vote,
+                    key_pairs[i].sign_key_ref(),
+                    &mut rng,
+                )
+                .unwrap()
+            })
+            .collect();
+        let qc = SchnorrDomainQC::assemble(&qc_pp, &signers, &sigs).unwrap();
+        assert_eq!(
+            SchnorrDomainQC::check_bound(&qc_pp, &st_comm, vote, &qc).unwrap(),
+            U256::from(2u8)
+        );
+        let message = stake_table_bound_message(QC_VOTE_DOMAIN_TAG, &st_comm, vote).unwrap();
+        assert!(SchnorrDomainQC::check(&qc_pp, &FromDigest::from_digest(message), &qc).is_ok());
+        assert!(SchnorrDomainQC::check_bound(&qc_pp, &other_st_comm, vote, &qc).is_err());
+
+        // arbitrary length messages
+        let msg: Vec<u8> = (0u8..100).collect();
+        let sigs: Vec<_> = [0, 2]
+            .iter()
+            .map(|&i| {
+                SchnorrDomainQC::sign_bytes(&(), &msg, key_pairs[i].sign_key_ref(), &mut rng)
+                    .unwrap()
+            })
+            .collect();
+        let qc = SchnorrDomainQC::assemble(&qc_pp, &signers, &sigs).unwrap();
+        assert_eq!(
+            SchnorrDomainQC::check_bytes(&qc_pp, &msg, &qc).unwrap(),
+            U256::from(2u8)
+        );
+        assert_eq!(
+            SchnorrDomainQC::trace_bytes(&qc_pp, &msg, &qc).unwrap(),
+            vec![key_pairs[0].ver_key(), key_pairs[2].ver_key()]
+        );
+        assert!(SchnorrDomainQC::check_bytes(&qc_pp, &msg[..99], &qc).is_err());
+    }
+}
@@ -0,0 +1,489 @@
+//! Detection of validators signing conflicting messages for the same view.
+//! Votes are signed with `QuorumCertificate::sign` over [`vote_message`], which binds the vote to
+//! its view, or equivalently with `VariableLengthQC::sign_bytes` over [`vote_bytes`], see
+//! [`sign_vote`]. The same partial signatures can thus be both assembled into a QC and fed to the
+//! detector. They are collected per
+//! (view, signer), and a second vote with a different message yields a self-contained
+//! [`EquivocationEvidence`] usable for slashing.
+
+use crate::{
+    qc::{
+        bit_vector::{QCParams, StakeTableEntry},
+        domain::hash_to_message,
+        keys::StakeKey,
+        stake::StakeAmount,
+        QuorumCertificate,
+    },
+    stake_table::{EncodedPublicKey, MerkleCommitment, MerkleProof},
+};
+use ark_std::{
+    collections::HashMap,
+    format,
+    rand::{CryptoRng, RngCore},
+    vec::Vec,
+};
+use ethereum_types::U256;
+use generic_array::GenericArray;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::{ParameterError, VerificationError};
+use jf_primitives::signatures::{AggregateableSignatureSchemes, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use typenum::U32;
+
+/// A vote payload, e.g. the commitment of the proposal being voted for.
+pub type Message = [u8; 32];
+
+/// Prefix of the encoded view-bound vote, before hashing with [`hash_to_message`].
+pub const VOTE_DOMAIN_TAG: &[u8] = b"HOTSHOT_VOTE";
+
+/// The encoding of a vote for a view: `VOTE_DOMAIN_TAG || view || vote`, the view being 8-byte
+/// little-endian.
+/// * `view` - the view the vote is cast for
+/// * `vote` - the vote payload
+pub fn vote_bytes(view: u64, vote: &Message) -> Vec<u8> {
+    let mut bytes = VOTE_DOMAIN_TAG.to_vec();
+    bytes.extend_from_slice(&view.to_le_bytes());
+    bytes.extend_from_slice(vote);
+    bytes
+}
+
+/// The QC message signed by a vote, i.e. [`vote_bytes`] hashed with [`hash_to_message`].
+/// * `view` - the view the vote is cast for
+/// * `vote` - the vote payload
+pub fn vote_message(view: u64, vote: &Message) -> GenericArray<u8, U32> {
+    hash_to_message(&vote_bytes(view, vote))
+}
+
+/// Produces a partial signature on the vote `vote` for `view` with the QC signing path, i.e.
+/// `Q::sign` over [`vote_message`].
+/// * `Q` - the QC the votes are assembled into, whose partial signatures are signatures of `A`
+///     over the QC message
+/// * `agg_sig_pp` - public parameters for aggregate signature
+/// * `view` - the view the vote is cast for
+/// * `vote` - the vote payload
+/// * `sk` - user signing key
+pub fn sign_vote<A, Q, R>(
+    agg_sig_pp: &A::PublicParameter,
+    view: u64,
+    vote: &Message,
+    sk: &A::SigningKey,
+    prng: &mut R,
+) -> Result<A::Signature, PrimitivesError>
+where
+    A: AggregateableSignatureSchemes<MessageUnit = u8> + Serialize + for<'a> Deserialize<'a>,
+    Q: QuorumCertificate<A, MessageLength = U32>,
+    R: CryptoRng + RngCore,
+{
+    Q::sign(agg_sig_pp, &vote_message(view, vote), sk, prng)
+}
+
+/// Evidence that a validator signed two different messages for the same view.
+/// * `K` - the keys of the stake table entry, see [`StakeKey`]
+/// * `Sig` - the signatures
+/// * `S` - the type of the stake amounts
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EquivocationEvidence<K, Sig, S = U256> {
+    /// The view both messages were signed for
+    pub view: u64,
+    /// Index of the signer in the stake table
+    pub signer_index: usize,
+    /// Stake table entry of the signer
+    pub entry: StakeTableEntry<K, S>,
+    /// The first vote payload and its signature over [`vote_message`]
+    pub first: (Message, Sig),
+    /// The second, conflicting, vote payload and its signature over [`vote_message`]
+    pub second: (Message, Sig),
+}
+
+impl<K, Sig, S> EquivocationEvidence<K, Sig, S> {
+    /// Check that the votes differ and are both signed by the signer's key for `self.view`.
+    /// The signed messages are recomputed from the view, so that votes of different views
+    /// cannot be presented as an equivocation.
+    fn verify_signatures<A>(&self, agg_sig_pp: &A::PublicParameter) -> Result<(), PrimitivesError>
+    where
+        A: SignatureScheme<Signature = Sig, MessageUnit = u8>,
+        K: StakeKey<A::VerificationKey>,
+    {
+        if self.first.0 == self.second.0 {
+            return Err(VerificationError(
+                "the two messages of an equivocation should differ".into(),
+            ));
+        }
+        A::verify(
+            agg_sig_pp,
+            self.entry.key::<A::VerificationKey>(),
+            vote_message(self.view, &self.first.0),
+            &self.first.1,
+        )?;
+        A::verify(
+            agg_sig_pp,
+            self.entry.key::<A::VerificationKey>(),
+            vote_message(self.view, &self.second.0),
+            &self.second.1,
+        )
+    }
+
+    /// Verify the evidence against the QC parameters of the view.
+    /// * `qc_pp` - the QC parameters the votes were counted against
+    pub fn verify<A>(
+        &self,
+        qc_pp: &QCParams<K, A::PublicParameter, S>,
+    ) -> Result<(), PrimitivesError>
+    where
+        A: SignatureScheme<Signature = Sig, MessageUnit = u8>,
+        K: StakeKey<A::VerificationKey> + PartialEq,
+        S: StakeAmount,
+    {
+        if qc_pp.stake_entries().get(self.signer_index) != Some(&self.entry) {
+            return Err(VerificationError(format!(
+                "the signer is not the stake table entry {}",
+                self.signer_index
+            )));
+        }
+        self.verify_signatures::<A>(qc_pp.agg_sig_pp())
+    }
+}
+
+impl<K, Sig> EquivocationEvidence<K, Sig, U256> {
+    /// Verify the evidence against a stake table commitment.
+    /// The stake table stores `U256` stake amounts.
+    /// * `agg_sig_pp` - public parameters for aggregate signature
+    /// * `st_comm` - the stake table commitment
+    /// * `proof` - membership proof of the signer in the committed stake table
+    /// * `encode` - maps the signer's key to its encoding stored in the stake table
+    pub fn verify_with_commitment<A>(
+        &self,
+        agg_sig_pp: &A::PublicParameter,
+        st_comm: &MerkleCommitment,
+        proof: &MerkleProof,
+        encode: impl Fn(&K) -> EncodedPublicKey,
+    ) -> Result<(), PrimitivesError>
+    where
+        A: SignatureScheme<Signature = Sig, MessageUnit = u8>,
+        K: StakeKey<A::VerificationKey>,
+    {
+        proof
+            .verify(st_comm)
+            .map_err(|e| VerificationError(format!("invalid membership proof: {}", e)))?;
+        if *proof.index() != self.signer_index
+            || proof.get_key_value()
+                != Some((&encode(&self.entry.stake_key), &self.entry.stake_amount))
+        {
+            return Err(VerificationError(
+                "the membership proof does not match the signer".into(),
+            ));
+        }
+        self.verify_signatures::<A>(agg_sig_pp)
+    }
+}
+
+/// Collects votes and reports validators signing conflicting messages for the same view.
+/// * `K` - the keys of the stake table entries, see [`StakeKey`]
+/// * `S` - the type of the stake amounts
+pub struct EquivocationDetector<'a, A, K = <A as SignatureScheme>::VerificationKey, S = U256>
+where
+    A: AggregateableSignatureSchemes<MessageUnit = u8>,
+{
+    qc_pp: &'a QCParams<K, A::PublicParameter, S>,
+    votes: HashMap<(u64, usize), (Message, A::Signature)>,
+}
+
+impl<'a, A, K, S> EquivocationDetector<'a, A, K, S>
+where
+    A: AggregateableSignatureSchemes<MessageUnit = u8>,
+    K: StakeKey<A::VerificationKey>,
+    S: StakeAmount,
+{
+    /// Creates a detector for votes counted against `qc_pp`.
+    pub fn new(qc_pp: &'a QCParams<K, A::PublicParameter, S>) -> Self {
+        Self {
+            qc_pp,
+            votes: HashMap::new(),
+        }
+    }
+
+    /// Record a vote of the `signer_index`-th stake table entry.
+    /// * `view` - the view the vote is cast for
+    /// * `signer_index` - index of the signer in the stake table
+    /// * `message` - the vote payload
+    /// * `sig` - the partial signature produced by [`sign_vote`]
+    /// * `returns` - an error if the vote is invalid, an evidence if the signer already voted
+    ///     for a different message in this view, `None` otherwise.
+    pub fn add_vote(
+        &mut self,
+        view: u64,
+        signer_index: usize,
+        message: &Message,
+        sig: &A::Signature,
+    ) -> Result<Option<EquivocationEvidence<K, A::Signature, S>>, PrimitivesError>
+    where
+        K: Clone,
+    {
+        let entry = self
+            .qc_pp
+            .stake_entries()
+            .get(signer_index)
+            .ok_or_else(|| {
+                ParameterError(format!(
+                    "signer index {} out of range {}",
+                    signer_index,
+                    self.qc_pp.stake_entries().len()
+                ))
+            })?;
+        A::verify(
+            self.qc_pp.agg_sig_pp(),
+            entry.key::<A::VerificationKey>(),
+            vote_message(view, message),
+            sig,
+        )?;
+        match self.votes.get(&(view, signer_index)) {
+            Some((first_message, _)) if first_message == message => Ok(None),
+            Some((first_message, first_sig)) => Ok(Some(EquivocationEvidence {
+                view,
+                signer_index,
+                entry: entry.clone(),
+                first: (*first_message, first_sig.clone()),
+                second: (*message, sig.clone()),
+            })),
+            None => {
+                self.votes
+                    .insert((view, signer_index), (*message, sig.clone()));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Forget the votes of the views before `view`.
+    pub fn prune(&mut self, view: u64) {
+        self.votes.retain(|(v, _), _| *v >= view);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::{
+        bit_vector::BitVectorQC, domain::VariableLengthQC, keys::ValidatorKeys,
+        policy::ThresholdPolicy,
+    };
+    use crate::stake_table::{STVersion, StakeTable};
+    use ark_ed_on_bn254::EdwardsConfig;
+    use ark_ff::PrimeField;
+    use bitvec::prelude::*;
+    use jf_primitives::signatures::bls_over_bn254::{BLSOverBN254CurveSignatureScheme, KeyPair};
+    use jf_primitives::signatures::schnorr;
+    use jf_utils::to_bytes;
+
+    type BLS = BLSOverBN254CurveSignatureScheme;
+    type QC = BitVectorQC<BLS>;
+
+    #[test]
+    fn test_equivocation() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLS::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..3).map(|_| KeyPair::generate(&mut rng)).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(10u8),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(20u8)),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let msg1 = [1u8; 32];
+        let msg2 = [2u8; 32];
+        let mut sign = |view, msg: &Message, signer: usize| {
+            sign_vote::<BLS, QC, _>(
+                &agg_sig_pp,
+                view,
+                msg,
+                key_pairs[signer].sign_key_ref(),
+                &mut rng,
+            )
+            .unwrap()
+        };
+        let sig0_1 = sign(7, &msg1, 0);
+        let sig0_2 = sign(7, &msg2, 0);
+        let sig1_1 = sign(7, &msg1, 1);
+        let sig0_2_view8 = sign(8, &msg2, 0);
+        let sig0_1_view8 = sign(8, &msg1, 0);
+        // votes signed with `VariableLengthQC::sign_bytes` over the vote encoding are the same
+        let sig2_1 = QC::sign_bytes(
+            &agg_sig_pp,
+            &vote_bytes(7, &msg1),
+            key_pairs[2].sign_key_ref(),
+            &mut rng,
+        )
+        .unwrap();
+
+        let mut detector = EquivocationDetector::<BLS>::new(&qc_pp);
+        assert!(detector.add_vote(7, 0, &msg1, &sig0_1).unwrap().is_none());
+        assert!(detector.add_vote(7, 1, &msg1, &sig1_1).unwrap().is_none());
+        assert!(detector.add_vote(7, 2, &msg1, &sig2_1).unwrap().is_none());
+        // the votes form a QC over the vote message
+        let qc = QC::assemble(
+            &qc_pp,
+            &bitvec![1, 1, 1],
+            &[sig0_1.clone(), sig1_1.clone(), sig2_1],
+        )
+        .unwrap();
+        assert_eq!(
+            QC::check(&qc_pp, &vote_message(7, &msg1), &qc).unwrap(),
+            U256::from(30u8)
+        );
+        // same vote twice is fine
+        assert!(detector.add_vote(7, 0, &msg1, &sig0_1).unwrap().is_none());
+        // a different message in another view is fine
+        assert!(detector
+            .add_vote(8, 0, &msg2, &sig0_2_view8)
+            .unwrap()
+            .is_none());
+        // invalid votes are rejected
+        assert!(detector.add_vote(7, 1, &msg2, &sig0_2).is_err());
+        assert!(detector.add_vote(7, 3, &msg2, &sig0_2).is_err());
+        // a vote signed for another view is rejected
+        assert!(detector.add_vote(9, 0, &msg1, &sig0_1).is_err());
+        assert!(detector.add_vote(7, 0, &msg2, &sig0_2_view8).is_err());
+
+        let evidence = detector.add_vote(7, 0, &msg2, &sig0_2).unwrap().unwrap();
+        assert_eq!(evidence.view, 7);
+        assert_eq!(evidence.signer_index, 0);
+        assert_eq!(evidence.first, (msg1, sig0_1.clone()));
+        assert_eq!(evidence.second, (msg2, sig0_2.clone()));
+        assert!(evidence.verify::<BLS>(&qc_pp).is_ok());
+        assert_eq!(
+            evidence,
+            bincode::deserialize(&bincode::serialize(&evidence).unwrap()).unwrap()
+        );
+
+        // bad evidences
+        let mut bad = evidence.clone();
+        bad.signer_index = 1;
+        assert!(bad.verify::<BLS>(&qc_pp).is_err());
+        let mut bad = evidence.clone();
+        bad.second = bad.first.clone();
+        assert!(bad.verify::<BLS>(&qc_pp).is_err());
+        let mut bad = evidence.clone();
+        bad.second.1 = sig1_1;
+        assert!(bad.verify::<BLS>(&qc_pp).is_err());
+        let mut bad = evidence.clone();
+        bad.entry.stake_amount = U256::from(11u8);
+        assert!(bad.verify::<BLS>(&qc_pp).is_err());
+        // votes of different views relabeled as the same view
+        let mut relabeled = evidence.clone();
+        relabeled.second.1 = sig0_2_view8.clone();
+        assert!(relabeled.verify::<BLS>(&qc_pp).is_err());
+        relabeled.view = 8;
+        assert!(relabeled.verify::<BLS>(&qc_pp).is_err());
+        let mut relabeled = evidence.clone();
+        relabeled.view = 8;
+        assert!(relabeled.verify::<BLS>(&qc_pp).is_err());
+
+        // verify against a stake table commitment
+        let encode = |vk: &<BLS as SignatureScheme>::VerificationKey| {
+            let key = ark_bn254::Fr::from_le_bytes_mod_order(&to_bytes!(vk).unwrap());
+            EncodedPublicKey(to_bytes!(&key).unwrap())
+        };
+        let mut st = StakeTable::new(3);
+        for entry in qc_pp.stake_entries().iter() {
+            st.register(&encode(&entry.stake_key), entry.stake_amount)
+                .unwrap();
+        }
+        let st_comm = st.commitment(STVersion::PENDING);
+        let proof = st
+            .lookup(STVersion::PENDING, &encode(&key_pairs[0].ver_key()))
+            .unwrap();
+        let other_proof = st
+            .lookup(STVersion::PENDING, &encode(&key_pairs[1].ver_key()))
+            .unwrap();
+        assert!(evidence
+            .verify_with_commitment::<BLS>(&agg_sig_pp, &st_comm, &proof, encode)
+            .is_ok());
+        assert!(evidence
+            .verify_with_commitment::<BLS>(&agg_sig_pp, &st_comm, &other_proof, encode)
+            .is_err());
+        assert!(bad
+            .verify_with_commitment::<BLS>(&agg_sig_pp, &st_comm, &proof, encode)
+            .is_err());
+        assert!(relabeled
+            .verify_with_commitment::<BLS>(&agg_sig_pp, &st_comm, &proof, encode)
+            .is_err());
+        st.set_value(&encode(&key_pairs[0].ver_key()), U256::from(11u8))
+            .unwrap();
+        assert!(evidence
+            .verify_with_commitment::<BLS>(
+                &agg_sig_pp,
+                &st.commitment(STVersion::PENDING),
+                &proof,
+                encode
+            )
+            .is_err());
+
+        detector.prune(8);
+        assert!(detector.add_vote(7, 0, &msg2, &sig0_2).unwrap().is_none());
+        // a vote of view 7 cannot be replayed as a vote of view 8
+        assert!(detector.add_vote(8, 0, &msg1, &sig0_1).is_err());
+        let evidence = detector
+            .add_vote(8, 0, &msg1, &sig0_1_view8)
+            .unwrap()
+            .unwrap();
+        assert_eq!(evidence.view, 8);
+        assert!(evidence.verify::<BLS>(&qc_pp).is_ok());
+    }
+
+    #[test]
+    fn test_equivocation_validator_keys() {
+        type Keys = ValidatorKeys<EdwardsConfig>;
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLS::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..3).map(|_| KeyPair::generate(&mut rng)).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: Keys {
+                        bls_key: kp.ver_key(),
+                        schnorr_key: schnorr::KeyPair::<EdwardsConfig>::generate(&mut rng)
+                            .ver_key(),
+                    },
+                    stake_amount: 10u64,
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(20u64),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let msg1 = [1u8; 32];
+        let msg2 = [2u8; 32];
+        let mut sign = |msg: &Message, signer: usize| {
+            sign_vote::<BLS, BitVectorQC<BLS, Keys, u64>, _>(
+                &agg_sig_pp,
+                7,
+                msg,
+                key_pairs[signer].sign_key_ref(),
+                &mut rng,
+            )
+            .unwrap()
+        };
+        let sig0_1 = sign(&msg1, 0);
+        let sig0_2 = sign(&msg2, 0);
+        let sig1_2 = sign(&msg2, 1);
+
+        let mut detector = EquivocationDetector::<BLS, Keys, u64>::new(&qc_pp);
+        assert!(detector.add_vote(7, 0, &msg1, &sig0_1).unwrap().is_none());
+        assert!(detector.add_vote(7, 1, &msg1, &sig1_2).is_err());
+        let evidence = detector.add_vote(7, 0, &msg2, &sig0_2).unwrap().unwrap();
+        assert_eq!(evidence.entry, qc_pp.stake_entries()[0]);
+        assert!(evidence.verify::<BLS>(&qc_pp).is_ok());
+        let mut bad = evidence.clone();
+        bad.entry.stake_amount = 11;
+        assert!(bad.verify::<BLS>(&qc_pp).is_err());
+        let mut bad = evidence;
+        bad.second.1 = sig1_2;
+        assert!(bad.verify::<BLS>(&qc_pp).is_err());
+    }
+}
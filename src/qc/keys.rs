@@ -0,0 +1,163 @@
+//! Keys of the stake table entries.
+//! A validator may hold one key per signature scheme (e.g. BLS for consensus QCs and Schnorr for
+//! light client state signing) with a single stake amount. Each QC scheme picks its key through
+//! [`StakeKey`].
+
+use ark_ec::twisted_edwards::TECurveConfig;
+use derivative::Derivative;
+use jf_primitives::signatures::{bls_over_bn254, schnorr};
+use serde::{Deserialize, Serialize};
+
+/// Access to the verification key of type `V` among the keys of a stake table entry.
+pub trait StakeKey<V> {
+    /// Returns the key of type `V`.
+    fn key(&self) -> &V;
+}
+
+/// A single key is its own stake key.
+impl<V> StakeKey<V> for V {
+    fn key(&self) -> &V {
+        self
+    }
+}
+
+/// The keys of a validator: a BLS key for consensus and a Schnorr key for the light client.
+#[derive(Derivative, Serialize, Deserialize)]
+#[derivative(
+    Clone(bound = ""),
+    Debug(bound = ""),
+    PartialEq(bound = ""),
+    Eq(bound = "")
+)]
+#[serde(bound = "schnorr::VerKey<P>: Serialize + for<'a> Deserialize<'a>")]
+pub struct ValidatorKeys<P: TECurveConfig> {
+    /// Key verifying the consensus votes
+    pub bls_key: bls_over_bn254::VerKey,
+    /// Key verifying the light client state signatures
+    pub schnorr_key: schnorr::VerKey<P>,
+}
+
+impl<P: TECurveConfig> StakeKey<bls_over_bn254::VerKey> for ValidatorKeys<P> {
+    fn key(&self) -> &bls_over_bn254::VerKey {
+        &self.bls_key
+    }
+}
+
+impl<P: TECurveConfig> StakeKey<schnorr::VerKey<P>> for ValidatorKeys<P> {
+    fn key(&self) -> &schnorr::VerKey<P> {
+        &self.schnorr_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::{
+        bit_vector::{BitVectorQC, QCParams, StakeTableEntry},
+        policy::ThresholdPolicy,
+        schnorr::SchnorrQC,
+        QuorumCertificate,
+    };
+    use ark_ed_on_bn254::EdwardsConfig;
+    use ark_std::{vec, vec::Vec, UniformRand};
+    use bitvec::prelude::*;
+    use ethereum_types::U256;
+    use generic_array::GenericArray;
+    use jf_primitives::signatures::{
+        bls_over_bn254::BLSOverBN254CurveSignatureScheme, SignatureScheme,
+    };
+    use typenum::U3;
+
+    type Keys = ValidatorKeys<EdwardsConfig>;
+    type BLSQC = BitVectorQC<BLSOverBN254CurveSignatureScheme, Keys>;
+    type SchnorrKeysQC = SchnorrQC<EdwardsConfig, U3, Keys>;
+
+    #[test]
+    fn test_multi_key_qc() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLSOverBN254CurveSignatureScheme::param_gen(Some(&mut rng)).unwrap();
+        let bls_key_pairs: Vec<_> = (0..3)
+            .map(|_| bls_over_bn254::KeyPair::generate(&mut rng))
+            .collect();
+        let schnorr_key_pairs: Vec<_> = (0..3)
+            .map(|_| schnorr::KeyPair::<EdwardsConfig>::generate(&mut rng))
+            .collect();
+        let stake_entries: Vec<_> = bls_key_pairs
+            .iter()
+            .zip(schnorr_key_pairs.iter())
+            .map(|(bls_kp, schnorr_kp)| StakeTableEntry {
+                stake_key: Keys {
+                    bls_key: bls_kp.ver_key(),
+                    schnorr_key: schnorr_kp.ver_key(),
+                },
+                stake_amount: U256::from(5u8),
+            })
+            .collect();
+        assert_eq!(
+            stake_entries[1].key::<bls_over_bn254::VerKey>(),
+            &bls_key_pairs[1].ver_key()
+        );
+        assert_eq!(
+            stake_entries[1].key::<schnorr::VerKey<EdwardsConfig>>(),
+            &schnorr_key_pairs[1].ver_key()
+        );
+        let policy = ThresholdPolicy::Absolute(U256::from(10u8));
+        let bls_pp = QCParams::new(stake_entries.clone(), policy, agg_sig_pp).unwrap();
+        let schnorr_pp = QCParams::new(stake_entries, policy, ()).unwrap();
+        assert_eq!(
+            bls_pp,
+            bincode::deserialize(&bincode::serialize(&bls_pp).unwrap()).unwrap()
+        );
+        let signers = bitvec![1, 0, 1];
+
+        // consensus QC over the BLS keys
+        let msg = [72u8; 32];
+        let sigs: Vec<_> = [0, 2]
+            .iter()
+            .map(|&i| {
+                BLSQC::sign(
+                    &agg_sig_pp,
+                    &msg.into(),
+                    bls_key_pairs[i].sign_key_ref(),
+                    &mut rng,
+                )
+                .unwrap()
+            })
+            .collect();
+        let qc = BLSQC::assemble(&bls_pp, &signers, &sigs).unwrap();
+        assert_eq!(
+            BLSQC::check(&bls_pp, &msg.into(), &qc).unwrap(),
+            U256::from(10u8)
+        );
+        assert_eq!(
+            BLSQC::trace(&bls_pp, &msg.into(), &qc).unwrap(),
+            vec![bls_key_pairs[0].ver_key(), bls_key_pairs[2].ver_key()]
+        );
+
+        // light client QC over the Schnorr keys
+        let msg: GenericArray<_, U3> = (0..3).map(|_| ark_bn254::Fr::rand(&mut rng)).collect();
+        let sigs: Vec<_> = [0, 2]
+            .iter()
+            .map(|&i| {
+                SchnorrKeysQC::sign(&(), &msg, schnorr_key_pairs[i].sign_key_ref(), &mut rng)
+                    .unwrap()
+            })
+            .collect();
+        let qc = SchnorrKeysQC::assemble(&schnorr_pp, &signers, &sigs).unwrap();
+        assert_eq!(
+            SchnorrKeysQC::check(&schnorr_pp, &msg, &qc).unwrap(),
+            U256::from(10u8)
+        );
+        assert_eq!(
+            SchnorrKeysQC::trace(&schnorr_pp, &msg, &qc).unwrap(),
+            vec![
+                schnorr_key_pairs[0].ver_key(),
+                schnorr_key_pairs[2].ver_key()
+            ]
+        );
+
+        // a signature under the other signer's key is rejected
+        let bad_qc = (vec![sigs[1].clone(), sigs[0].clone()], qc.1);
+        assert!(SchnorrKeysQC::check(&schnorr_pp, &msg, &bad_qc).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Merging of partial `BitVectorQC`s over the same message.
+//! Votes received through different relays end up in different partial QCs, merging them
+//! is the building block for gossip-based aggregation trees.
+
+use crate::qc::{
+    bit_vector::{BitVectorQC, QCParams},
+    convert_canonical,
+    keys::StakeKey,
+    signer_set::SignerSet,
+    stake::StakeAmount,
+    QuorumCertificate,
+};
+use ark_bn254::G1Projective;
+use ark_std::{format, vec::Vec, Zero};
+use bitvec::prelude::*;
+use generic_array::GenericArray;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::signatures::{
+    bls_over_bn254::Signature, AggregateableSignatureSchemes, SignatureScheme,
+};
+use serde::{Deserialize, Serialize};
+
+/// Signatures forming a group, such that aggregated signatures can be combined.
+pub trait AggregateableSignature: Sized {
+    /// Returns the sum of `adds` minus the sum of `subs`.
+    fn combine_sigs(adds: &[Self], subs: &[Self]) -> Result<Self, PrimitivesError>;
+}
+
+impl AggregateableSignature for Signature {
+    fn combine_sigs(adds: &[Self], subs: &[Self]) -> Result<Self, PrimitivesError> {
+        let mut sum = G1Projective::zero();
+        for sig in adds {
+            sum += convert_canonical::<_, G1Projective>(sig)?;
+        }
+        for sig in subs {
+            sum -= convert_canonical::<_, G1Projective>(sig)?;
+        }
+        convert_canonical(&sum)
+    }
+}
+
+impl<A, K, S> BitVectorQC<A, K, S>
+where
+    A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>,
+    A::Signature: AggregateableSignature + Clone,
+    K: StakeKey<A::VerificationKey> + Clone + Serialize + for<'a> Deserialize<'a>,
+    S: StakeAmount,
+{
+    /// Combine two (partial) QCs without checking the result against the threshold.
+    /// The overlapping partial signatures are verified, as a wrong one would silently
+    /// corrupt the combined signature.
+    fn combine(
+        qc_pp: &QCParams<K, A::PublicParameter, S>,
+        message: &GenericArray<A::MessageUnit, <Self as QuorumCertificate<A>>::MessageLength>,
+        qc1: &<Self as QuorumCertificate<A>>::QC,
+        qc2: &<Self as QuorumCertificate<A>>::QC,
+        overlap_sigs: &[A::Signature],
+    ) -> Result<<Self as QuorumCertificate<A>>::QC, PrimitivesError> {
+        let signers1 = qc1.1.to_checked_bitvec(qc_pp.stake_entries().len())?;
+        let signers2 = qc2.1.to_checked_bitvec(qc_pp.stake_entries().len())?;
+        let overlap_keys: Vec<_> = qc_pp
+            .stake_entries()
+            .iter()
+            .zip(signers1.iter().by_vals().zip(signers2.iter().by_vals()))
+            .filter(|(_, (b1, b2))| *b1 && *b2)
+            .map(|(entry, _)| entry.key::<A::VerificationKey>())
+            .collect();
+        if overlap_keys.len() != overlap_sigs.len() {
+            return Err(ParameterError(format!(
+                "{} signers in both QCs but {} overlapping partial signatures provided",
+                overlap_keys.len(),
+                overlap_sigs.len(),
+            )));
+        }
+        for (key, sig) in overlap_keys.into_iter().zip(overlap_sigs.iter()) {
+            A::verify(qc_pp.agg_sig_pp(), key, message, sig)?;
+        }
+        let signers: BitVec = signers1
+            .iter()
+            .by_vals()
+            .zip(signers2.iter().by_vals())
+            .map(|(b1, b2)| b1 || b2)
+            .collect();
+        let sig = A::Signature::combine_sigs(&[qc1.0.clone(), qc2.0.clone()], overlap_sigs)?;
+
+        Ok((sig, SignerSet::compact(&signers)?))
+    }
+
+    /// Merge two (partial) QCs over the same message into a QC passing `check`.
+    /// * `qc_pp` - public parameters for generating the QC
+    /// * `message` - the message signed by both QCs
+    /// * `qc1`, `qc2` - the QCs to merge, e.g. produced by `assemble_partial`
+    /// * `overlap_sigs` - partial signatures of the signers present in both QCs, ordered by
+    ///     their index in the stake table. Should be empty if the signer sets are disjoint.
+    /// * `returns` - an error if an overlapping partial signature is missing or invalid, or if
+    ///     the merged QC does not pass `check`, the merged QC otherwise.
+    pub fn merge(
+        qc_pp: &QCParams<K, A::PublicParameter, S>,
+        message: &GenericArray<A::MessageUnit, <Self as QuorumCertificate<A>>::MessageLength>,
+        qc1: &<Self as QuorumCertificate<A>>::QC,
+        qc2: &<Self as QuorumCertificate<A>>::QC,
+        overlap_sigs: &[A::Signature],
+    ) -> Result<<Self as QuorumCertificate<A>>::QC, PrimitivesError> {
+        let merged = Self::combine(qc_pp, message, qc1, qc2, overlap_sigs)?;
+        Self::check(qc_pp, message, &merged)?;
+        Ok(merged)
+    }
+
+    /// Merge a list of (partial) QCs with pairwise disjoint signer sets into a QC passing
+    /// `check`. The intermediate merges may be under threshold.
+    pub fn merge_disjoint(
+        qc_pp: &QCParams<K, A::PublicParameter, S>,
+        message: &GenericArray<A::MessageUnit, <Self as QuorumCertificate<A>>::MessageLength>,
+        qcs: &[<Self as QuorumCertificate<A>>::QC],
+    ) -> Result<<Self as QuorumCertificate<A>>::QC, PrimitivesError> {
+        let (first, rest) = qcs
+            .split_first()
+            .ok_or_else(|| ParameterError("no QC to merge".into()))?;
+        let mut merged = first.clone();
+        for qc in rest {
+            merged = Self::combine(qc_pp, message, &merged, qc, &[])?;
+        }
+        Self::check(qc_pp, message, &merged)?;
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::bit_vector::StakeTableEntry;
+    use crate::qc::policy::ThresholdPolicy;
+    use ethereum_types::U256;
+    use jf_primitives::signatures::bls_over_bn254::{
+        BLSOverBN254CurveSignatureScheme, KeyPair, VerKey,
+    };
+
+    type QC<S> = BitVectorQC<BLSOverBN254CurveSignatureScheme, VerKey, S>;
+
+    #[test]
+    fn test_merge_qc() {
+        test_merge_qc_helper::<U256>();
+        test_merge_qc_helper::<u64>();
+    }
+
+    fn test_merge_qc_helper<S: StakeAmount>() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLSOverBN254CurveSignatureScheme::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..5).map(|_| KeyPair::generate(&mut rng)).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: S::from_u64(1),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(S::from_u64(4)),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let msg = [72u8; 32];
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .map(|kp| QC::<S>::sign(&agg_sig_pp, &msg.into(), kp.sign_key_ref(), &mut rng).unwrap())
+            .collect();
+
+        // partial QCs are under threshold
+        let qc1 = QC::<S>::assemble_partial(&qc_pp, &bitvec![1, 1, 0, 0, 0], &sigs[..2]).unwrap();
+        let qc2 = QC::<S>::assemble_partial(&qc_pp, &bitvec![0, 0, 1, 1, 0], &sigs[2..4]).unwrap();
+        let qc3 = QC::<S>::assemble_partial(&qc_pp, &bitvec![0, 1, 1, 1, 1], &sigs[1..]).unwrap();
+        assert!(QC::<S>::check(&qc_pp, &msg.into(), &qc1).is_err());
+        assert!(QC::<S>::check(&qc_pp, &msg.into(), &qc2).is_err());
+        assert!(QC::<S>::assemble(&qc_pp, &bitvec![1, 1, 0, 0, 0], &sigs[..2]).is_err());
+
+        // disjoint signer sets
+        let merged = QC::<S>::merge(&qc_pp, &msg.into(), &qc1, &qc2, &[]).unwrap();
+        assert_eq!(merged.1.to_bitvec().unwrap(), bitvec![1, 1, 1, 1, 0]);
+        assert_eq!(
+            QC::<S>::check(&qc_pp, &msg.into(), &merged).unwrap(),
+            S::from_u64(4)
+        );
+        assert_eq!(
+            merged,
+            QC::<S>::assemble(&qc_pp, &bitvec![1, 1, 1, 1, 0], &sigs[..4]).unwrap()
+        );
+        assert_eq!(
+            QC::<S>::merge_disjoint(&qc_pp, &msg.into(), &[qc1.clone(), qc2.clone()]).unwrap(),
+            merged
+        );
+        // the intermediate merges of `merge_disjoint` may be under threshold
+        let qc4 = QC::<S>::assemble_partial(&qc_pp, &bitvec![0, 0, 0, 0, 1], &sigs[4..]).unwrap();
+        let single_qcs: Vec<_> = (0..4)
+            .map(|i| {
+                let mut signers = bitvec![0; 5];
+                signers.set(i, true);
+                QC::<S>::assemble_partial(&qc_pp, &signers, &sigs[i..=i]).unwrap()
+            })
+            .collect();
+        assert_eq!(
+            QC::<S>::merge_disjoint(&qc_pp, &msg.into(), &single_qcs).unwrap(),
+            merged
+        );
+
+        // overlapping signer sets, the overlap (signer 1) is subtracted
+        let merged = QC::<S>::merge(&qc_pp, &msg.into(), &qc1, &qc3, &[sigs[1].clone()]).unwrap();
+        assert_eq!(
+            QC::<S>::check(&qc_pp, &msg.into(), &merged).unwrap(),
+            S::from_u64(5)
+        );
+
+        // bad paths
+        // overlapping signatures are required
+        assert!(QC::<S>::merge(&qc_pp, &msg.into(), &qc1, &qc3, &[]).is_err());
+        assert!(QC::<S>::merge_disjoint(&qc_pp, &msg.into(), &[qc1.clone(), qc3.clone()]).is_err());
+        assert!(QC::<S>::merge_disjoint(&qc_pp, &msg.into(), &[]).is_err());
+        // a wrong overlapping signature is rejected
+        assert!(QC::<S>::merge(&qc_pp, &msg.into(), &qc1, &qc3, &[sigs[0].clone()]).is_err());
+        // the merged QC is under threshold
+        assert!(QC::<S>::merge(&qc_pp, &msg.into(), &qc1, &qc4, &[]).is_err());
+        assert!(QC::<S>::merge_disjoint(&qc_pp, &msg.into(), &single_qcs[..3]).is_err());
+        // the partial QCs are not over the message
+        let bad_msg = [70u8; 32];
+        assert!(QC::<S>::merge(&qc_pp, &bad_msg.into(), &qc1, &qc2, &[]).is_err());
+        // wrong bit vector length
+        let bad_qc = (qc2.0.clone(), SignerSet::from(bitvec![0, 0, 1, 1]));
+        assert!(QC::<S>::merge(&qc_pp, &msg.into(), &qc1, &bad_qc, &[]).is_err());
+    }
+}
@@ -1,20 +1,48 @@
 //! Quorum Certificate traits and implementations.
 
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use ark_std::{
+    format,
     rand::{CryptoRng, RngCore},
     vec::Vec,
 };
 use bitvec::prelude::*;
 use generic_array::{ArrayLength, GenericArray};
 use jf_primitives::errors::PrimitivesError;
-use jf_primitives::signatures::AggregateableSignatureSchemes;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::signatures::SignatureScheme;
 use serde::{Deserialize, Serialize};
 
 pub mod bit_vector;
+pub mod chain;
+pub mod digest;
+pub mod dkg;
+pub mod domain;
+pub mod equivocation;
+pub mod keys;
+pub mod merge;
+pub mod policy;
+pub mod prepared;
+pub mod schnorr;
+pub mod signer_set;
+pub mod stake;
+pub mod threshold;
+pub mod timeout;
+
+/// Convert between two types sharing the same canonical encoding.
+/// Jellyfish BLS keys and signatures wrap a group element without exposing it,
+/// so the group arithmetic is done on the arkworks types they encode.
+pub(crate) fn convert_canonical<T: CanonicalSerialize, U: CanonicalDeserialize>(
+    t: &T,
+) -> Result<U, PrimitivesError> {
+    let mut bytes = Vec::new();
+    t.serialize_compressed(&mut bytes)
+        .map_err(|e| ParameterError(format!("{:?}", e)))?;
+    U::deserialize_compressed(&bytes[..]).map_err(|e| ParameterError(format!("{:?}", e)))
+}
 
 /// Trait for validating a QC built from different signatures on the same message
-pub trait QuorumCertificate<A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>>
-{
+pub trait QuorumCertificate<A: SignatureScheme + Serialize + for<'a> Deserialize<'a>> {
     /// Public parameters for generating the QC
     /// E.g: snark proving/verifying keys, list of (or pointer to) public keys stored in the smart contract.
     type QCProverParams: Serialize + for<'a> Deserialize<'a>;
@@ -33,7 +61,8 @@ pub trait QuorumCertificate<A: AggregateableSignatureSchemes + Serialize + for<'
     type QuorumSize;
 
     /// Produces a partial signature on a message with a single user signing key
-    /// NOTE: the original message (vote) should be prefixed with the hash of the stake table.
+    /// NOTE: the original message (vote) should be prefixed with the hash of the stake table,
+    /// see [`domain::StakeTableBoundQC::sign_bound`].
     /// * `agg_sig_pp` - public parameters for aggregate signature
     /// * `message` - message to be signed
     /// * `sk` - user signing key
@@ -0,0 +1,226 @@
+//! Quorum threshold policies.
+//! The absolute threshold of `QCParams` is derived from a policy and the total stake, the
+//! policy is kept along with the params so that all the verifiers agree on how it was computed,
+//! see `QCParams::new`.
+
+use crate::qc::stake::StakeAmount;
+use ark_std::format;
+use ethereum_types::U256;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use serde::{Deserialize, Serialize};
+
+/// How the quorum threshold is derived from the total stake.
+/// * `S` - the type of the stake amounts
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ThresholdPolicy<S = U256> {
+    /// A quorum holds more than (`strict`) or at least `numerator / denominator` of the total stake.
+    Fraction {
+        numerator: u64,
+        denominator: u64,
+        strict: bool,
+    },
+    /// A quorum holds at least the given stake.
+    Absolute(S),
+}
+
+impl<S> ThresholdPolicy<S> {
+    /// More than 2/3 of the total stake, as required by HotShot.
+    pub const SUPER_MAJORITY: Self = Self::Fraction {
+        numerator: 2,
+        denominator: 3,
+        strict: true,
+    };
+}
+
+impl<S: StakeAmount> ThresholdPolicy<S> {
+    /// Computes the minimal stake reaching the quorum.
+    /// * `total_stake` - the sum of all the stake amounts
+    /// * `returns` - an error if the policy is malformed or can never be met, the absolute
+    ///     threshold otherwise.
+    pub fn threshold(&self, total_stake: S) -> Result<S, PrimitivesError> {
+        let threshold = match *self {
+            Self::Fraction {
+                numerator,
+                denominator,
+                strict,
+            } => {
+                if strict && numerator == denominator {
+                    return Err(ParameterError(format!(
+                        "invalid strict threshold fraction {}/{}",
+                        numerator, denominator
+                    )));
+                }
+                let (quot, exact) = total_stake.mul_fraction(numerator, denominator)?;
+                // strict: smallest w such that w * den > total * num, i.e. floor(total * num / den) + 1
+                // non-strict: smallest w such that w * den >= total * num, i.e. ceil(total * num / den)
+                if strict || !exact {
+                    quot.try_add(&S::from_u64(1))?
+                } else {
+                    quot
+                }
+            }
+            Self::Absolute(threshold) => threshold,
+        };
+        if threshold > total_stake {
+            return Err(ParameterError(format!(
+                "threshold {} exceeds the total stake {}",
+                threshold, total_stake
+            )));
+        }
+        Ok(threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::{
+        bit_vector::{QCParams, StakeTableEntry},
+        stake::FieldStake,
+    };
+    use ark_bn254::Fr;
+    use ark_std::{vec, vec::Vec};
+
+    fn fraction(numerator: u64, denominator: u64, strict: bool) -> ThresholdPolicy {
+        ThresholdPolicy::Fraction {
+            numerator,
+            denominator,
+            strict,
+        }
+    }
+
+    #[test]
+    fn test_threshold_policy() {
+        // exact division
+        assert_eq!(
+            fraction(2, 3, false).threshold(U256::from(30u8)).unwrap(),
+            U256::from(20u8)
+        );
+        assert_eq!(
+            fraction(2, 3, true).threshold(U256::from(30u8)).unwrap(),
+            U256::from(21u8)
+        );
+        // rounding
+        assert_eq!(
+            fraction(2, 3, false).threshold(U256::from(31u8)).unwrap(),
+            U256::from(21u8)
+        );
+        assert_eq!(
+            ThresholdPolicy::SUPER_MAJORITY
+                .threshold(U256::from(31u8))
+                .unwrap(),
+            U256::from(21u8)
+        );
+        assert_eq!(
+            fraction(1, 2, true).threshold(U256::from(4u8)).unwrap(),
+            U256::from(3u8)
+        );
+        assert_eq!(
+            fraction(1, 1, false).threshold(U256::from(4u8)).unwrap(),
+            U256::from(4u8)
+        );
+        assert_eq!(
+            fraction(0, 1, true).threshold(U256::from(4u8)).unwrap(),
+            U256::from(1u8)
+        );
+        // no overflow with large stakes
+        assert_eq!(
+            fraction(u64::MAX - 1, u64::MAX, false)
+                .threshold(U256::MAX)
+                .unwrap(),
+            U256::MAX - U256::MAX / U256::from(u64::MAX)
+        );
+        assert_eq!(
+            ThresholdPolicy::SUPER_MAJORITY
+                .threshold(U256::MAX)
+                .unwrap(),
+            U256::MAX / 3 * 2 + 1
+        );
+        assert_eq!(
+            ThresholdPolicy::Absolute(U256::from(7u8))
+                .threshold(U256::from(7u8))
+                .unwrap(),
+            U256::from(7u8)
+        );
+
+        // bad paths
+        assert!(fraction(1, 0, false).threshold(U256::from(4u8)).is_err());
+        assert!(fraction(4, 3, false).threshold(U256::from(4u8)).is_err());
+        assert!(fraction(3, 3, true).threshold(U256::from(4u8)).is_err());
+        assert!(fraction(0, 1, true).threshold(U256::zero()).is_err());
+        assert!(ThresholdPolicy::Absolute(U256::from(8u8))
+            .threshold(U256::from(7u8))
+            .is_err());
+    }
+
+    #[test]
+    fn test_qc_params_from_policy() {
+        let entries = vec![
+            StakeTableEntry {
+                stake_key: 1u8,
+                stake_amount: U256::from(10u8),
+            },
+            StakeTableEntry {
+                stake_key: 2u8,
+                stake_amount: U256::from(20u8),
+            },
+        ];
+        let qc_pp = QCParams::new(entries.clone(), ThresholdPolicy::SUPER_MAJORITY, ()).unwrap();
+        assert_eq!(*qc_pp.threshold(), U256::from(21u8));
+        assert!(qc_pp.check_policy().is_ok());
+        assert_eq!(
+            qc_pp,
+            bincode::deserialize(&bincode::serialize(&qc_pp).unwrap()).unwrap()
+        );
+
+        // inconsistent threshold or policy are rejected on deserialization
+        let bad_pps = [
+            (U256::from(20u8), ThresholdPolicy::SUPER_MAJORITY),
+            (U256::from(21u8), fraction(2, 3, false)),
+            (
+                U256::from(31u8),
+                ThresholdPolicy::Absolute(U256::from(31u8)),
+            ),
+        ];
+        for (threshold, policy) in bad_pps {
+            let bytes = bincode::serialize(&(&entries, threshold, policy, ())).unwrap();
+            assert!(bincode::deserialize::<QCParams<u8, ()>>(&bytes).is_err());
+        }
+        let bytes = bincode::serialize(&(&entries, U256::from(21u8), qc_pp.policy(), ())).unwrap();
+        assert_eq!(
+            bincode::deserialize::<QCParams<u8, ()>>(&bytes).unwrap(),
+            qc_pp
+        );
+
+        // other stake types
+        let entries_u64: Vec<_> = entries
+            .iter()
+            .map(|entry| StakeTableEntry {
+                stake_key: entry.stake_key,
+                stake_amount: entry.stake_amount.as_u64(),
+            })
+            .collect();
+        let qc_pp_u64 =
+            QCParams::new(entries_u64.clone(), ThresholdPolicy::SUPER_MAJORITY, ()).unwrap();
+        assert_eq!(*qc_pp_u64.threshold(), 21u64);
+        let mut entries_u64 = entries_u64;
+        entries_u64[0].stake_amount = u64::MAX;
+        assert!(QCParams::new(entries_u64, ThresholdPolicy::SUPER_MAJORITY, ()).is_err());
+        let entries_field: Vec<_> = entries
+            .iter()
+            .map(|entry| StakeTableEntry {
+                stake_key: entry.stake_key,
+                stake_amount: FieldStake(Fr::from(entry.stake_amount.as_u64())),
+            })
+            .collect();
+        let qc_pp_field =
+            QCParams::new(entries_field, ThresholdPolicy::SUPER_MAJORITY, ()).unwrap();
+        assert_eq!(*qc_pp_field.threshold(), FieldStake(Fr::from(21u8)));
+
+        // total stake overflow
+        let mut entries = entries;
+        entries[0].stake_amount = U256::MAX;
+        assert!(QCParams::new(entries, ThresholdPolicy::SUPER_MAJORITY, ()).is_err());
+    }
+}
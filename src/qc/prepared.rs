@@ -0,0 +1,248 @@
+//! Prepared verifier parameters for `BitVectorQC`.
+//! The aggregate of all the stake keys and the total stake are computed once per stake table,
+//! a QC is then checked by either adding up the keys of the signers or subtracting the keys
+//! of the non-signers from the total, whichever involves fewer keys.
+
+use crate::qc::{
+    bit_vector::{BitVectorQC, QCParams},
+    convert_canonical,
+    stake::{sum_stakes, StakeAmount},
+    QuorumCertificate,
+};
+use ark_bn254::G2Projective;
+use ark_std::{format, vec, vec::Vec, Zero};
+use ethereum_types::U256;
+use generic_array::GenericArray;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::signatures::{bls_over_bn254::VerKey, AggregateableSignatureSchemes};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Verification keys forming a group, such that aggregated keys can be updated incrementally.
+pub trait AggregateableVerKey: Sized {
+    /// Returns the sum of `adds` minus the sum of `subs`.
+    fn combine_keys(adds: &[Self], subs: &[Self]) -> Result<Self, PrimitivesError>;
+}
+
+impl AggregateableVerKey for VerKey {
+    fn combine_keys(adds: &[Self], subs: &[Self]) -> Result<Self, PrimitivesError> {
+        let mut sum = G2Projective::zero();
+        for key in adds {
+            sum += convert_canonical::<_, G2Projective>(key)?;
+        }
+        for key in subs {
+            sum -= convert_canonical::<_, G2Projective>(key)?;
+        }
+        convert_canonical(&sum)
+    }
+}
+
+/// QC verifier parameters with a cached aggregate of all the stake keys.
+/// The cache is derived from the parameters and not serialized: deserialization recomputes it,
+/// so that it cannot diverge from the stake entries.
+#[derive(PartialEq, Debug)]
+pub struct PreparedQCParams<V, P, S = U256> {
+    params: QCParams<V, P, S>,
+    total_key: V,
+    total_stake: S,
+}
+
+impl<V, P, S> PreparedQCParams<V, P, S>
+where
+    V: AggregateableVerKey + Clone,
+    S: StakeAmount,
+{
+    /// Prepare the verifier parameters, should be called once per stake table.
+    pub fn new(params: QCParams<V, P, S>) -> Result<Self, PrimitivesError> {
+        let keys: Vec<V> = params
+            .stake_entries()
+            .iter()
+            .map(|entry| entry.stake_key.clone())
+            .collect();
+        let total_key = V::combine_keys(&keys, &[])?;
+        let total_stake = sum_stakes(
+            params
+                .stake_entries()
+                .iter()
+                .map(|entry| &entry.stake_amount),
+        )?;
+        Ok(Self {
+            params,
+            total_key,
+            total_stake,
+        })
+    }
+}
+
+impl<V, P, S: StakeAmount> PreparedQCParams<V, P, S> {
+    /// The underlying verifier parameters
+    pub fn params(&self) -> &QCParams<V, P, S> {
+        &self.params
+    }
+
+    /// Aggregate of all the stake keys
+    pub fn total_key(&self) -> &V {
+        &self.total_key
+    }
+
+    /// Sum of all the stake amounts
+    pub fn total_stake(&self) -> S {
+        self.total_stake
+    }
+}
+
+impl<V, P, S> Serialize for PreparedQCParams<V, P, S>
+where
+    QCParams<V, P, S>: Serialize,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        self.params.serialize(serializer)
+    }
+}
+
+impl<'de, V, P, S> Deserialize<'de> for PreparedQCParams<V, P, S>
+where
+    V: AggregateableVerKey + Clone,
+    S: StakeAmount,
+    QCParams<V, P, S>: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let params = QCParams::deserialize(deserializer)?;
+        Self::new(params).map_err(D::Error::custom)
+    }
+}
+
+impl<A, S> BitVectorQC<A, A::VerificationKey, S>
+where
+    A: AggregateableSignatureSchemes + Serialize + for<'a> Deserialize<'a>,
+    A::VerificationKey: AggregateableVerKey,
+    S: StakeAmount,
+{
+    /// Same as `check`, with the aggregate key derived from the prepared parameters.
+    /// * `qc_vp` - prepared parameters for validating the QC
+    /// * `message` - message to check the aggregated signature against
+    /// * `qc` - quorum certificate
+    /// * `returns` - the quorum size if the qc is valid, an error otherwise.
+    pub fn check_prepared(
+        qc_vp: &PreparedQCParams<A::VerificationKey, A::PublicParameter, S>,
+        message: &GenericArray<A::MessageUnit, <Self as QuorumCertificate<A>>::MessageLength>,
+        qc: &<Self as QuorumCertificate<A>>::QC,
+    ) -> Result<S, PrimitivesError> {
+        let (sig, signers) = qc;
+        let entries = qc_vp.params.stake_entries();
+        let signers = signers.to_checked_bitvec(entries.len())?;
+        // subtract the non-signers iff they are the minority
+        let subtract = signers.count_zeros() < signers.count_ones();
+        let mut keys = vec![];
+        let mut weight = S::zero();
+        for (entry, b) in entries.iter().zip(signers.iter()) {
+            if *b != subtract {
+                keys.push(entry.stake_key.clone());
+                weight = weight.try_add(&entry.stake_amount)?;
+            }
+        }
+        let (total_weight, agg_key) = if subtract {
+            (
+                qc_vp.total_stake.try_sub(&weight)?,
+                A::VerificationKey::combine_keys(&[qc_vp.total_key.clone()], &keys)?,
+            )
+        } else {
+            (weight, A::VerificationKey::combine_keys(&keys, &[])?)
+        };
+        if total_weight < *qc_vp.params.threshold() {
+            return Err(ParameterError(format!(
+                "total_weight {} less than threshold {}",
+                total_weight,
+                qc_vp.params.threshold(),
+            )));
+        }
+        A::multi_sig_verify(qc_vp.params.agg_sig_pp(), &[agg_key], message, sig)?;
+
+        Ok(total_weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::bit_vector::StakeTableEntry;
+    use crate::qc::policy::ThresholdPolicy;
+    use bitvec::prelude::*;
+    use jf_primitives::signatures::bls_over_bn254::{BLSOverBN254CurveSignatureScheme, KeyPair};
+    use jf_primitives::signatures::SignatureScheme;
+
+    type QC = BitVectorQC<BLSOverBN254CurveSignatureScheme>;
+
+    #[test]
+    fn test_check_prepared() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLSOverBN254CurveSignatureScheme::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..10).map(|_| KeyPair::generate(&mut rng)).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .enumerate()
+                .map(|(i, kp)| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(i + 1),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(10u8)),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let msg = [72u8; 32];
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .map(|kp| QC::sign(&agg_sig_pp, &msg.into(), kp.sign_key_ref(), &mut rng).unwrap())
+            .collect();
+        let prepared = PreparedQCParams::new(qc_pp.clone()).unwrap();
+        assert_eq!(prepared.total_stake(), U256::from(55u8));
+        assert_eq!(prepared.params(), &qc_pp);
+
+        // the cache is recomputed on deserialization
+        let bytes = bincode::serialize(&prepared).unwrap();
+        assert_eq!(bytes, bincode::serialize(&qc_pp).unwrap());
+        assert_eq!(
+            bincode::deserialize::<PreparedQCParams<_, _>>(&bytes).unwrap(),
+            prepared
+        );
+
+        // all signers, most signers (subtraction) and few signers (addition)
+        let signer_sets = [
+            BitVec::repeat(true, 10),
+            bitvec![1, 1, 0, 1, 1, 1, 1, 0, 1, 1],
+            bitvec![0, 0, 0, 0, 0, 0, 0, 0, 1, 1],
+        ];
+        for signers in signer_sets.iter() {
+            let active_sigs: Vec<_> = sigs
+                .iter()
+                .zip(signers.iter())
+                .filter(|(_, b)| **b)
+                .map(|(sig, _)| sig.clone())
+                .collect();
+            let qc = QC::assemble(&qc_pp, signers, &active_sigs).unwrap();
+            assert_eq!(
+                QC::check_prepared(&prepared, &msg.into(), &qc).unwrap(),
+                QC::check(&qc_pp, &msg.into(), &qc).unwrap(),
+            );
+            let bad_msg = [70u8; 32];
+            assert!(QC::check_prepared(&prepared, &bad_msg.into(), &qc).is_err());
+        }
+
+        // bad paths
+        let signers = bitvec![1, 1, 1, 1, 1, 1, 1, 1, 1, 0];
+        let qc = QC::assemble(&qc_pp, &signers, &sigs[..9]).unwrap();
+        // signers under threshold
+        let under = bitvec![1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+        assert!(QC::check_prepared(&prepared, &msg.into(), &(qc.0.clone(), under.into())).is_err());
+        // wrong bit vector length
+        let too_long = BitVec::repeat(true, 11);
+        assert!(
+            QC::check_prepared(&prepared, &msg.into(), &(qc.0.clone(), too_long.into())).is_err()
+        );
+        // the signature does not match the signer set
+        let other = BitVec::repeat(true, 10);
+        assert!(QC::check_prepared(&prepared, &msg.into(), &(qc.0, other.into())).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Implementation of a QC using Schnorr signatures over an embedded twisted Edwards curve.
+//! The signatures are not aggregated: the QC is the list of partial signatures together with
+//! the signer set. Verifying it in a circuit only takes native field arithmetic, see
+//! [`crate::circuit::qc_schnorr`].
+
+use crate::qc::{
+    bit_vector::QCParams, keys::StakeKey, signer_set::SignerSet, stake::StakeAmount,
+    QuorumCertificate,
+};
+use ark_ec::twisted_edwards::TECurveConfig;
+use ark_std::{
+    format,
+    marker::PhantomData,
+    rand::{CryptoRng, RngCore},
+    vec::Vec,
+};
+use bitvec::prelude::*;
+use ethereum_types::U256;
+use generic_array::{ArrayLength, GenericArray};
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::rescue::RescueParameter;
+use jf_primitives::signatures::{
+    schnorr::{SchnorrSignatureScheme, SignKey, Signature, VerKey},
+    SignatureScheme,
+};
+use serde::{Deserialize, Serialize};
+
+/// An implementation of QC using Schnorr signatures and a bit-vector.
+/// * `P` - the embedded curve, whose base field is the circuit field
+/// * `L` - the number of field elements of the message
+/// * `K` - the keys of a stake table entry, from which the Schnorr key is picked
+/// * `S` - the type of the stake amounts
+#[derive(Serialize, Deserialize)]
+pub struct SchnorrQC<P: TECurveConfig, L, K = VerKey<P>, S = U256>(PhantomData<(P, L, K, S)>);
+
+impl<F, P, L, K, S> SchnorrQC<P, L, K, S>
+where
+    F: RescueParameter,
+    P: TECurveConfig<BaseField = F>,
+    L: ArrayLength<F>,
+    K: StakeKey<VerKey<P>>,
+    S: StakeAmount,
+{
+    /// Returns the keys of the signers and their total weight, or an error if under threshold.
+    fn signers_and_weight(
+        qc_pp: &QCParams<K, (), S>,
+        signers: &BitSlice,
+    ) -> Result<(Vec<VerKey<P>>, S), PrimitivesError> {
+        if signers.len() != qc_pp.stake_entries().len() {
+            return Err(ParameterError(format!(
+                "bit vector len {} != the number of stake entries {}",
+                signers.len(),
+                qc_pp.stake_entries().len(),
+            )));
+        }
+        let mut ver_keys = Vec::new();
+        let mut total_weight = S::zero();
+        for (entry, b) in qc_pp.stake_entries().iter().zip(signers.iter()) {
+            if *b {
+                ver_keys.push(entry.key::<VerKey<P>>().clone());
+                total_weight = total_weight.try_add(&entry.stake_amount)?;
+            }
+        }
+        if total_weight < *qc_pp.threshold() {
+            return Err(ParameterError(format!(
+                "total_weight {} less than threshold {}",
+                total_weight,
+                qc_pp.threshold(),
+            )));
+        }
+        Ok((ver_keys, total_weight))
+    }
+}
+
+impl<F, P, L, K, S> QuorumCertificate<SchnorrSignatureScheme<P>> for SchnorrQC<P, L, K, S>
+where
+    F: RescueParameter,
+    P: TECurveConfig<BaseField = F>,
+    L: ArrayLength<F>,
+    K: StakeKey<VerKey<P>> + Clone + Serialize + for<'a> Deserialize<'a>,
+    S: StakeAmount,
+    SchnorrSignatureScheme<P>: Serialize + for<'a> Deserialize<'a>,
+{
+    type QCProverParams = QCParams<K, (), S>;
+
+    type QCVerifierParams = QCParams<K, (), S>;
+
+    /// The partial signatures of the signers, in the order of the stake table, together with
+    /// the signer set.
+    type QC = (Vec<Signature<P>>, SignerSet);
+    type MessageLength = L;
+    type QuorumSize = S;
+
+    fn sign<R: CryptoRng + RngCore>(
+        pp: &(),
+        message: &GenericArray<F, Self::MessageLength>,
+        sk: &SignKey<P::ScalarField>,
+        prng: &mut R,
+    ) -> Result<Signature<P>, PrimitivesError> {
+        SchnorrSignatureScheme::<P>::sign(pp, sk, message, prng)
+    }
+
+    fn assemble(
+        qc_pp: &Self::QCProverParams,
+        signers: &BitSlice,
+        sigs: &[Signature<P>],
+    ) -> Result<Self::QC, PrimitivesError> {
+        let (ver_keys, _) = Self::signers_and_weight(qc_pp, signers)?;
+        if ver_keys.len() != sigs.len() {
+            return Err(ParameterError(format!(
+                "the number of ver_keys {} != the number of partial signatures {}",
+                ver_keys.len(),
+                sigs.len(),
+            )));
+        }
+        Ok((sigs.to_vec(), SignerSet::compact(signers)?))
+    }
+
+    fn check(
+        qc_vp: &Self::QCVerifierParams,
+        message: &GenericArray<F, Self::MessageLength>,
+        qc: &Self::QC,
+    ) -> Result<Self::QuorumSize, PrimitivesError> {
+        let (sigs, signers) = qc;
+        let (ver_keys, total_weight) = Self::signers_and_weight(
+            qc_vp,
+            &signers.to_checked_bitvec(qc_vp.stake_entries().len())?,
+        )?;
+        if ver_keys.len() != sigs.len() {
+            return Err(ParameterError(format!(
+                "the number of signers {} != the number of signatures {}",
+                ver_keys.len(),
+                sigs.len(),
+            )));
+        }
+        for (vk, sig) in ver_keys.iter().zip(sigs.iter()) {
+            SchnorrSignatureScheme::<P>::verify(qc_vp.agg_sig_pp(), vk, message, sig)?;
+        }
+
+        Ok(total_weight)
+    }
+
+    fn trace(
+        qc_vp: &Self::QCVerifierParams,
+        message: &GenericArray<F, Self::MessageLength>,
+        qc: &Self::QC,
+    ) -> Result<Vec<VerKey<P>>, PrimitivesError> {
+        Self::check(qc_vp, message, qc)?;
+        Ok(
+            Self::signers_and_weight(qc_vp, &qc.1.to_checked_bitvec(qc_vp.stake_entries().len())?)?
+                .0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::{bit_vector::StakeTableEntry, policy::ThresholdPolicy};
+    use ark_ed_on_bn254::EdwardsConfig;
+    use ark_std::{vec, UniformRand};
+    use jf_primitives::signatures::schnorr::KeyPair;
+    use typenum::U3;
+
+    type QC = SchnorrQC<EdwardsConfig, U3>;
+
+    #[test]
+    fn test_schnorr_qc() {
+        let mut rng = jf_utils::test_rng();
+        let key_pairs: Vec<_> = (0..3)
+            .map(|_| KeyPair::<EdwardsConfig>::generate(&mut rng))
+            .collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .zip([3u8, 5, 7])
+                .map(|(kp, amount)| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(amount),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(10u8)),
+            (),
+        )
+        .unwrap();
+        let msg: GenericArray<_, U3> = (0..3).map(|_| ark_bn254::Fr::rand(&mut rng)).collect();
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .map(|kp| QC::sign(&(), &msg, kp.sign_key_ref(), &mut rng).unwrap())
+            .collect();
+
+        // happy path
+        let signers = bitvec![0, 1, 1];
+        let qc = QC::assemble(&qc_pp, &signers, &sigs[1..]).unwrap();
+        assert_eq!(QC::check(&qc_pp, &msg, &qc).unwrap(), U256::from(12u8));
+        assert_eq!(
+            QC::trace(&qc_pp, &msg, &qc).unwrap(),
+            vec![key_pairs[1].ver_key(), key_pairs[2].ver_key()]
+        );
+        assert_eq!(
+            qc,
+            bincode::deserialize(&bincode::serialize(&qc).unwrap()).unwrap()
+        );
+
+        // bad paths
+        // under threshold
+        assert!(QC::assemble(&qc_pp, &bitvec![1, 1, 0], &sigs[..2]).is_err());
+        // number of signatures unmatch
+        assert!(QC::assemble(&qc_pp, &signers, &sigs[..1]).is_err());
+        // wrong bit vector length
+        assert!(QC::assemble(&qc_pp, &bitvec![0, 1, 1, 0], &sigs[1..]).is_err());
+        // wrong message
+        let mut bad_msg = msg.clone();
+        bad_msg[0] += ark_bn254::Fr::from(1u8);
+        assert!(QC::check(&qc_pp, &bad_msg, &qc).is_err());
+        // signatures in the wrong order
+        let bad_qc = (vec![sigs[2].clone(), sigs[1].clone()], qc.1.clone());
+        assert!(QC::check(&qc_pp, &msg, &bad_qc).is_err());
+        // missing signature
+        let bad_qc = (vec![sigs[1].clone()], qc.1.clone());
+        assert!(QC::check(&qc_pp, &msg, &bad_qc).is_err());
+        // signature from a non-signer
+        let bad_qc = (vec![sigs[0].clone(), sigs[2].clone()], qc.1);
+        assert!(QC::check(&qc_pp, &msg, &bad_qc).is_err());
+    }
+}
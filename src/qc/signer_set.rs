@@ -0,0 +1,310 @@
+//! Compact encodings of the set of signers of a QC.
+
+use ark_std::{format, vec, vec::Vec};
+use bitvec::prelude::*;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use serde::{Deserialize, Serialize};
+
+/// Available encodings for a [`SignerSet`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignerSetEncoding {
+    /// One bit per stake entry
+    Bitmap,
+    /// Sorted indices of the signers
+    Signers,
+    /// Sorted indices of the non-signers
+    NonSigners,
+    /// Run-length encoding
+    RunLength,
+}
+
+impl SignerSetEncoding {
+    /// All the available encodings.
+    pub const ALL: [SignerSetEncoding; 4] = [
+        SignerSetEncoding::Bitmap,
+        SignerSetEncoding::Signers,
+        SignerSetEncoding::NonSigners,
+        SignerSetEncoding::RunLength,
+    ];
+}
+
+/// The set of signers of a QC, given as a subset of the stake table entries.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignerSet {
+    /// `bits[i]` is set iff the `i`-th entry signed.
+    Bitmap(BitVec),
+    /// Strictly increasing indices of the signers among `len` entries.
+    Signers { len: u32, indices: Vec<u32> },
+    /// Strictly increasing indices of the non-signers among `len` entries.
+    NonSigners { len: u32, indices: Vec<u32> },
+    /// Lengths of the alternating runs of non-signers and signers, starting with non-signers.
+    /// Only the first run may be empty.
+    RunLength { len: u32, runs: Vec<u32> },
+}
+
+impl From<BitVec> for SignerSet {
+    fn from(bits: BitVec) -> Self {
+        SignerSet::Bitmap(bits)
+    }
+}
+
+impl SignerSet {
+    /// Encode a bit vector with the given encoding.
+    pub fn encode(
+        signers: &BitSlice,
+        encoding: SignerSetEncoding,
+    ) -> Result<Self, PrimitivesError> {
+        let len = u32::try_from(signers.len())
+            .map_err(|_| ParameterError(format!("too many stake entries: {}", signers.len())))?;
+        Ok(match encoding {
+            SignerSetEncoding::Bitmap => SignerSet::Bitmap(signers.into()),
+            SignerSetEncoding::Signers => SignerSet::Signers {
+                len,
+                indices: signers.iter_ones().map(|i| i as u32).collect(),
+            },
+            SignerSetEncoding::NonSigners => SignerSet::NonSigners {
+                len,
+                indices: signers.iter_zeros().map(|i| i as u32).collect(),
+            },
+            SignerSetEncoding::RunLength => {
+                let mut runs = vec![0u32];
+                let mut current = false;
+                for bit in signers.iter().by_vals() {
+                    if bit != current {
+                        runs.push(0);
+                        current = bit;
+                    }
+                    *runs.last_mut().unwrap() += 1;
+                }
+                SignerSet::RunLength { len, runs }
+            }
+        })
+    }
+
+    /// Encode a bit vector with the encoding of the smallest serialized size.
+    pub fn compact(signers: &BitSlice) -> Result<Self, PrimitivesError> {
+        let mut candidates = vec![];
+        for encoding in SignerSetEncoding::ALL {
+            let candidate = Self::encode(signers, encoding)?;
+            let size = bincode::serialized_size(&candidate)
+                .map_err(|e| ParameterError(format!("{:?}", e)))?;
+            candidates.push((size, candidate));
+        }
+        // ties are broken in favor of the first encoding
+        Ok(candidates
+            .into_iter()
+            .min_by_key(|(size, _)| *size)
+            .unwrap()
+            .1)
+    }
+
+    /// The encoding used by this signer set.
+    pub fn encoding(&self) -> SignerSetEncoding {
+        match self {
+            SignerSet::Bitmap(_) => SignerSetEncoding::Bitmap,
+            SignerSet::Signers { .. } => SignerSetEncoding::Signers,
+            SignerSet::NonSigners { .. } => SignerSetEncoding::NonSigners,
+            SignerSet::RunLength { .. } => SignerSetEncoding::RunLength,
+        }
+    }
+
+    /// The number of stake entries covered by this signer set.
+    pub fn len(&self) -> usize {
+        match self {
+            SignerSet::Bitmap(bits) => bits.len(),
+            SignerSet::Signers { len, .. }
+            | SignerSet::NonSigners { len, .. }
+            | SignerSet::RunLength { len, .. } => *len as usize,
+        }
+    }
+
+    /// Returns true if the signer set covers no stake entry.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decode into a bit vector covering `num_entries` stake entries.
+    /// The length is compared before decoding, so that an untrusted signer set claiming a huge
+    /// length cannot force a large allocation.
+    /// Return an error if the length differs or the encoding is malformed.
+    pub fn to_checked_bitvec(&self, num_entries: usize) -> Result<BitVec, PrimitivesError> {
+        if self.len() != num_entries {
+            return Err(ParameterError(format!(
+                "signers bit vector len {} != the number of stake entries {}",
+                self.len(),
+                num_entries,
+            )));
+        }
+        self.to_bitvec()
+    }
+
+    /// Decode into a bit vector, `bits[i]` is set iff the `i`-th entry signed.
+    /// Return an error if the encoding is malformed.
+    /// The length is not bounded, use `to_checked_bitvec` on untrusted signer sets.
+    pub fn to_bitvec(&self) -> Result<BitVec, PrimitivesError> {
+        match self {
+            SignerSet::Bitmap(bits) => Ok(bits.clone()),
+            SignerSet::Signers { len, indices } => {
+                Self::indices_to_bitvec(*len as usize, indices, true)
+            }
+            SignerSet::NonSigners { len, indices } => {
+                Self::indices_to_bitvec(*len as usize, indices, false)
+            }
+            SignerSet::RunLength { len, runs } => {
+                let mut bits = BitVec::with_capacity(*len as usize);
+                for (i, &run) in runs.iter().enumerate() {
+                    if i > 0 && run == 0 {
+                        return Err(ParameterError(format!("empty run at position {}", i)));
+                    }
+                    if bits.len() + run as usize > *len as usize {
+                        return Err(ParameterError(format!(
+                            "runs exceed the number of stake entries {}",
+                            len
+                        )));
+                    }
+                    bits.resize(bits.len() + run as usize, i % 2 == 1);
+                }
+                if bits.len() != *len as usize {
+                    return Err(ParameterError(format!(
+                        "runs cover {} entries, expecting {}",
+                        bits.len(),
+                        len
+                    )));
+                }
+                Ok(bits)
+            }
+        }
+    }
+
+    fn indices_to_bitvec(
+        len: usize,
+        indices: &[u32],
+        value: bool,
+    ) -> Result<BitVec, PrimitivesError> {
+        if indices.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(ParameterError("indices are not strictly increasing".into()));
+        }
+        let mut bits = BitVec::repeat(!value, len);
+        for &i in indices {
+            if i as usize >= len {
+                return Err(ParameterError(format!("index {} out of range {}", i, len)));
+            }
+            bits.set(i as usize, value);
+        }
+        Ok(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signer_set_encodings() {
+        let patterns: Vec<BitVec> = vec![
+            bitvec![],
+            bitvec![0, 0, 0],
+            bitvec![1, 1, 1],
+            bitvec![0, 1, 1, 0, 1],
+            bitvec![1, 0, 0, 1, 1, 1, 0],
+            BitVec::repeat(true, 1000),
+        ];
+        for bits in patterns.iter() {
+            for encoding in SignerSetEncoding::ALL {
+                let set = SignerSet::encode(bits, encoding).unwrap();
+                assert_eq!(set.encoding(), encoding);
+                assert_eq!(set.len(), bits.len());
+                assert_eq!(&set.to_bitvec().unwrap(), bits);
+                assert_eq!(
+                    set,
+                    bincode::deserialize(&bincode::serialize(&set).unwrap()).unwrap()
+                );
+            }
+            assert_eq!(
+                &SignerSet::compact(bits).unwrap().to_bitvec().unwrap(),
+                bits
+            );
+        }
+
+        // the smallest encoding is chosen
+        let mut bits = BitVec::repeat(true, 3000);
+        bits.set(17, false);
+        bits.set(2001, false);
+        let set = SignerSet::compact(&bits).unwrap();
+        assert_eq!(set.encoding(), SignerSetEncoding::NonSigners);
+        assert!(
+            bincode::serialized_size(&set).unwrap()
+                < bincode::serialized_size(&SignerSet::from(bits.clone())).unwrap()
+        );
+        let mut bits = BitVec::repeat(false, 3000);
+        bits.set(5, true);
+        assert_eq!(
+            SignerSet::compact(&bits).unwrap().encoding(),
+            SignerSetEncoding::Signers
+        );
+        let bits: BitVec = (0..3000).map(|i| (i / 500) % 2 == 1).collect();
+        assert_eq!(
+            SignerSet::compact(&bits).unwrap().encoding(),
+            SignerSetEncoding::RunLength
+        );
+
+        // malformed encodings
+        let bad_sets = [
+            SignerSet::Signers {
+                len: 3,
+                indices: vec![1, 1],
+            },
+            SignerSet::Signers {
+                len: 3,
+                indices: vec![2, 1],
+            },
+            SignerSet::NonSigners {
+                len: 3,
+                indices: vec![3],
+            },
+            SignerSet::RunLength {
+                len: 3,
+                runs: vec![1, 1],
+            },
+            SignerSet::RunLength {
+                len: 3,
+                runs: vec![1, 0, 2],
+            },
+            SignerSet::RunLength {
+                len: 3,
+                runs: vec![2, 2],
+            },
+        ];
+        for set in bad_sets.iter() {
+            assert!(set.to_bitvec().is_err());
+            assert!(set.to_checked_bitvec(3).is_err());
+        }
+
+        // the length is checked before decoding
+        let bits = bitvec![0, 1, 1, 0, 1];
+        for encoding in SignerSetEncoding::ALL {
+            let set = SignerSet::encode(&bits, encoding).unwrap();
+            assert_eq!(set.to_checked_bitvec(5).unwrap(), bits);
+            assert!(set.to_checked_bitvec(4).is_err());
+            assert!(set.to_checked_bitvec(6).is_err());
+        }
+        let huge_sets = [
+            SignerSet::Signers {
+                len: u32::MAX,
+                indices: vec![],
+            },
+            SignerSet::NonSigners {
+                len: u32::MAX,
+                indices: vec![],
+            },
+            SignerSet::RunLength {
+                len: u32::MAX,
+                runs: vec![u32::MAX],
+            },
+        ];
+        for set in huge_sets.iter() {
+            assert!(set.to_checked_bitvec(5).is_err());
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Stake amounts of the stake table entries.
+//! QCs are generic over the type of the stake amounts, as long as they can be summed without
+//! overflow, compared, and converted to the field elements used by the circuits.
+//! The threshold policies only scale stake amounts by a fraction, see
+//! [`StakeAmount::mul_fraction`].
+
+use ark_ff::{BigInteger, PrimeField};
+use ark_std::{
+    cmp::Ordering,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    format,
+};
+use ethereum_types::{U256, U512};
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_utils::canonical;
+use serde::{Deserialize, Serialize};
+
+/// Trait for stake amounts.
+pub trait StakeAmount: Copy + Ord + Debug + Display + Serialize + for<'a> Deserialize<'a> {
+    /// The zero stake.
+    fn zero() -> Self;
+
+    /// Returns `self + rhs`, or an error on overflow.
+    fn try_add(&self, rhs: &Self) -> Result<Self, PrimitivesError>;
+
+    /// Returns `self - rhs`, or an error on underflow.
+    fn try_sub(&self, rhs: &Self) -> Result<Self, PrimitivesError>;
+
+    /// Returns `floor(self * numerator / denominator)` and whether the division is exact, or an
+    /// error if `denominator` is zero or less than `numerator`. The result is at most `self`.
+    fn mul_fraction(
+        &self,
+        numerator: u64,
+        denominator: u64,
+    ) -> Result<(Self, bool), PrimitivesError>;
+
+    /// Converts a `u64` to a stake.
+    fn from_u64(value: u64) -> Self;
+
+    /// Converts the stake to a field element, or returns an error if it does not fit.
+    fn to_field<F: PrimeField>(&self) -> Result<F, PrimitivesError>;
+}
+
+/// Sums stake amounts, or returns an error on overflow.
+pub fn sum_stakes<'a, S: StakeAmount + 'a>(
+    stakes: impl IntoIterator<Item = &'a S>,
+) -> Result<S, PrimitivesError> {
+    let mut sum = S::zero();
+    for stake in stakes {
+        sum = sum.try_add(stake)?;
+    }
+    Ok(sum)
+}
+
+/// Returns an error if `numerator / denominator` is not a fraction in `[0, 1]`.
+fn check_fraction(numerator: u64, denominator: u64) -> Result<(), PrimitivesError> {
+    if denominator == 0 || numerator > denominator {
+        return Err(ParameterError(format!(
+            "invalid fraction {}/{}",
+            numerator, denominator
+        )));
+    }
+    Ok(())
+}
+
+/// `StakeAmount::mul_fraction` over `u128`.
+fn mul_fraction_u128(
+    value: u128,
+    numerator: u64,
+    denominator: u64,
+) -> Result<(u128, bool), PrimitivesError> {
+    check_fraction(numerator, denominator)?;
+    let (numerator, denominator) = (u128::from(numerator), u128::from(denominator));
+    // value * n / d = quot * n + rem * n / d, where quot * n <= value and rem * n < 2^128
+    let (quot, rem) = (value / denominator, value % denominator);
+    let prod = rem * numerator;
+    Ok((
+        quot * numerator + prod / denominator,
+        prod % denominator == 0,
+    ))
+}
+
+/// Length of a little-endian byte string without its trailing zeros.
+fn trimmed_len(bytes: &[u8]) -> usize {
+    bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1)
+}
+
+/// Converts a little-endian integer to a field element, or returns an error if not less than
+/// the modulus.
+fn le_bytes_to_field<F: PrimeField>(bytes: &[u8]) -> Result<F, PrimitivesError> {
+    let f = F::from_le_bytes_mod_order(bytes);
+    let f_bytes = f.into_bigint().to_bytes_le();
+    if bytes[..trimmed_len(bytes)] != f_bytes[..trimmed_len(&f_bytes)] {
+        return Err(ParameterError(format!(
+            "stake amount overflows the field of {} bits",
+            F::MODULUS_BIT_SIZE
+        )));
+    }
+    Ok(f)
+}
+
+macro_rules! impl_stake_amount_for_uint {
+    ($t:ty) => {
+        // the conversions from and to `u128` are the identity for `u128`
+        #[allow(clippy::useless_conversion)]
+        impl StakeAmount for $t {
+            fn zero() -> Self {
+                0
+            }
+
+            fn try_add(&self, rhs: &Self) -> Result<Self, PrimitivesError> {
+                <$t>::checked_add(*self, *rhs)
+                    .ok_or_else(|| ParameterError(format!("stake overflow: {} + {}", self, rhs)))
+            }
+
+            fn try_sub(&self, rhs: &Self) -> Result<Self, PrimitivesError> {
+                <$t>::checked_sub(*self, *rhs)
+                    .ok_or_else(|| ParameterError(format!("stake underflow: {} - {}", self, rhs)))
+            }
+
+            fn mul_fraction(
+                &self,
+                numerator: u64,
+                denominator: u64,
+            ) -> Result<(Self, bool), PrimitivesError> {
+                let (quot, exact) = mul_fraction_u128(u128::from(*self), numerator, denominator)?;
+                let quot = <$t>::try_from(quot)
+                    .map_err(|_| ParameterError(format!("{} overflows the stake", quot)))?;
+                Ok((quot, exact))
+            }
+
+            fn from_u64(value: u64) -> Self {
+                <$t>::from(value)
+            }
+
+            fn to_field<F: PrimeField>(&self) -> Result<F, PrimitivesError> {
+                le_bytes_to_field(&self.to_le_bytes())
+            }
+        }
+    };
+}
+
+impl_stake_amount_for_uint!(u64);
+impl_stake_amount_for_uint!(u128);
+
+impl StakeAmount for U256 {
+    fn zero() -> Self {
+        U256::zero()
+    }
+
+    fn try_add(&self, rhs: &Self) -> Result<Self, PrimitivesError> {
+        self.checked_add(*rhs)
+            .ok_or_else(|| ParameterError(format!("stake overflow: {} + {}", self, rhs)))
+    }
+
+    fn try_sub(&self, rhs: &Self) -> Result<Self, PrimitivesError> {
+        self.checked_sub(*rhs)
+            .ok_or_else(|| ParameterError(format!("stake underflow: {} - {}", self, rhs)))
+    }
+
+    fn mul_fraction(
+        &self,
+        numerator: u64,
+        denominator: u64,
+    ) -> Result<(Self, bool), PrimitivesError> {
+        check_fraction(numerator, denominator)?;
+        // the product has at most 256 + 64 bits
+        let prod = U512::from(*self) * U512::from(numerator);
+        let (quot, rem) = prod.div_mod(U512::from(denominator));
+        let quot =
+            U256::try_from(quot).map_err(|_| ParameterError(format!("{} overflows U256", quot)))?;
+        Ok((quot, rem.is_zero()))
+    }
+
+    fn from_u64(value: u64) -> Self {
+        U256::from(value)
+    }
+
+    fn to_field<F: PrimeField>(&self) -> Result<F, PrimitivesError> {
+        let mut bytes = [0u8; 32];
+        self.to_little_endian(&mut bytes);
+        le_bytes_to_field(&bytes)
+    }
+}
+
+/// A stake amount given by a field element, compared as an integer less than the modulus.
+/// Matches the stake amounts of the circuits.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(bound = "")]
+pub struct FieldStake<F: PrimeField>(#[serde(with = "canonical")] pub F);
+
+impl<F: PrimeField> PartialOrd for FieldStake<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: PrimeField> Ord for FieldStake<F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.into_bigint().cmp(&other.0.into_bigint())
+    }
+}
+
+impl<F: PrimeField> Display for FieldStake<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl<F: PrimeField> StakeAmount for FieldStake<F> {
+    fn zero() -> Self {
+        Self(F::zero())
+    }
+
+    fn try_add(&self, rhs: &Self) -> Result<Self, PrimitivesError> {
+        let sum = Self(self.0 + rhs.0);
+        // the sum wraps around the modulus iff it is less than an operand
+        if sum < *self {
+            return Err(ParameterError(format!(
+                "stake overflow: {} + {}",
+                self, rhs
+            )));
+        }
+        Ok(sum)
+    }
+
+    fn try_sub(&self, rhs: &Self) -> Result<Self, PrimitivesError> {
+        if self < rhs {
+            return Err(ParameterError(format!(
+                "stake underflow: {} - {}",
+                self, rhs
+            )));
+        }
+        Ok(Self(self.0 - rhs.0))
+    }
+
+    fn mul_fraction(
+        &self,
+        numerator: u64,
+        denominator: u64,
+    ) -> Result<(Self, bool), PrimitivesError> {
+        check_fraction(numerator, denominator)?;
+        let denominator = u128::from(denominator);
+        // long division of the limbs by the denominator, from the most significant one
+        let mut quot = self.0.into_bigint();
+        let mut rem = 0u128;
+        for limb in quot.as_mut().iter_mut().rev() {
+            let cur = (rem << 64) | u128::from(*limb);
+            *limb = (cur / denominator) as u64;
+            rem = cur % denominator;
+        }
+        // self * n / d = quot * n + rem * n / d, where quot * n <= self and rem * n < 2^128
+        let quot = F::from_bigint(quot)
+            .ok_or_else(|| ParameterError(format!("{} / {} overflows", self, denominator)))?;
+        let prod = rem * u128::from(numerator);
+        Ok((
+            Self(quot * F::from(numerator) + F::from(prod / denominator)),
+            prod % denominator == 0,
+        ))
+    }
+
+    fn from_u64(value: u64) -> Self {
+        Self(F::from(value))
+    }
+
+    fn to_field<F2: PrimeField>(&self) -> Result<F2, PrimitivesError> {
+        le_bytes_to_field(&self.0.into_bigint().to_bytes_le())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bn254::Fr;
+    use ark_std::{vec, vec::Vec};
+
+    fn test_stake_amount<S: StakeAmount>(max: S) {
+        let one = S::from_u64(1);
+        let two = S::from_u64(2);
+        assert_eq!(S::zero().try_add(&one).unwrap(), one);
+        assert_eq!(one.try_add(&one).unwrap(), two);
+        assert_eq!(two.try_sub(&one).unwrap(), one);
+        assert!(one < two);
+        assert_eq!(sum_stakes(&[one, one]).unwrap(), two);
+        assert_eq!(two.to_field::<Fr>().unwrap(), Fr::from(2u8));
+        assert_eq!(two.mul_fraction(1, 2).unwrap(), (one, true));
+        assert_eq!(two.mul_fraction(2, 3).unwrap(), (one, false));
+        assert_eq!(max.mul_fraction(1, 1).unwrap(), (max, true));
+        assert_eq!(max.mul_fraction(0, 5).unwrap(), (S::zero(), true));
+        assert_eq!(
+            max,
+            bincode::deserialize(&bincode::serialize(&max).unwrap()).unwrap()
+        );
+
+        // overflows are explicit errors
+        assert!(max.try_add(&one).is_err());
+        assert!(sum_stakes(&[max, S::zero(), one]).is_err());
+        assert!(one.try_sub(&two).is_err());
+        assert_eq!(max.try_sub(&max).unwrap(), S::zero());
+        assert!(one.mul_fraction(1, 0).is_err());
+        assert!(one.mul_fraction(3, 2).is_err());
+    }
+
+    #[test]
+    fn test_stake_amounts() {
+        test_stake_amount(u64::MAX);
+        test_stake_amount(u128::MAX);
+        test_stake_amount(U256::MAX);
+        test_stake_amount(FieldStake(-Fr::from(1u8)));
+
+        // conversions
+        assert_eq!(u64::MAX.to_field::<Fr>().unwrap(), Fr::from(u64::MAX));
+        assert!(U256::MAX.to_field::<Fr>().is_err());
+        let modulus = U256::from_little_endian(&Fr::MODULUS.to_bytes_le());
+        assert!(modulus.to_field::<Fr>().is_err());
+        assert_eq!((modulus - 1).to_field::<Fr>().unwrap(), -Fr::from(1u8));
+        // a BLS12-381 scalar does not always fit in the smaller BN254 scalar field
+        assert!(FieldStake(-ark_bls12_381::Fr::from(1u8))
+            .to_field::<Fr>()
+            .is_err());
+        assert_eq!(
+            FieldStake(-Fr::from(1u8))
+                .to_field::<ark_bls12_381::Fr>()
+                .unwrap(),
+            ark_bls12_381::Fr::from_le_bytes_mod_order(
+                &(-Fr::from(1u8)).into_bigint().to_bytes_le()
+            )
+        );
+        let stakes: Vec<_> = vec![1u8, 2, 3]
+            .into_iter()
+            .map(|x| FieldStake(Fr::from(x)))
+            .collect();
+        assert_eq!(sum_stakes(&stakes).unwrap(), FieldStake(Fr::from(6u8)));
+
+        // fractions of large stakes agree across the types
+        for (numerator, denominator) in [(2, 3), (u64::MAX - 1, u64::MAX), (1, 1 << 40)] {
+            let (quot, exact) = U256::from(u128::MAX)
+                .mul_fraction(numerator, denominator)
+                .unwrap();
+            assert_eq!(
+                u128::MAX.mul_fraction(numerator, denominator).unwrap(),
+                (quot.as_u128(), exact)
+            );
+            let (quot, exact) = (modulus - 1).mul_fraction(numerator, denominator).unwrap();
+            assert_eq!(
+                FieldStake(-Fr::from(1u8))
+                    .mul_fraction(numerator, denominator)
+                    .unwrap(),
+                (FieldStake(quot.to_field().unwrap()), exact)
+            );
+        }
+    }
+}
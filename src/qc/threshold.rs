@@ -0,0 +1,282 @@
+//! Implementation of a threshold QC using Shamir-shared BLS keys over BN254.
+//! The QC is a single BLS signature under the group key: its size is constant and it does not
+//! reveal the set of signers. Key shares are produced with the DKG in [`crate::qc::dkg`].
+
+use crate::qc::{convert_canonical, dkg::party_point, QuorumCertificate};
+use ark_bn254::{Fr, G1Projective};
+use ark_ff::{Field, One, Zero};
+use ark_std::{
+    format,
+    rand::{CryptoRng, RngCore},
+    vec::Vec,
+};
+use bitvec::prelude::*;
+use generic_array::GenericArray;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::signatures::bls_over_bn254::{
+    BLSOverBN254CurveSignatureScheme, Signature, VerKey,
+};
+use jf_primitives::signatures::SignatureScheme;
+use serde::{Deserialize, Serialize};
+use typenum::U32;
+
+/// The signature scheme underlying [`ThresholdBLSQC`].
+pub type ThresholdBLSScheme = BLSOverBN254CurveSignatureScheme;
+
+/// An implementation of QC using threshold BLS signatures.
+#[derive(Serialize, Deserialize)]
+pub struct ThresholdBLSQC;
+
+/// Public parameters of a threshold BLS QC.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ThresholdQCParams {
+    /// The group verification key
+    pub group_key: VerKey,
+    /// Verification keys of each key share, `share_keys[i]` belongs to party `i`
+    pub share_keys: Vec<VerKey>,
+    /// Number of partial signatures required
+    pub threshold: usize,
+    pub agg_sig_pp: <ThresholdBLSScheme as SignatureScheme>::PublicParameter,
+}
+
+/// Lagrange coefficients for interpolating at zero from the given points.
+fn lagrange_coefs_at_zero(points: &[Fr]) -> Result<Vec<Fr>, PrimitivesError> {
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, x_i)| {
+            let (num, den) = points
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .fold((Fr::one(), Fr::one()), |(num, den), (_, x_j)| {
+                    (num * x_j, den * (*x_j - x_i))
+                });
+            den.inverse()
+                .map(|den_inv| num * den_inv)
+                .ok_or_else(|| ParameterError("duplicated interpolation points".into()))
+        })
+        .collect()
+}
+
+impl ThresholdBLSQC {
+    /// Verify the partial signature of party `signer` against its key share.
+    /// `assemble` has no access to the message, so the partial signatures should be verified
+    /// before being assembled: a single invalid one yields an invalid QC.
+    /// * `qc_pp` - public parameters of the QC
+    /// * `signer` - index of the signing party
+    /// * `message` - the signed message
+    /// * `sig` - the partial signature
+    pub fn verify_partial(
+        qc_pp: &ThresholdQCParams,
+        signer: usize,
+        message: &GenericArray<u8, <Self as QuorumCertificate<ThresholdBLSScheme>>::MessageLength>,
+        sig: &Signature,
+    ) -> Result<(), PrimitivesError> {
+        let share_key = qc_pp.share_keys.get(signer).ok_or_else(|| {
+            ParameterError(format!(
+                "signer index {} out of range {}",
+                signer,
+                qc_pp.share_keys.len()
+            ))
+        })?;
+        ThresholdBLSScheme::verify(&qc_pp.agg_sig_pp, share_key, message, sig)
+    }
+}
+
+impl QuorumCertificate<ThresholdBLSScheme> for ThresholdBLSQC {
+    type QCProverParams = ThresholdQCParams;
+    type QCVerifierParams = ThresholdQCParams;
+
+    type QC = Signature;
+    type MessageLength = U32;
+    type QuorumSize = usize;
+
+    fn sign<R: CryptoRng + RngCore>(
+        agg_sig_pp: &<ThresholdBLSScheme as SignatureScheme>::PublicParameter,
+        message: &GenericArray<u8, Self::MessageLength>,
+        sk: &<ThresholdBLSScheme as SignatureScheme>::SigningKey,
+        prng: &mut R,
+    ) -> Result<Signature, PrimitivesError> {
+        ThresholdBLSScheme::sign(agg_sig_pp, sk, message, prng)
+    }
+
+    /// The partial signatures are interpolated without being verified, callers should check
+    /// them with [`ThresholdBLSQC::verify_partial`] first.
+    fn assemble(
+        qc_pp: &Self::QCProverParams,
+        signers: &BitSlice,
+        sigs: &[Signature],
+    ) -> Result<Self::QC, PrimitivesError> {
+        if signers.len() != qc_pp.share_keys.len() {
+            return Err(ParameterError(format!(
+                "bit vector len {} != the number of key shares {}",
+                signers.len(),
+                qc_pp.share_keys.len(),
+            )));
+        }
+        let num_signers = signers.count_ones();
+        if num_signers < qc_pp.threshold {
+            return Err(ParameterError(format!(
+                "the number of signers {} less than threshold {}",
+                num_signers, qc_pp.threshold,
+            )));
+        }
+        if num_signers != sigs.len() {
+            return Err(ParameterError(format!(
+                "the number of signers {} != the number of partial signatures {}",
+                num_signers,
+                sigs.len(),
+            )));
+        }
+        let points: Vec<Fr> = signers.iter_ones().map(party_point).collect();
+        let coefs = lagrange_coefs_at_zero(&points)?;
+        let mut sigma = G1Projective::zero();
+        for (sig, coef) in sigs.iter().zip(coefs.iter()) {
+            let partial_sigma: G1Projective = convert_canonical(sig)?;
+            sigma += partial_sigma * coef;
+        }
+        convert_canonical(&sigma)
+    }
+
+    fn check(
+        qc_vp: &Self::QCVerifierParams,
+        message: &GenericArray<u8, Self::MessageLength>,
+        qc: &Self::QC,
+    ) -> Result<Self::QuorumSize, PrimitivesError> {
+        ThresholdBLSScheme::verify(&qc_vp.agg_sig_pp, &qc_vp.group_key, message, qc)?;
+        Ok(qc_vp.threshold)
+    }
+
+    fn trace(
+        _qc_vp: &Self::QCVerifierParams,
+        _message: &GenericArray<u8, Self::MessageLength>,
+        _qc: &Self::QC,
+    ) -> Result<Vec<VerKey>, PrimitivesError> {
+        Err(ParameterError(
+            "a threshold QC does not reveal its signers".into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::dkg::{deal, derive_key_pair, derive_qc_params, verify_share, DkgParams};
+    use ark_std::vec;
+
+    #[test]
+    fn test_threshold_qc_with_dkg() {
+        let mut rng = jf_utils::test_rng();
+        let num_parties = 5;
+        let params = DkgParams::new(num_parties, 3).unwrap();
+
+        // Every party deals, the last dealer sends a bad share to party 1.
+        let (comms, mut shares): (Vec<_>, Vec<_>) = (0..num_parties)
+            .map(|dealer| deal(&params, dealer, &mut rng).unwrap())
+            .unzip();
+        shares[4][1].share += Fr::one();
+
+        // Party 1 complains, every party disqualifies the dealers with bad shares.
+        let qualified: Vec<usize> = (0..num_parties)
+            .filter(|&dealer| {
+                (0..num_parties).all(|receiver| {
+                    verify_share(&params, &comms[dealer], &shares[dealer][receiver]).is_ok()
+                })
+            })
+            .collect();
+        assert_eq!(qualified, vec![0, 1, 2, 3]);
+        let qualified_comms: Vec<_> = qualified.iter().map(|&i| comms[i].clone()).collect();
+        let key_pairs: Vec<_> = (0..num_parties)
+            .map(|receiver| {
+                let received: Vec<_> = qualified
+                    .iter()
+                    .map(|&dealer| shares[dealer][receiver].clone())
+                    .collect();
+                derive_key_pair(&params, receiver, &qualified_comms, &received).unwrap()
+            })
+            .collect();
+        let qc_pp = derive_qc_params(&params, &qualified_comms).unwrap();
+        for (key_pair, share_key) in key_pairs.iter().zip(qc_pp.share_keys.iter()) {
+            assert_eq!(&key_pair.ver_key(), share_key);
+        }
+
+        let msg = [72u8; 32];
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .map(|kp| {
+                ThresholdBLSQC::sign(&qc_pp.agg_sig_pp, &msg.into(), kp.sign_key_ref(), &mut rng)
+                    .unwrap()
+            })
+            .collect();
+
+        // partial signatures
+        for (i, sig) in sigs.iter().enumerate() {
+            assert!(ThresholdBLSQC::verify_partial(&qc_pp, i, &msg.into(), sig).is_ok());
+        }
+        assert!(ThresholdBLSQC::verify_partial(&qc_pp, 1, &msg.into(), &sigs[0]).is_err());
+        assert!(ThresholdBLSQC::verify_partial(&qc_pp, 0, &[70u8; 32].into(), &sigs[0]).is_err());
+        assert!(ThresholdBLSQC::verify_partial(&qc_pp, 5, &msg.into(), &sigs[0]).is_err());
+
+        // happy path
+        let signers = bitvec![1, 0, 1, 0, 1];
+        let qc = ThresholdBLSQC::assemble(
+            &qc_pp,
+            signers.as_bitslice(),
+            &[sigs[0].clone(), sigs[2].clone(), sigs[4].clone()],
+        )
+        .unwrap();
+        assert_eq!(ThresholdBLSQC::check(&qc_pp, &msg.into(), &qc).unwrap(), 3);
+        // any quorum yields the same signature
+        let qc2 =
+            ThresholdBLSQC::assemble(&qc_pp, bitvec![0, 1, 1, 1, 1].as_bitslice(), &sigs[1..])
+                .unwrap();
+        assert_eq!(qc, qc2);
+        assert!(ThresholdBLSQC::trace(&qc_pp, &msg.into(), &qc).is_err());
+
+        // Check the QC and the QCParams can be serialized / deserialized
+        assert_eq!(
+            qc,
+            bincode::deserialize(&bincode::serialize(&qc).unwrap()).unwrap()
+        );
+        assert_eq!(
+            qc_pp,
+            bincode::deserialize(&bincode::serialize(&qc_pp).unwrap()).unwrap()
+        );
+
+        // bad paths
+        // not enough signers
+        assert!(ThresholdBLSQC::assemble(
+            &qc_pp,
+            bitvec![1, 0, 1, 0, 0].as_bitslice(),
+            &[sigs[0].clone(), sigs[2].clone()],
+        )
+        .is_err());
+        // number of signatures unmatch
+        assert!(ThresholdBLSQC::assemble(
+            &qc_pp,
+            signers.as_bitslice(),
+            &[sigs[0].clone(), sigs[2].clone()],
+        )
+        .is_err());
+        // wrong bool vector length
+        assert!(ThresholdBLSQC::assemble(
+            &qc_pp,
+            bitvec![1, 0, 1, 0, 1, 0].as_bitslice(),
+            &[sigs[0].clone(), sigs[2].clone(), sigs[4].clone()],
+        )
+        .is_err());
+        // signatures interpolated with the wrong indices
+        let bad_qc = ThresholdBLSQC::assemble(
+            &qc_pp,
+            bitvec![1, 1, 1, 0, 0].as_bitslice(),
+            &[sigs[0].clone(), sigs[2].clone(), sigs[4].clone()],
+        )
+        .unwrap();
+        assert!(ThresholdBLSQC::check(&qc_pp, &msg.into(), &bad_qc).is_err());
+        let bad_msg = [70u8; 32];
+        assert!(ThresholdBLSQC::check(&qc_pp, &bad_msg.into(), &qc).is_err());
+        assert!(ThresholdBLSQC::check(&qc_pp, &msg.into(), &sigs[0]).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! Timeout certificates for HotShot view-change.
+//! Each signer signs the pair (view, view of its highest QC), so the messages differ among the
+//! signers and the partial signatures are aggregated over distinct messages.
+
+use crate::qc::{
+    bit_vector::QCParams, domain::hash_to_message, signer_set::SignerSet, stake::StakeAmount,
+};
+use ark_std::{
+    format,
+    marker::PhantomData,
+    rand::{CryptoRng, RngCore},
+    vec,
+    vec::Vec,
+};
+use bitvec::prelude::*;
+use ethereum_types::U256;
+use generic_array::GenericArray;
+use jf_primitives::errors::PrimitivesError;
+use jf_primitives::errors::PrimitivesError::ParameterError;
+use jf_primitives::signatures::AggregateableSignatureSchemes;
+use serde::{Deserialize, Serialize};
+use typenum::U32;
+
+/// Prefix of the encoded timeout vote, before hashing with [`hash_to_message`].
+pub const TIMEOUT_DOMAIN_TAG: &[u8] = b"HOTSHOT_TIMEOUT";
+
+/// The message signed by a timeout vote.
+/// * `view` - the view timing out
+/// * `high_qc_view` - the view of the signer's highest QC
+pub fn timeout_message(view: u64, high_qc_view: u64) -> GenericArray<u8, U32> {
+    let mut bytes = TIMEOUT_DOMAIN_TAG.to_vec();
+    bytes.extend_from_slice(&view.to_le_bytes());
+    bytes.extend_from_slice(&high_qc_view.to_le_bytes());
+    hash_to_message(&bytes)
+}
+
+/// A timeout certificate: an aggregated signature over distinct (view, high QC view) messages.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TimeoutCertificate<S> {
+    /// The view timing out
+    pub view: u64,
+    /// Aggregated signature
+    pub sig: S,
+    /// The signers among the stake table entries
+    pub signers: SignerSet,
+    /// The high QC view of each signer, in the order of the stake table
+    pub high_qc_views: Vec<u64>,
+}
+
+impl<S> TimeoutCertificate<S> {
+    /// The maximum high QC view among the signers.
+    pub fn max_high_qc_view(&self) -> Option<u64> {
+        self.high_qc_views.iter().max().copied()
+    }
+}
+
+/// Timeout certificates using aggregate signatures over distinct messages.
+/// * `S` - the type of the stake amounts
+pub struct TimeoutQC<A: AggregateableSignatureSchemes, S = U256>(PhantomData<(A, S)>);
+
+impl<A, S> TimeoutQC<A, S>
+where
+    A: AggregateableSignatureSchemes<MessageUnit = u8>,
+    S: StakeAmount,
+{
+    /// Produces a timeout vote.
+    /// * `agg_sig_pp` - public parameters for aggregate signature
+    /// * `view` - the view timing out
+    /// * `high_qc_view` - the view of the signer's highest QC
+    /// * `sk` - user signing key
+    /// * `returns` - a "simple" signature
+    pub fn sign<R: CryptoRng + RngCore>(
+        agg_sig_pp: &A::PublicParameter,
+        view: u64,
+        high_qc_view: u64,
+        sk: &A::SigningKey,
+        prng: &mut R,
+    ) -> Result<A::Signature, PrimitivesError> {
+        if high_qc_view >= view {
+            return Err(ParameterError(format!(
+                "high QC view {} should be less than view {}",
+                high_qc_view, view
+            )));
+        }
+        A::sign(agg_sig_pp, sk, timeout_message(view, high_qc_view), prng)
+    }
+
+    /// Computes a timeout certificate from a set of timeout votes.
+    /// * `qc_pp` - public parameters for generating the QC
+    /// * `view` - the view timing out
+    /// * `signers` - a bool vector indicating the signers among the stake table entries
+    /// * `high_qc_views` - the high QC view of each signer
+    /// * `sigs` - the timeout votes of each signer
+    pub fn assemble(
+        qc_pp: &QCParams<A::VerificationKey, A::PublicParameter, S>,
+        view: u64,
+        signers: &BitSlice,
+        high_qc_views: &[u64],
+        sigs: &[A::Signature],
+    ) -> Result<TimeoutCertificate<A::Signature>, PrimitivesError> {
+        let (ver_keys, _) = Self::signers_and_weight(qc_pp, signers)?;
+        if ver_keys.len() != sigs.len() || ver_keys.len() != high_qc_views.len() {
+            return Err(ParameterError(format!(
+                "the number of ver_keys {} != the number of partial signatures {} or high QC views {}",
+                ver_keys.len(),
+                sigs.len(),
+                high_qc_views.len(),
+            )));
+        }
+        let sig = A::aggregate(qc_pp.agg_sig_pp(), &ver_keys[..], sigs)?;
+
+        Ok(TimeoutCertificate {
+            view,
+            sig,
+            signers: SignerSet::compact(signers)?,
+            high_qc_views: high_qc_views.to_vec(),
+        })
+    }
+
+    /// Checks a timeout certificate.
+    /// * `qc_vp` - public parameters for validating the QC
+    /// * `tc` - timeout certificate
+    /// * `returns` - the quorum size if the certificate is valid, an error otherwise.
+    pub fn check(
+        qc_vp: &QCParams<A::VerificationKey, A::PublicParameter, S>,
+        tc: &TimeoutCertificate<A::Signature>,
+    ) -> Result<S, PrimitivesError> {
+        let signers = tc.signers.to_checked_bitvec(qc_vp.stake_entries().len())?;
+        let (ver_keys, total_weight) = Self::signers_and_weight(qc_vp, &signers)?;
+        if ver_keys.len() != tc.high_qc_views.len() {
+            return Err(ParameterError(format!(
+                "the number of signers {} != the number of high QC views {}",
+                ver_keys.len(),
+                tc.high_qc_views.len(),
+            )));
+        }
+        if let Some(high_qc_view) = tc.high_qc_views.iter().find(|v| **v >= tc.view) {
+            return Err(ParameterError(format!(
+                "high QC view {} should be less than view {}",
+                high_qc_view, tc.view
+            )));
+        }
+        let msgs: Vec<_> = tc
+            .high_qc_views
+            .iter()
+            .map(|high_qc_view| timeout_message(tc.view, *high_qc_view))
+            .collect();
+        A::aggregate_verify(qc_vp.agg_sig_pp(), &ver_keys[..], &msgs[..], &tc.sig)?;
+
+        Ok(total_weight)
+    }
+
+    /// Returns the keys of the signers and their total weight, or an error if under threshold.
+    fn signers_and_weight(
+        qc_pp: &QCParams<A::VerificationKey, A::PublicParameter, S>,
+        signers: &BitSlice,
+    ) -> Result<(Vec<A::VerificationKey>, S), PrimitivesError> {
+        if signers.len() != qc_pp.stake_entries().len() {
+            return Err(ParameterError(format!(
+                "bit vector len {} != the number of stake entries {}",
+                signers.len(),
+                qc_pp.stake_entries().len(),
+            )));
+        }
+        let mut ver_keys = vec![];
+        let mut total_weight = S::zero();
+        for (entry, b) in qc_pp.stake_entries().iter().zip(signers.iter()) {
+            if *b {
+                ver_keys.push(entry.stake_key.clone());
+                total_weight = total_weight.try_add(&entry.stake_amount)?;
+            }
+        }
+        if total_weight < *qc_pp.threshold() {
+            return Err(ParameterError(format!(
+                "total_weight {} less than threshold {}",
+                total_weight,
+                qc_pp.threshold(),
+            )));
+        }
+        Ok((ver_keys, total_weight))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qc::bit_vector::StakeTableEntry;
+    use crate::qc::policy::ThresholdPolicy;
+    use jf_primitives::signatures::bls_over_bn254::{BLSOverBN254CurveSignatureScheme, KeyPair};
+    use jf_primitives::signatures::SignatureScheme;
+
+    type BLS = BLSOverBN254CurveSignatureScheme;
+    type TC = TimeoutQC<BLS>;
+
+    #[test]
+    fn test_timeout_certificate() {
+        let mut rng = jf_utils::test_rng();
+        let agg_sig_pp = BLS::param_gen(Some(&mut rng)).unwrap();
+        let key_pairs: Vec<_> = (0..4).map(|_| KeyPair::generate(&mut rng)).collect();
+        let qc_pp = QCParams::new(
+            key_pairs
+                .iter()
+                .map(|kp| StakeTableEntry {
+                    stake_key: kp.ver_key(),
+                    stake_amount: U256::from(5u8),
+                })
+                .collect(),
+            ThresholdPolicy::Absolute(U256::from(15u8)),
+            agg_sig_pp,
+        )
+        .unwrap();
+        let view = 10;
+        let high_qc_views = [7u64, 9, 8, 3];
+        let sigs: Vec<_> = key_pairs
+            .iter()
+            .zip(high_qc_views.iter())
+            .map(|(kp, hqc)| {
+                TC::sign(&agg_sig_pp, view, *hqc, kp.sign_key_ref(), &mut rng).unwrap()
+            })
+            .collect();
+        assert!(TC::sign(
+            &agg_sig_pp,
+            view,
+            view,
+            key_pairs[0].sign_key_ref(),
+            &mut rng
+        )
+        .is_err());
+
+        // happy path
+        let signers = bitvec![1, 1, 1, 0];
+        let tc = TC::assemble(&qc_pp, view, &signers, &high_qc_views[..3], &sigs[..3]).unwrap();
+        assert_eq!(TC::check(&qc_pp, &tc).unwrap(), U256::from(15u8));
+        assert_eq!(tc.max_high_qc_view(), Some(9));
+        assert_eq!(
+            tc,
+            bincode::deserialize(&bincode::serialize(&tc).unwrap()).unwrap()
+        );
+
+        // bad paths
+        // under threshold
+        assert!(TC::assemble(
+            &qc_pp,
+            view,
+            &bitvec![1, 1, 0, 0],
+            &high_qc_views[..2],
+            &sigs[..2]
+        )
+        .is_err());
+        // number of signatures unmatch
+        assert!(TC::assemble(&qc_pp, view, &signers, &high_qc_views[..3], &sigs[..2]).is_err());
+        assert!(TC::assemble(&qc_pp, view, &signers, &high_qc_views[..2], &sigs[..3]).is_err());
+        // lying about a high QC view
+        let mut bad_tc = tc.clone();
+        bad_tc.high_qc_views[0] = 9;
+        assert!(TC::check(&qc_pp, &bad_tc).is_err());
+        // another view
+        let mut bad_tc = tc.clone();
+        bad_tc.view = 11;
+        assert!(TC::check(&qc_pp, &bad_tc).is_err());
+        // high QC view not before the view
+        let mut bad_tc = tc.clone();
+        bad_tc.view = 9;
+        assert!(TC::check(&qc_pp, &bad_tc).is_err());
+        // wrong signers
+        let mut bad_tc = tc.clone();
+        bad_tc.signers = bitvec![0, 1, 1, 1].into();
+        assert!(TC::check(&qc_pp, &bad_tc).is_err());
+        let mut bad_tc = tc;
+        bad_tc.high_qc_views.pop();
+        assert!(TC::check(&qc_pp, &bad_tc).is_err());
+    }
+}
@@ -10,6 +10,8 @@ pub enum StakeTableError {
     KeyNotFound,
     /// Key already exists
     ExistingKey,
+    /// Malformed key: not an encoded field element
+    MalformedKey,
     /// Malformed Merkle proof
     MalformedProof,
     /// Verification Error
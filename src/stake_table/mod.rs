@@ -1,6 +1,6 @@
 use self::{
     error::StakeTableError,
-    utils::{to_merkle_path, PersistentMerkleNode},
+    utils::{to_merkle_path, MerkleSiblings, PersistentMerkleNode},
 };
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use ark_std::{
@@ -13,7 +13,7 @@ use ethereum_types::{U256, U512};
 use serde::{Deserialize, Serialize};
 use tagged_base64::tagged;
 
-mod config;
+pub(crate) mod config;
 mod utils;
 
 // Exports
@@ -36,6 +36,7 @@ pub struct EncodedPublicKey(pub Vec<u8>);
 ///  * `STVersion::PENDING`: the most up-to-date stake table, where the incoming transactions shall be performed on.
 ///  * `STVersion::FROZEN`: when an epoch ends, the PENDING stake table is frozen for leader elections for next epoch.
 ///  * `STVersion::ACTIVE`: the active stake table for leader election.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum STVersion {
     PENDING,
     FROZEN,
@@ -119,6 +120,27 @@ impl StakeTable {
         }
     }
 
+    /// Returns the location of `key` in the pending stake table as a list of Merkle path
+    /// branches, the commitments of the siblings along it from the bottom up, and the stake of
+    /// `key`. A key not registered yet is located at the next free position, with no stake.
+    pub(crate) fn pending_location(
+        &self,
+        key: &EncodedPublicKey,
+    ) -> Result<(Vec<usize>, Vec<MerkleSiblings>, Option<U256>), StakeTableError> {
+        let (index, registered) = match self.mapping.get(key) {
+            Some(index) => (*index, true),
+            None => (self.mapping.len(), false),
+        };
+        let branches = to_merkle_path(index, self.height);
+        let value = if registered {
+            Some(self.pending.simple_lookup(self.height, &branches)?)
+        } else {
+            None
+        };
+        let siblings = self.pending.siblings(self.height, &branches);
+        Ok((branches, siblings, value))
+    }
+
     /// Returns a succint commitment for a specific stake table version
     pub fn commitment(&self, version: STVersion) -> MerkleCommitment {
         let root = match version {
@@ -149,6 +171,32 @@ impl StakeTable {
         root.num_keys()
     }
 
+    /// Returns the keys and their stakes for a specific stake table version, in the order of
+    /// their registration.
+    pub fn entries(
+        &self,
+        version: STVersion,
+    ) -> Result<Vec<(EncodedPublicKey, U256)>, StakeTableError> {
+        let root = match version {
+            STVersion::PENDING => &self.pending,
+            STVersion::FROZEN => &self.frozen,
+            STVersion::ACTIVE => &self.active,
+        };
+        let mut keys: Vec<(&EncodedPublicKey, usize)> = self
+            .mapping
+            .iter()
+            .filter(|(_, index)| **index < root.num_keys())
+            .map(|(key, index)| (key, *index))
+            .collect();
+        keys.sort_by_key(|(_, index)| *index);
+        keys.into_iter()
+            .map(|(key, index)| {
+                let branches = to_merkle_path(index, self.height);
+                Ok((key.clone(), root.simple_lookup(self.height, &branches)?))
+            })
+            .collect()
+    }
+
     /// Almost uniformly samples a key weighted by its stake from the active stake table
     pub fn sample_key_by_stake<R: CryptoRng + RngCore>(&self, rng: &mut R) -> &EncodedPublicKey {
         let mut bytes = [0u8; 64];
@@ -46,6 +46,9 @@ pub enum MerklePathEntry {
         value: U256,
     },
 }
+/// Commitments of the siblings of a Merkle node, from left to right
+pub(crate) type MerkleSiblings = [FieldType; TREE_BRANCH - 1];
+
 /// Path from a Merkle root to a leaf
 pub type MerklePath = Vec<MerklePathEntry>;
 
@@ -271,6 +274,31 @@ impl PersistentMerkleNode {
         }
     }
 
+    /// Returns the commitments of the siblings along the given location, from the bottom up.
+    /// The siblings inside an empty subtree are all empty.
+    pub fn siblings(&self, height: usize, path: &[usize]) -> Vec<MerkleSiblings> {
+        match self {
+            PersistentMerkleNode::Branch {
+                comm: _,
+                children,
+                num_keys: _,
+                total_stakes: _,
+            } => {
+                let pos = path[height - 1];
+                let mut ret = children[pos].siblings(height - 1, path);
+                let siblings = children
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != pos)
+                    .map(|(_, node)| node.commitment())
+                    .collect::<Vec<_>>();
+                ret.push(siblings.try_into().unwrap());
+                ret
+            }
+            _ => vec![[FieldType::from(0); TREE_BRANCH - 1]; height],
+        }
+    }
+
     /// Imagine that the keys in this subtree is sorted, returns the first key such that
     /// the prefix sum of withholding stakes is greater or equal the given `stake_number`.
     /// Useful for key sampling weighted by withholding stakes
@@ -525,7 +553,7 @@ pub fn from_merkle_path(path: &[usize]) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use super::{to_merkle_path, PersistentMerkleNode};
+    use super::{to_merkle_path, MerklePathEntry, PersistentMerkleNode};
     use crate::stake_table::{config::FieldType, EncodedPublicKey};
     use ark_std::{sync::Arc, vec, vec::Vec};
     use ethereum_types::U256;
@@ -586,8 +614,21 @@ mod tests {
                 roots.last().unwrap().commitment(),
                 proof.compute_root().unwrap()
             );
+            let siblings = roots.last().unwrap().siblings(height, &path[i]);
+            assert_eq!(siblings.len(), height);
+            assert!(proof.path.iter().skip(1).zip(siblings.iter()).all(
+                |(node, s)| matches!(node, MerklePathEntry::Branch { pos: _, siblings } if siblings == s)
+            ));
         }
 
+        // the siblings of an empty location
+        let empty_path = to_merkle_path(20, height);
+        let siblings = roots.last().unwrap().siblings(height, &empty_path);
+        assert_eq!(siblings.len(), height);
+        assert_eq!(siblings[0], [FieldType::from(0); 2]);
+        assert_eq!(siblings[1], [FieldType::from(0); 2]);
+        assert!(siblings[2].iter().all(|s| *s != FieldType::from(0)));
+
         // test for `set_value`
         // `set_value` with wrong key should fail
         assert!(roots
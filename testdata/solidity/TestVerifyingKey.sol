@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: MIT
+// Generated by hotshot-primitives, do not edit.
+
+pragma solidity ^0.8.0;
+
+library TestVerifyingKey {
+    uint256 internal constant DOMAIN_SIZE =
+        0x0000000000000000000000000000000000000000000000000000000000000008;
+    uint256 internal constant NUM_INPUTS =
+        0x0000000000000000000000000000000000000000000000000000000000000005;
+    uint256 internal constant SIGMA_0_X =
+        0x0000000000000000000000000000000000000000000000000000000000000001;
+    uint256 internal constant SIGMA_0_Y =
+        0x0000000000000000000000000000000000000000000000000000000000000002;
+    uint256 internal constant K_0 =
+        0x0000000000000000000000000000000000000000000000000000000000000001;
+    uint256 internal constant H_X_1 =
+        0x198e9393920d483a7260bfb731fb5d25f1aa493335a9e71297e485b7aef312c2;
+    uint256 internal constant H_X_0 =
+        0x1800deef121f1e76426a00665e5c4479674322d4f75edadd46debd5cd992f6ed;
+    uint256 internal constant H_Y_1 =
+        0x090689d0585ff075ec9e99ad690c3395bc4b313370b38ef355acdadcd122975b;
+    uint256 internal constant H_Y_0 =
+        0x12c85ea5db8c6deb4aab71808dcb408fe3d1e7690c43d37b4ce6cc0166fa7daa;
+}